@@ -1,7 +1,7 @@
 use core::fmt::{Debug, Display};
 
 use alloc::{borrow::ToOwned, format, string::String};
-use embassy_time::{Timer, Duration};
+use embassy_time::{Duration, Ticker};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::signal::Signal;
 use embedded_hal::blocking::spi::{Transfer, Write};
@@ -20,7 +20,7 @@ use shared_bus::BusManagerSimple;
 use crate::debounce::DebounceCallback;
 use crate::{debounce, mpmc::{self, TryRecvError}};
 use crate::{
-    gate_cv::{self, GateCVOutWithPins},
+    gate_cv::{self, GateCVOutWithPins, OUTPUT_UPDATE_INTERVAL_MS},
     DummyTime,
 };
 
@@ -103,9 +103,11 @@ where
     <SPI as Transfer<u8>>::Error: Debug,
     <SPI as Write<u8>>::Error: Debug,
 {
+    // ticks at a fixed rate, however long the updates take, so that glides don't drift
+    let mut ticker = Ticker::every(Duration::from_millis(OUTPUT_UPDATE_INTERVAL_MS));
     loop {
-        output.update();
-        Timer::after(Duration::from_millis(1000)).await;
+        output.update(OUTPUT_UPDATE_INTERVAL_MS as u32);
+        ticker.next().await;
     }
 }
 
@@ -6,7 +6,9 @@ use critical_section::{Mutex, with};
 use defmt::trace;
 use embedded_hal::blocking::spi::Write;
 use embedded_hal::digital::v2::{OutputPin, PinState};
-use logic::stdlib::{CVChannel, CVChannelId, Channel, GateChannel, GateChannelId, Output};
+use logic::stdlib::{
+    CVChannel, CVChannelId, Channel, GateChannel, GateChannelId, GlideMode, Output, Slew,
+};
 use mcp49xx::marker::{DualChannel, Resolution12Bit, Unbuffered};
 use mcp49xx::{Channel as MCPChannel, Command, Mcp49xx};
 use rp2040_hal::gpio::bank0::{Gpio10, Gpio11, Gpio4, Gpio5, Gpio9};
@@ -21,6 +23,10 @@ use voice_lib::{InvalidNotePair, NotePair};
 pub type GateCVOutWithPins<SPI> = GateCVOut<SPI, Gpio10, Gpio11, Gpio9, Gpio4, Gpio5>;
 
 const MIDI_NOTE_0V: u16 = 36;
+// 1V/oct, 1mV per DAC step
+const DAC_UNITS_PER_OCTAVE: u16 = 1000;
+
+pub const OUTPUT_UPDATE_INTERVAL_MS: u64 = 1;
 
 #[derive(Default, Copy, Clone)]
 pub struct DACVoltage(u16);
@@ -49,17 +55,63 @@ impl TryFrom<&NotePair> for DACVoltage {
 }
 
 #[derive(Default)]
-pub struct StoredGateChannel(bool);
+pub struct StoredGateChannel {
+    high: bool,
+    // time left (ms) until a scheduled gate-off
+    off_in: Option<u32>,
+}
 impl GateChannel for StoredGateChannel {}
 
+impl StoredGateChannel {
+    fn pulse(&mut self, length_ms: u32) {
+        self.high = true;
+        self.off_in = Some(length_ms);
+    }
+
+    fn tick(&mut self, elapsed_ms: u32) -> bool {
+        if let Some(t) = self.off_in {
+            if t <= elapsed_ms {
+                self.high = false;
+                self.off_in = None;
+            } else {
+                self.off_in = Some(t - elapsed_ms);
+            }
+        }
+        self.high
+    }
+}
+
 impl Channel<bool> for StoredGateChannel {
     fn set(&mut self, val: bool) {
-        self.0 = val;
+        self.high = val;
+        self.off_in = None;
+    }
+}
+
+pub struct StoredCVChannel {
+    slew: Slew,
+    glide: Option<GlideMode>,
+}
+
+impl Default for StoredCVChannel {
+    fn default() -> Self {
+        Self {
+            slew: Slew::new(DAC_UNITS_PER_OCTAVE),
+            glide: None,
+        }
+    }
+}
+
+impl StoredCVChannel {
+    fn set_glide(&mut self, glide: Option<GlideMode>) {
+        self.glide = glide;
+    }
+
+    fn tick(&mut self, elapsed_ms: u32) -> DACVoltage {
+        self.slew.tick(elapsed_ms).into()
     }
 }
 
-#[derive(Default)]
-pub struct StoredCVChannel(DACVoltage);
 impl CVChannel<DACVoltage> for StoredCVChannel {
     type Error = InvalidNotePair;
 
@@ -71,7 +123,7 @@ impl CVChannel<DACVoltage> for StoredCVChannel {
 
 impl Channel<DACVoltage> for StoredCVChannel {
     fn set(&mut self, val: DACVoltage) {
-        self.0 = val;
+        self.slew.set(val.into(), self.glide);
     }
 }
 
@@ -139,11 +191,14 @@ where
         }
     }
 
-    pub fn update(&mut self) {
+    pub fn update(&mut self, elapsed_ms: u32) {
         let ((gate0, cv0), (gate1, cv1)) = with(|cs| {
-            let v = OUTPUTS.borrow(cs).borrow();
-            let out = v.as_ref().unwrap();
-            ((out.0 .0 .0, out.0 .1 .0), (out.1 .0 .0, out.1 .1 .0))
+            let mut v = OUTPUTS.borrow(cs).borrow_mut();
+            let out = v.as_mut().unwrap();
+            (
+                (out.0 .0.tick(elapsed_ms), out.0 .1.tick(elapsed_ms)),
+                (out.1 .0.tick(elapsed_ms), out.1 .1.tick(elapsed_ms)),
+            )
         });
 
         // channel 0
@@ -197,6 +252,22 @@ impl<'t> Output<DACVoltage, InvalidNotePair> for GateCVProxy {
         });
     }
 
+    fn pulse_gate(&mut self, id: GateChannelId, length_ms: u32) {
+        with(|cs| {
+            let mut val = OUTPUTS.borrow(cs).borrow_mut();
+            let v = val.as_mut().unwrap();
+
+            match id {
+                GateChannelId::Gate0 => {
+                    v.0 .0.pulse(length_ms);
+                }
+                GateChannelId::Gate1 => {
+                    v.1 .0.pulse(length_ms);
+                }
+            }
+        });
+    }
+
     fn set_cv(&mut self, id: logic::stdlib::CVChannelId, value: DACVoltage) {
         with(|cs| {
             let mut val = OUTPUTS.borrow(cs).borrow_mut();
@@ -211,4 +282,23 @@ impl<'t> Output<DACVoltage, InvalidNotePair> for GateCVProxy {
             }
         });
     }
+
+    fn set_cv_raw(&mut self, id: CVChannelId, value: u16) {
+        self.set_cv(id, DACVoltage(value & 0xfff));
+    }
+
+    fn set_glide(&mut self, id: CVChannelId, glide: Option<GlideMode>) {
+        with(|cs| {
+            let mut val = OUTPUTS.borrow(cs).borrow_mut();
+            let v = val.as_mut().unwrap();
+            match id {
+                CVChannelId::CV0 => {
+                    v.0 .1.set_glide(glide);
+                }
+                CVChannelId::CV1 => {
+                    v.1 .1.set_glide(glide);
+                }
+            }
+        });
+    }
 }
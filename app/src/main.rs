@@ -221,6 +221,8 @@ fn main() -> ! {
     info!("Program start");
     {
         use core::mem::MaybeUninit;
+        // the sequencer budgets it: 4 KB of undo history, 4 KB of tracks (all patterns)
+        // and up to 5 KB for a project being saved or loaded
         const HEAP_SIZE: usize = 16 * 1024;
         static mut HEAP: [MaybeUninit<u8>; HEAP_SIZE] = [MaybeUninit::uninit(); HEAP_SIZE];
         unsafe { ALLOCATOR.init(HEAP.as_ptr() as usize, HEAP_SIZE) }
@@ -19,8 +19,8 @@ use js_sys::{Date, Uint8Array};
 use logic::log::info;
 use logic::stdlib::ui::UIInputEvent;
 use logic::stdlib::{
-    CVChannel, CVChannelId, Channel, FileSystem, GateChannel, GateChannelId, Output, Task, TaskId,
-    TaskInterface, TaskManager, TaskReturn, TaskType,
+    CVChannel, CVChannelId, Channel, FileSystem, GateChannel, GateChannelId, GlideMode, Output,
+    Task, TaskId, TaskInterface, TaskManager, TaskReturn, TaskType,
 };
 use midi_types::MidiMessage;
 use serde::{Deserialize, Serialize};
@@ -109,11 +109,25 @@ impl TryFrom<&NotePair> for Frequency {
 }
 
 struct BrowserGateChannel {
+    ctx: AudioContext,
     vol0: GainNode,
 }
 
+impl BrowserGateChannel {
+    fn pulse(&mut self, length_ms: u32) {
+        let g = self.vol0.gain();
+        let now = self.ctx.current_time();
+        g.cancel_scheduled_values(now).unwrap();
+        g.set_value_at_time(1.0, now).unwrap();
+        g.set_value_at_time(0.0, now + length_ms as f64 / 1000.0)
+            .unwrap();
+    }
+}
+
 struct BrowserCVChannel {
     osc0: OscillatorNode,
+    glide: Option<GlideMode>,
+    last_freq: f32,
 }
 
 impl GateChannel for BrowserGateChannel {}
@@ -121,6 +135,7 @@ impl GateChannel for BrowserGateChannel {}
 impl Channel<bool> for BrowserGateChannel {
     fn set(&mut self, val: bool) {
         let g = self.vol0.gain();
+        g.cancel_scheduled_values(self.ctx.current_time()).unwrap();
         if val {
             g.set_value(1.0);
         } else {
@@ -140,7 +155,27 @@ impl CVChannel<Frequency> for BrowserCVChannel {
 
 impl Channel<Frequency> for BrowserCVChannel {
     fn set(&mut self, val: Frequency) {
-        self.osc0.frequency().set_value(val.0);
+        if val.0 == self.last_freq {
+            return;
+        }
+
+        let freq = self.osc0.frequency();
+        let glide_ms = match self.glide {
+            None => 0.0,
+            Some(GlideMode::Time(ms)) => ms as f32,
+            Some(GlideMode::Rate(ms_per_octave)) => {
+                (val.0 / self.last_freq).log2().abs() * ms_per_octave as f32
+            }
+        };
+
+        if glide_ms > 0.0 && self.last_freq > 0.0 {
+            // ~95% of the way there after `glide_ms`
+            freq.set_target_at_time(val.0, 0.0, (glide_ms / 3000.0) as f64)
+                .unwrap();
+        } else {
+            freq.set_value(val.0);
+        }
+        self.last_freq = val.0;
     }
 }
 
@@ -155,7 +190,17 @@ impl Output<Frequency, InvalidNotePair> for BrowserOutput {
             GateChannelId::Gate0 => {
                 self.gate0.set(value);
             }
-            GateChannelId::Gate1 => todo!(),
+            // only one voice is emulated (clock outputs can still be assigned here)
+            GateChannelId::Gate1 => {}
+        }
+    }
+
+    fn pulse_gate(&mut self, id: GateChannelId, length_ms: u32) {
+        match id {
+            GateChannelId::Gate0 => {
+                self.gate0.pulse(length_ms);
+            }
+            GateChannelId::Gate1 => {}
         }
     }
 
@@ -164,7 +209,21 @@ impl Output<Frequency, InvalidNotePair> for BrowserOutput {
             CVChannelId::CV0 => {
                 self.cv0.set(value);
             }
-            CVChannelId::CV1 => todo!(),
+            // the second voice (track output Voice1) isn't emulated, like Gate1
+            CVChannelId::CV1 => {}
+        }
+    }
+
+    // only pitch is emulated, raw values (modulation) are dropped
+    fn set_cv_raw(&mut self, _id: CVChannelId, _value: u16) {}
+
+    fn set_glide(&mut self, id: CVChannelId, glide: Option<GlideMode>) {
+        match id {
+            CVChannelId::CV0 => {
+                self.cv0.glide = glide;
+            }
+            // the modulation lane can glide CV1, which isn't emulated
+            CVChannelId::CV1 => {}
         }
     }
 }
@@ -179,8 +238,12 @@ impl BrowserOutput {
         vol0.connect_with_audio_node(&ac.destination()).unwrap();
         osc0.start().unwrap();
         Self {
-            gate0: BrowserGateChannel { vol0 },
-            cv0: BrowserCVChannel { osc0 },
+            gate0: BrowserGateChannel { ctx: ac, vol0 },
+            cv0: BrowserCVChannel {
+                osc0,
+                glide: None,
+                last_freq: 0.0,
+            },
         }
     }
 }
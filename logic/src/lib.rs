@@ -20,6 +20,11 @@ pub enum LogLevel {
     Error,
 }
 
+// the app and the emulator provide the logger, there is none in tests
+#[cfg(test)]
+#[no_mangle]
+fn _log(_text: *const str, _level: LogLevel) {}
+
 pub mod log {
     use super::LogLevel;
 
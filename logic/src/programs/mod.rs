@@ -46,7 +46,7 @@ pub trait Program<
         E: Debug,
         O: Deref<Target = impl Output<T, E>> + DerefMut,
     >(
-        &self,
+        &mut self,
         mut _output: O,
     ) -> Result<(), E> {
         Ok(())
@@ -0,0 +1,125 @@
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+use voice_lib::{ArpMode, Arpeggiator, NotePair};
+
+use super::{
+    clock::ClockRate,
+    transport::{Bpm, Position},
+};
+
+const MAX_HELD_KEYS: usize = 8;
+const RANDOM_SEED: u32 = 0x5eed;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ArpSettings {
+    pub(crate) enabled: bool,
+    pub(crate) mode: ArpMode,
+    pub(crate) octaves: u8,
+    /// Notes per step, like the clock outputs
+    pub(crate) rate: ClockRate,
+    /// Gate length, as % of the time between two notes
+    pub(crate) gate: u8,
+    /// Keep playing after the keys are released, until new ones are pressed
+    pub(crate) latch: bool,
+}
+
+impl Default for ArpSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: ArpMode::Up,
+            octaves: 1,
+            rate: ClockRate(2),
+            gate: 50,
+            latch: false,
+        }
+    }
+}
+
+impl ArpSettings {
+    /// Gate length of each note, in ms
+    pub(crate) fn gate_ms(&self, bpm: Bpm) -> u32 {
+        let note_ms = match self.rate.0 {
+            mult if mult > 0 => bpm.beat_ms() / mult as u32,
+            div => bpm.beat_ms() * (-div) as u32,
+        };
+        note_ms * self.gate as u32 / 100
+    }
+}
+
+/// Arpeggiates the keys being held, in time with the transport (or with
+/// the free-running clock, when stopped)
+pub(crate) struct Arp {
+    engine: Arpeggiator,
+    // notes being arpeggiated, in the order they were pressed
+    held: Vec<NotePair, MAX_HELD_KEYS>,
+    // number of keys which are actually down (`held` may also contain latched ones)
+    down: usize,
+    last_ticks: Option<u32>,
+    /// Note which is due to be output
+    pub(crate) pending: Option<NotePair>,
+}
+
+impl Default for Arp {
+    fn default() -> Self {
+        Self {
+            engine: Arpeggiator::new(ArpMode::Up, 1, RANDOM_SEED),
+            held: Vec::new(),
+            down: 0,
+            last_ticks: None,
+            pending: None,
+        }
+    }
+}
+
+impl Arp {
+    pub(crate) fn key_pressed(&mut self, settings: &ArpSettings, n: NotePair) {
+        // a new chord replaces the latched one
+        if settings.latch && self.down == 0 {
+            self.held.clear();
+        }
+        // start right away, from the first note
+        if self.held.is_empty() {
+            self.engine.reset();
+            self.last_ticks = None;
+        }
+        if !self.held.contains(&n) {
+            self.held.push(n).ok();
+        }
+        self.down += 1;
+    }
+
+    pub(crate) fn key_released(&mut self, settings: &ArpSettings, n: NotePair) {
+        self.down = self.down.saturating_sub(1);
+        if !settings.latch {
+            self.held = self.held.iter().filter(|e| **e != n).cloned().collect();
+        }
+    }
+
+    /// Stop arpeggiating (e.g. once disabled or unlatched)
+    pub(crate) fn clear(&mut self) {
+        self.held.clear();
+        self.pending = None;
+    }
+
+    /// There are notes being arpeggiated
+    pub(crate) fn is_active(&self) -> bool {
+        !self.held.is_empty()
+    }
+
+    /// Move on to `pos`. Returns the next note of the arpeggio whenever it is due.
+    pub(crate) fn tick(&mut self, settings: &ArpSettings, pos: &Position) -> Option<NotePair> {
+        if self.held.is_empty() {
+            return None;
+        }
+        self.engine.mode = settings.mode;
+        self.engine.octaves = settings.octaves;
+
+        let ticks = settings.rate.ticks(pos);
+        if self.last_ticks == Some(ticks) {
+            return None;
+        }
+        self.last_ticks = Some(ticks);
+        self.engine.next(&self.held)
+    }
+}
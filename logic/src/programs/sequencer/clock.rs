@@ -0,0 +1,139 @@
+use core::fmt::Debug;
+
+use serde::{Deserialize, Serialize};
+use voice_lib::NotePair;
+
+use super::transport::{Bpm, Position};
+use crate::stdlib::{GateChannelId, Output};
+
+/// Available clock rates, from fastest to slowest
+pub(crate) const CLOCK_RATES: &[ClockRate] = &[
+    ClockRate(4),
+    ClockRate(3),
+    ClockRate(2),
+    ClockRate(1),
+    ClockRate(-2),
+    ClockRate(-3),
+    ClockRate(-4),
+    ClockRate(-6),
+    ClockRate(-8),
+    ClockRate(-12),
+    ClockRate(-16),
+];
+pub(crate) const CLOCK_RATE_LABELS: &[&str] = &[
+    "x4", "x3", "x2", "x1", "/2", "/3", "/4", "/6", "/8", "/12", "/16",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum ClockMode {
+    /// The output plays notes
+    Off,
+    /// The output sends clock pulses
+    Clock,
+    /// The output sends a single pulse whenever the transport starts
+    Reset,
+}
+
+/// Pulses per beat if positive (multiplication), beats per pulse if negative (division)
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ClockRate(pub(crate) i8);
+
+impl ClockRate {
+    /// Number of clock pulses since the origin, at position `pos`
+    pub(crate) fn ticks(&self, pos: &Position) -> u32 {
+        if self.0 > 0 {
+            let mult = self.0 as u32;
+            pos.beat * mult + pos.phase(mult)
+        } else {
+            pos.beat / (-self.0) as u32
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ClockSettings {
+    pub(crate) mode: ClockMode,
+    pub(crate) rate: ClockRate,
+    pub(crate) pulse_ms: u16,
+    /// Keep running even if the transport is stopped
+    pub(crate) free_run: bool,
+}
+
+impl Default for ClockSettings {
+    fn default() -> Self {
+        Self {
+            mode: ClockMode::Off,
+            rate: ClockRate(1),
+            pulse_ms: 10,
+            free_run: false,
+        }
+    }
+}
+
+/// Generates the clock/reset pulses for the gate outputs which are in clock mode.
+#[derive(Default)]
+pub(crate) struct Clock {
+    // position used when free-running
+    free: Position,
+    // last pulse sent by each output
+    last_ticks: [Option<u32>; 2],
+    reset: bool,
+}
+
+impl Clock {
+    /// The transport (re)started from the origin
+    pub(crate) fn start(&mut self) {
+        self.free = Position::default();
+        self.last_ticks = [None; 2];
+        self.reset = true;
+    }
+
+    /// Position of the free-running clock
+    pub(crate) fn free_position(&self) -> Position {
+        self.free
+    }
+
+    pub(crate) fn advance(&mut self, elapsed_ms: u32, bpm: Bpm) {
+        self.free.advance(elapsed_ms, bpm);
+    }
+
+    /// Send pulses as needed. `transport` is the current position, if the transport is running.
+    pub(crate) fn update<T: for<'u> TryFrom<&'u NotePair, Error = E>, E: Debug>(
+        &mut self,
+        settings: &[ClockSettings; 2],
+        transport: Option<Position>,
+        output: &mut impl Output<T, E>,
+    ) {
+        for (n, (settings, last_ticks)) in settings.iter().zip(self.last_ticks.iter_mut()).enumerate() {
+            let id = if n == 0 {
+                GateChannelId::Gate0
+            } else {
+                GateChannelId::Gate1
+            };
+
+            match settings.mode {
+                ClockMode::Off => {}
+                ClockMode::Clock => {
+                    let pos = if settings.free_run {
+                        Some(self.free)
+                    } else {
+                        transport
+                    };
+
+                    if let Some(ticks) = pos.map(|p| settings.rate.ticks(&p)) {
+                        if *last_ticks != Some(ticks) {
+                            output.pulse_gate(id, settings.pulse_ms as u32);
+                            *last_ticks = Some(ticks);
+                        }
+                    }
+                }
+                ClockMode::Reset => {
+                    if self.reset {
+                        output.pulse_gate(id, settings.pulse_ms as u32);
+                    }
+                }
+            }
+        }
+        self.reset = false;
+    }
+}
@@ -1,15 +1,40 @@
 use crate::{stdlib::{
-    Closed, File,
+    Closed, File, GlideSettings,
 }};
 use serde::{Deserialize, Serialize};
 
+use super::{
+    arp::ArpSettings, clock::ClockSettings, groove::Groove, metronome::MetronomeSettings,
+    modulation::ModLaneSettings,
+};
+
 #[derive(Serialize, Deserialize, Debug)]
-pub(super) struct Config {
+pub(crate) struct Config {
     current_data_file: Option<File<Closed>>,
+    #[serde(default)]
+    pub(crate) glide: [GlideSettings; 2],
+    #[serde(default)]
+    pub(crate) clock: [ClockSettings; 2],
+    #[serde(default)]
+    pub(crate) groove: Groove,
+    #[serde(default)]
+    pub(crate) arp: ArpSettings,
+    #[serde(default)]
+    pub(crate) modulation: ModLaneSettings,
+    #[serde(default)]
+    pub(crate) metronome: MetronomeSettings,
 }
 
 impl Default for Config {
     fn default() -> Self {
-        Self { current_data_file: None }
+        Self {
+            current_data_file: None,
+            glide: Default::default(),
+            clock: Default::default(),
+            groove: Default::default(),
+            arp: Default::default(),
+            modulation: Default::default(),
+            metronome: Default::default(),
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,21 +1,66 @@
+use alloc::{boxed::Box, vec::Vec};
 use heapless::String;
 use serde::{Serialize, Deserialize};
 use ufmt::uwrite;
+use voice_lib::VoiceTrack;
 
-use crate::{util::DiscreetUnwrap, stdlib::Closed};
+use crate::{util::DiscreetUnwrap, stdlib::{Closed, StdlibError, TaskResult, TaskType}};
 use crate::stdlib::File;
 
+use super::{
+    recorder::{Punch, RecordMode},
+    smf::{SmfReader, SmfWriter},
+    song::{SongEntry, MAX_SONG_ENTRIES},
+    text,
+    tracks::{TrackSettings, NUM_TRACKS},
+    transport::Bpm,
+};
+
 const FILE_BUFFER_SIZE: usize = 10240;
 
+/// A project: all of its patterns (with all their tracks) and the song they are arranged in.
+/// `T` is a reference when saving, so that the tracks don't have to be copied.
 #[derive(Serialize, Deserialize, Debug)]
-pub(super) struct SequenceFile {
-    seq_name: String<8>
+pub(super) struct SequenceFile<T> {
+    seq_name: String<8>,
+    #[serde(default)]
+    pub(super) bpm: Option<Bpm>,
+    #[serde(default)]
+    pub(super) patterns: Vec<Option<Vec<T>>>,
+    #[serde(default)]
+    pub(super) current: u8,
+    #[serde(default)]
+    pub(super) song: heapless::Vec<SongEntry, MAX_SONG_ENTRIES>,
+    #[serde(default)]
+    pub(super) tracks: [TrackSettings; NUM_TRACKS],
+    #[serde(default)]
+    pub(super) record_mode: RecordMode,
+    #[serde(default)]
+    pub(super) punch: Option<Punch>,
 }
 
-impl SequenceFile {
+impl<T> SequenceFile<T> {
 
-    pub(crate) fn new(seq_name: &str) -> Self {
-        Self { seq_name: seq_name.into() }
+    pub(crate) fn new(
+        seq_name: &str,
+        bpm: Bpm,
+        patterns: Vec<Option<Vec<T>>>,
+        current: u8,
+        song: heapless::Vec<SongEntry, MAX_SONG_ENTRIES>,
+        tracks: [TrackSettings; NUM_TRACKS],
+        record_mode: RecordMode,
+        punch: Option<Punch>,
+    ) -> Self {
+        Self {
+            seq_name: seq_name.into(),
+            bpm: Some(bpm),
+            patterns,
+            current,
+            song,
+            tracks,
+            record_mode,
+            punch,
+        }
     }
 
     fn _load_data_file(&self) -> File<Closed> {
@@ -28,3 +73,64 @@ impl SequenceFile {
         self.seq_name = file_name.into();
     }
 }
+
+/// Formats a track can be exported to/imported from, besides the CBOR `.seq` files
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum FileFormat {
+    Midi,
+    Text,
+}
+
+impl FileFormat {
+    pub(crate) fn file_name(&self, name: &str) -> String<12> {
+        let mut tmp = String::<12>::new();
+        let ext = match self {
+            FileFormat::Midi => "mid",
+            FileFormat::Text => "txt",
+        };
+        uwrite!(tmp, "{}.{}", name, ext).duwrp();
+        tmp
+    }
+
+    /// The task saving `track` in this format
+    pub(crate) fn save(&self, name: &str, track: &VoiceTrack, bpm: Bpm) -> Result<TaskType, StdlibError> {
+        Ok(match self {
+            FileFormat::Midi => TaskType::FileSaveStream(
+                "data".into(),
+                self.file_name(name),
+                Box::new(SmfWriter::new(track, bpm)?),
+            ),
+            FileFormat::Text => {
+                TaskType::FileSaveBytes("data".into(), self.file_name(name), text::write(track, bpm))
+            }
+        })
+    }
+
+    /// The task loading a track in this format, to be `read` once done
+    pub(crate) fn load(&self, name: &str) -> TaskType {
+        match self {
+            FileFormat::Midi => TaskType::FileLoadStream(
+                "data".into(),
+                self.file_name(name),
+                Box::new(SmfReader::default()),
+            ),
+            FileFormat::Text => TaskType::FileLoadBytes("data".into(), self.file_name(name)),
+        }
+    }
+
+    /// The track loaded by the task of `load`, plus its tempo (if the file has one)
+    pub(crate) fn read(&self, result: TaskResult) -> Result<(VoiceTrack, Option<Bpm>), StdlibError> {
+        match (self, result) {
+            (FileFormat::Midi, TaskResult::FileStream(reader)) => reader
+                .into_any()
+                .downcast::<SmfReader>()
+                .map_err(|_| StdlibError::Deserialization)?
+                .track(),
+            (FileFormat::Text, TaskResult::FileBytes(data)) => {
+                text::read(&data).map(|(track, bpm)| (track, Some(bpm)))
+            }
+            (_, TaskResult::Error(e)) => Err(e),
+            _ => Err(StdlibError::Deserialization),
+        }
+    }
+}
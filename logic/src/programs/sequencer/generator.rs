@@ -0,0 +1,289 @@
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::fmt::Debug;
+
+use embedded_graphics::{draw_target::DrawTarget, pixelcolor::Rgb565};
+use embedded_sdmmc::{BlockDevice, TimeSource};
+use heapless::String;
+use ufmt::uwrite;
+use voice_lib::{
+    Euclid, Key, Markov, NotePair, TuringMachine, VoiceTrack, MARKOV_TEMPERATURE, MAX_TURING_LENGTH,
+    MIN_TURING_LENGTH,
+};
+
+use super::SequencerProgram;
+use crate::stdlib::{
+    ui::{Param, UIInputEvent},
+    TaskInterface,
+};
+
+const KINDS: &[&str] = &["Euclid", "Turing", "Markov"];
+const PITCH_MODES: &[&str] = &["Note", "Scale"];
+
+// index of the generator kind, the same for all kinds
+const KIND: usize = 0;
+
+// indexes of the Euclid parameters
+const STEPS: usize = 1;
+const PULSES: usize = 2;
+const ROTATION: usize = 3;
+const NOTE: usize = 4;
+const PITCH_MODE: usize = 5;
+
+// indexes of the Turing machine parameters
+const LENGTH: usize = 1;
+const FLIP: usize = 2;
+const SEED: usize = 3;
+const LOW_NOTE: usize = 4;
+const OCTAVES: usize = 5;
+
+// indexes of the Markov chain parameters
+const ORDER: usize = 1;
+const TEMPERATURE: usize = 2;
+const MARKOV_SEED: usize = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum GeneratorKind {
+    Euclid,
+    /// Shift register, playing live (and evolving) while the transport runs
+    Turing,
+    /// Variations of the track, from what usually follows what in it
+    Markov,
+}
+
+/// Page which generates a pattern from a few parameters. The pattern is
+/// previewed on the piano roll and only written to the track once applied.
+pub(crate) struct GeneratorPage {
+    pub(crate) kind: GeneratorKind,
+    pub(crate) params: Vec<Param>,
+    pub(crate) selected: usize,
+    pub(crate) preview: VoiceTrack,
+    turing: TuringMachine,
+    // step of the preview the machine is at
+    turing_step: usize,
+    // learned from the track when the page is opened (only for `GeneratorKind::Markov`)
+    markov: Option<Box<Markov>>,
+}
+
+impl GeneratorPage {
+    pub(crate) fn euclid(len: usize, key: &Key) -> Self {
+        let mut params = vec![
+            Param::choice("Gen", KINDS),
+            Param::new("Stp", 1, 32),
+            Param::new("Pul", 0, 32),
+            Param::new("Rot", 0, 31),
+            Param::new("Note", 0, 127),
+            Param::choice("Mode", PITCH_MODES),
+        ];
+        params[STEPS].set(8);
+        params[PULSES].set(3);
+        params[NOTE].set(60);
+
+        Self::new(GeneratorKind::Euclid, params, len, key)
+    }
+
+    pub(crate) fn turing(len: usize, key: &Key) -> Self {
+        let mut params = vec![
+            Param::choice("Gen", KINDS),
+            Param::new("Len", MIN_TURING_LENGTH as i32, MAX_TURING_LENGTH as i32),
+            // the "lock" knob: probability (%) of flipping the bit coming around
+            Param::new("Flip", 0, 50),
+            Param::new("Seed", 0, 999),
+            Param::new("Low", 0, 127),
+            Param::new("Oct", 1, 4),
+        ];
+        params[KIND].set(1);
+        params[LENGTH].set(8);
+        params[FLIP].set(10);
+        params[LOW_NOTE].set(48);
+        params[OCTAVES].set(2);
+
+        Self::new(GeneratorKind::Turing, params, len, key)
+    }
+
+    /// Learns from `source`, the preview is as long
+    pub(crate) fn markov(source: &VoiceTrack, key: &Key) -> Self {
+        let mut params = vec![
+            Param::choice("Gen", KINDS),
+            Param::new("Ord", 1, 2),
+            Param::new("Temp", 0, 2 * MARKOV_TEMPERATURE as i32).with_step(10),
+            Param::new("Seed", 0, 999),
+        ];
+        params[KIND].set(2);
+        params[ORDER].set(2);
+        params[TEMPERATURE].set(MARKOV_TEMPERATURE as i32);
+
+        let mut page = Self::new(GeneratorKind::Markov, params, source.len(), key);
+        page.markov = Some(Box::new(Markov::new(source)));
+        page.generate(key);
+        page
+    }
+
+    fn new(kind: GeneratorKind, params: Vec<Param>, len: usize, key: &Key) -> Self {
+        let mut page = Self {
+            kind,
+            params,
+            selected: 0,
+            preview: VoiceTrack::new(len),
+            turing: TuringMachine::new(0, MAX_TURING_LENGTH, 0),
+            turing_step: 0,
+            markov: None,
+        };
+        page.reset_turing();
+        page.generate(key);
+        page
+    }
+
+    fn value(&self, param: usize) -> i32 {
+        self.params[param].value
+    }
+
+    pub(crate) fn format_param(&self, param: usize) -> String<12> {
+        match (self.kind, param) {
+            (GeneratorKind::Euclid, NOTE) | (GeneratorKind::Turing, LOW_NOTE) => {
+                let mut out = String::new();
+                uwrite!(out, "{}", NotePair::from(self.value(param) as u8)).ok();
+                out
+            }
+            _ => self.params[param].format(),
+        }
+    }
+
+    fn change(&mut self, delta: i8, key: &Key, source: &VoiceTrack) {
+        self.params[self.selected].change(delta);
+
+        if self.selected == KIND {
+            let len = self.preview.len();
+            *self = match self.value(KIND) {
+                0 => Self::euclid(len, key),
+                1 => Self::turing(len, key),
+                _ => Self::markov(source, key),
+            };
+            return;
+        }
+
+        match self.kind {
+            GeneratorKind::Euclid => {
+                let steps = self.value(STEPS);
+                if self.value(PULSES) > steps {
+                    self.params[PULSES].set(steps);
+                }
+                if self.value(ROTATION) >= steps {
+                    self.params[ROTATION].set(steps - 1);
+                }
+            }
+            GeneratorKind::Turing => match self.selected {
+                // the loop so far is kept
+                FLIP => self.turing.flip = self.value(FLIP) as u8,
+                LENGTH | SEED => self.reset_turing(),
+                _ => {}
+            },
+            GeneratorKind::Markov => {}
+        }
+        self.generate(key);
+    }
+
+    fn reset_turing(&mut self) {
+        if self.kind == GeneratorKind::Turing {
+            self.turing = TuringMachine::new(
+                self.value(SEED) as u32,
+                self.value(LENGTH) as u8,
+                self.value(FLIP) as u8,
+            );
+        }
+    }
+
+    /// Update the preview with the current parameters
+    fn generate(&mut self, key: &Key) {
+        match self.kind {
+            GeneratorKind::Euclid => {
+                let euclid = Euclid::new(
+                    self.value(STEPS) as u8,
+                    self.value(PULSES) as u8,
+                    self.value(ROTATION) as u8,
+                );
+                let root = NotePair::from(self.value(NOTE) as u8);
+                let degrees = key.scale.mask().count_ones().max(1) as usize;
+                let scale = self.value(PITCH_MODE) == 1;
+
+                // in scale mode, onsets walk up the scale (one octave, from the root note)
+                euclid.fill(&mut self.preview, |n| match scale {
+                    true => key.transpose(&root, (n % degrees) as i8),
+                    false => root,
+                });
+            }
+            GeneratorKind::Turing => {
+                let low = NotePair::from(self.value(LOW_NOTE) as u8);
+                let octaves = self.value(OCTAVES) as u8;
+                self.turing.fill(&mut self.preview, self.turing_step, |value| {
+                    TuringMachine::pitch(value, key, &low, octaves)
+                });
+            }
+            GeneratorKind::Markov => {
+                if let Some(chain) = &self.markov {
+                    chain.generate(
+                        &mut self.preview,
+                        self.value(ORDER) as u8,
+                        self.value(TEMPERATURE) as u8,
+                        self.value(MARKOV_SEED) as u32,
+                    );
+                }
+            }
+        }
+    }
+
+    /// The track to play instead of the recorded one, while the page is open
+    pub(crate) fn live_track(&self) -> Option<&VoiceTrack> {
+        (self.kind == GeneratorKind::Turing).then_some(&self.preview)
+    }
+
+    /// The transport moved on to `beat`: the Turing machine takes a step,
+    /// and the preview shows the loop from there on
+    pub(crate) fn advance(&mut self, beat: u32, key: &Key) {
+        if self.kind != GeneratorKind::Turing {
+            return;
+        }
+        let step = beat as usize % self.preview.len();
+        if step != self.turing_step {
+            self.turing.step();
+            self.turing_step = step;
+            self.generate(key);
+        }
+    }
+}
+
+impl<'t, B: BlockDevice, TS: TimeSource, D: DrawTarget<Color = Rgb565>, TI: TaskInterface>
+    SequencerProgram<'t, B, TS, D, TI>
+where
+    <D as DrawTarget>::Error: Debug,
+{
+    /// Process input while the generator page is open. Returns `false` if it should be closed.
+    pub(crate) fn process_generator_input(&mut self, msg: &UIInputEvent) -> bool {
+        let key = self.tracks.key();
+        let page = match self.generator.as_mut() {
+            Some(page) => page,
+            None => return false,
+        };
+
+        match msg {
+            UIInputEvent::Switch1(true) => {
+                page.selected = (page.selected + 1) % page.params.len();
+            }
+            UIInputEvent::EncoderTurn(v) => page.change(*v, &key, &self.recorder.voice_state),
+            // also "freezes" the current loop of the Turing machine
+            UIInputEvent::EncoderSwitch(true) => {
+                // only the notes, gate lengths are kept
+                let pattern = &page.preview;
+                self.recorder.transform(|track| {
+                    for step in 0..track.len().min(pattern.len()) {
+                        track.set_note(step, pattern.get_note(step).unwrap()).ok();
+                    }
+                });
+                return false;
+            }
+            // cancel
+            UIInputEvent::Switch2(false) => return false,
+            _ => {}
+        }
+        true
+    }
+}
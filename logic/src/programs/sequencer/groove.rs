@@ -0,0 +1,78 @@
+use alloc::{vec, vec::Vec};
+use heapless::String;
+use serde::{Deserialize, Serialize};
+
+pub(crate) const GROOVE_LENGTH: usize = 8;
+
+// offsets are kept within half a step, so that steps never swap places
+const MAX_OFFSET_PERMILLE: i32 = 499;
+
+/// Per-step timing offsets (% of a step) and velocity scaling (%),
+/// repeated every `GROOVE_LENGTH` steps.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct GrooveTemplate {
+    pub(crate) name: String<8>,
+    pub(crate) offsets: [i8; GROOVE_LENGTH],
+    pub(crate) velocities: [u8; GROOVE_LENGTH],
+}
+
+impl GrooveTemplate {
+    fn new(name: &str, offsets: [i8; GROOVE_LENGTH], velocities: [u8; GROOVE_LENGTH]) -> Self {
+        Self {
+            name: name.into(),
+            offsets,
+            velocities,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Groove {
+    /// 50 is straight, ~66 is triplet feel
+    pub(crate) swing: u8,
+    /// Index into `templates`, if any is in use
+    pub(crate) template: Option<usize>,
+    pub(crate) templates: Vec<GrooveTemplate>,
+}
+
+impl Default for Groove {
+    fn default() -> Self {
+        Self {
+            swing: 50,
+            template: None,
+            templates: vec![
+                GrooveTemplate::new("Shuffle", [0, 20, 0, 20, 0, 20, 0, 20], [100, 80, 100, 80, 100, 80, 100, 80]),
+                GrooveTemplate::new("Push", [0, -8, 0, -8, 0, -8, 0, -8], [100, 90, 100, 90, 100, 90, 100, 90]),
+                GrooveTemplate::new("Drag", [0, 4, 8, 12, 0, 4, 8, 12], [100; GROOVE_LENGTH]),
+                GrooveTemplate::new("Accent", [0; GROOVE_LENGTH], [100, 60, 80, 60, 100, 60, 80, 60]),
+            ],
+        }
+    }
+}
+
+impl Groove {
+    fn current(&self) -> Option<&GrooveTemplate> {
+        self.template.and_then(|n| self.templates.get(n))
+    }
+
+    /// How much step `step` should be moved, in thousandths of a step
+    pub(crate) fn offset(&self, step: u32) -> i32 {
+        // swing delays every second step
+        let swing = if step % 2 == 1 {
+            (self.swing as i32 - 50) * 20
+        } else {
+            0
+        };
+        let template = self
+            .current()
+            .map_or(0, |t| t.offsets[step as usize % GROOVE_LENGTH] as i32 * 10);
+
+        (swing + template).clamp(-MAX_OFFSET_PERMILLE, MAX_OFFSET_PERMILLE)
+    }
+
+    /// Velocity scaling (%) of step `step`
+    pub(crate) fn velocity(&self, step: u32) -> u8 {
+        self.current()
+            .map_or(100, |t| t.velocities[step as usize % GROOVE_LENGTH])
+    }
+}
@@ -0,0 +1,220 @@
+use alloc::{collections::VecDeque, vec::Vec};
+use core::mem::size_of;
+use voice_lib::{NoteFlag, NotePair, TrigCondition, VoiceTrack};
+
+use crate::util::DiscreetUnwrap;
+
+/// How much of the heap the undo history may take up (it's 16 KB in total)
+const MAX_HISTORY_SIZE: usize = 4 * 1024;
+
+/// Everything stored for a step, so that undo restores all of it
+#[derive(Clone, Copy, PartialEq)]
+struct StepState {
+    note: (Option<NotePair>, NoteFlag),
+    gate: Option<u8>,
+    condition: TrigCondition,
+    ratchets: u8,
+    modulation: Option<u16>,
+}
+
+impl StepState {
+    fn get(track: &VoiceTrack, step: usize) -> Self {
+        Self {
+            note: track.get_note(step).unwrap_or((None, NoteFlag::None)),
+            gate: track.get_gate(step),
+            condition: track.get_condition(step),
+            ratchets: track.get_ratchets(step),
+            modulation: track.get_modulation(step),
+        }
+    }
+
+    fn set(&self, track: &mut VoiceTrack, step: usize) {
+        track.set_note(step, self.note).duwrp();
+        track.set_gate(step, self.gate);
+        track.set_condition(step, self.condition);
+        track.set_ratchets(step, self.ratchets);
+        track.set_modulation(step, self.modulation);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Change {
+    step: u16,
+    before: StepState,
+    after: StepState,
+}
+
+/// A reversible operation: all the steps it changed, with their old and new values
+#[derive(Default)]
+struct Op {
+    changes: Vec<Change>,
+}
+
+impl Op {
+    fn size(&self) -> usize {
+        size_of::<Self>() + self.changes.capacity() * size_of::<Change>()
+    }
+}
+
+/// Undo/redo history of the edits done to a track.
+/// Changes are grouped into operations (e.g. a whole recording pass), which are
+/// undone/redone as a whole. The oldest operations are dropped once the history
+/// grows beyond `MAX_HISTORY_SIZE`.
+#[derive(Default)]
+pub(crate) struct History {
+    undo: VecDeque<Op>,
+    redo: Vec<Op>,
+    pending: Option<Op>,
+    // the pending operation grew too big to be kept
+    overflow: bool,
+    size: usize,
+}
+
+impl History {
+    /// Start grouping changes into a single operation
+    pub(crate) fn begin(&mut self) {
+        self.commit();
+        self.pending = Some(Op::default());
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.size = 0;
+    }
+
+    /// Close the current operation (if any), making it undoable
+    pub(crate) fn commit(&mut self) {
+        self.overflow = false;
+        if let Some(op) = self.pending.take() {
+            if !op.changes.is_empty() {
+                self.size += op.size();
+                self.undo.push_back(op);
+                self.trim();
+            }
+        }
+    }
+
+    /// Set the note of a step of `track`, keeping track of the change.
+    /// Outside of `begin`/`commit`, every change is an operation of its own.
+    pub(crate) fn set_note(
+        &mut self,
+        track: &mut VoiceTrack,
+        step: usize,
+        note: (Option<NotePair>, NoteFlag),
+    ) {
+        self.edit(track, step, |track| track.set_note(step, note).duwrp());
+    }
+
+    /// Change anything about a step of `track` with `f`, keeping track of the change
+    pub(crate) fn edit(
+        &mut self,
+        track: &mut VoiceTrack,
+        step: usize,
+        f: impl FnOnce(&mut VoiceTrack),
+    ) {
+        let before = StepState::get(track, step);
+        f(track);
+        self.record(step, before, StepState::get(track, step));
+    }
+
+    /// Apply `f` to the whole track, as a single operation
+    pub(crate) fn transform<R>(
+        &mut self,
+        track: &mut VoiceTrack,
+        f: impl FnOnce(&mut VoiceTrack) -> R,
+    ) -> R {
+        let before: Vec<StepState> = (0..track.len())
+            .map(|step| StepState::get(track, step))
+            .collect();
+
+        let res = f(track);
+
+        self.begin();
+        for (step, before) in before.into_iter().enumerate() {
+            self.record(step, before, StepState::get(track, step));
+        }
+        self.commit();
+        res
+    }
+
+    fn record(&mut self, step: usize, before: StepState, after: StepState) {
+        if before == after || self.overflow {
+            return;
+        }
+
+        // anything which was undone is now lost
+        for op in self.redo.drain(..) {
+            self.size -= op.size();
+        }
+
+        let single = self.pending.is_none();
+        let op = self.pending.get_or_insert_with(Op::default);
+
+        // only the first `before` and the last `after` matter
+        match op.changes.iter_mut().find(|c| c.step as usize == step) {
+            Some(change) => change.after = after,
+            None => op.changes.push(Change {
+                step: step as u16,
+                before,
+                after,
+            }),
+        }
+
+        let op_size = op.size();
+
+        if single {
+            self.commit();
+        } else if self.size + op_size > MAX_HISTORY_SIZE {
+            // make room for the operation being recorded
+            self.trim_to(MAX_HISTORY_SIZE.saturating_sub(op_size));
+            if self.size + op_size > MAX_HISTORY_SIZE {
+                // too big to be undone at all. Older operations don't
+                // apply anymore either, since the track changed under them.
+                self.pending = None;
+                self.overflow = true;
+                self.clear();
+            }
+        }
+    }
+
+    pub(crate) fn undo(&mut self, track: &mut VoiceTrack) -> bool {
+        self.commit();
+        match self.undo.pop_back() {
+            Some(op) => {
+                for change in op.changes.iter().rev() {
+                    change.before.set(track, change.step as usize);
+                }
+                self.redo.push(op);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn redo(&mut self, track: &mut VoiceTrack) -> bool {
+        match self.redo.pop() {
+            Some(op) => {
+                for change in op.changes.iter() {
+                    change.after.set(track, change.step as usize);
+                }
+                self.undo.push_back(op);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn trim(&mut self) {
+        self.trim_to(MAX_HISTORY_SIZE);
+    }
+
+    fn trim_to(&mut self, max_size: usize) {
+        while self.size > max_size {
+            match self.undo.pop_front() {
+                Some(op) => self.size -= op.size(),
+                None => break,
+            }
+        }
+    }
+}
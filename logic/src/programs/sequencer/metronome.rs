@@ -0,0 +1,143 @@
+use core::fmt::Debug;
+
+use embedded_graphics::{draw_target::DrawTarget, pixelcolor::Rgb565};
+use embedded_sdmmc::{BlockDevice, TimeSource};
+use serde::{Deserialize, Serialize};
+use voice_lib::NotePair;
+
+use super::{song::STEPS_PER_BAR, transport::Position, SequencerProgram, State};
+use crate::stdlib::{GateChannelId, Output, TaskInterface};
+
+pub(crate) const MAX_COUNT_IN_BARS: u8 = 2;
+const CLICK_MS: u32 = 10;
+/// How long the beat flash lasts, in thousandths of a beat
+const FLASH_LENGTH: u32 = 250;
+
+/// Where the metronome clicks
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum ClickOutput {
+    Off,
+    Gate0,
+    Gate1,
+}
+
+impl ClickOutput {
+    pub(crate) fn channel(&self) -> Option<GateChannelId> {
+        match self {
+            ClickOutput::Off => None,
+            ClickOutput::Gate0 => Some(GateChannelId::Gate0),
+            ClickOutput::Gate1 => Some(GateChannelId::Gate1),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct MetronomeSettings {
+    /// Bars counted in before recording starts, 0 for none
+    pub(crate) count_in: u8,
+    pub(crate) output: ClickOutput,
+    /// Keep clicking after the count-in, while recording
+    pub(crate) while_recording: bool,
+}
+
+impl Default for MetronomeSettings {
+    fn default() -> Self {
+        Self {
+            count_in: 1,
+            output: ClickOutput::Off,
+            while_recording: false,
+        }
+    }
+}
+
+/// The count-in before recording, and the clicks of the metronome
+#[derive(Default)]
+pub(crate) struct Metronome {
+    /// Time since the count-in started, while counting in
+    count_in: Option<Position>,
+    last_click: Option<u32>,
+}
+
+impl Metronome {
+    pub(crate) fn counting_in(&self) -> bool {
+        self.count_in.is_some()
+    }
+
+    /// Stop counting in. Returns whether it was.
+    pub(crate) fn cancel(&mut self) -> bool {
+        self.last_click = None;
+        self.count_in.take().is_some()
+    }
+
+    /// The beat which should be clicked now (if any), once per beat
+    fn due_click(&mut self, pos: Position) -> Option<u32> {
+        if self.last_click == Some(pos.beat) {
+            None
+        } else {
+            self.last_click = Some(pos.beat);
+            Some(pos.beat)
+        }
+    }
+}
+
+impl<'t, B: BlockDevice, TS: TimeSource, D: DrawTarget<Color = Rgb565>, TI: TaskInterface>
+    SequencerProgram<'t, B, TS, D, TI>
+where
+    <D as DrawTarget>::Error: Debug,
+{
+    /// Start recording: after the count-in, if there is one and the transport isn't running yet
+    pub(crate) fn record(&mut self, position: Position) {
+        let running = matches!(self.state, State::Playing(_) | State::Recording(_));
+        self.metronome.last_click = None;
+        if running || self.config.metronome.count_in == 0 {
+            self.set_state(State::Recording(position));
+        } else {
+            self.metronome.count_in = Some(Position::default());
+        }
+    }
+
+    /// Move the count-in forward by `elapsed_ms`. Recording starts once it's over.
+    pub(crate) fn count_in(&mut self, elapsed_ms: u32) {
+        let bars = self.config.metronome.count_in as u32;
+        let pos = match self.metronome.count_in.as_mut() {
+            Some(pos) => pos,
+            None => return,
+        };
+        pos.advance(elapsed_ms, self.bpm);
+        if pos.beat >= bars * STEPS_PER_BAR {
+            self.metronome.cancel();
+            self.set_state(State::Recording(self.state.position()));
+        }
+    }
+
+    /// Beats left to count in, and whether the current one is flashing
+    pub(crate) fn beat_flash(&self) -> Option<(u32, bool)> {
+        let bars = self.config.metronome.count_in as u32;
+        match (self.metronome.count_in, &self.state) {
+            (Some(pos), _) => Some((
+                (bars * STEPS_PER_BAR).saturating_sub(pos.beat),
+                pos.phase(1000) < FLASH_LENGTH,
+            )),
+            (None, State::Recording(pos)) if self.config.metronome.while_recording => {
+                Some((0, pos.phase(1000) < FLASH_LENGTH))
+            }
+            _ => None,
+        }
+    }
+
+    /// Click on every beat of the count-in (and of the recording, if enabled)
+    pub(crate) fn update_metronome<T: for<'u> TryFrom<&'u NotePair, Error = E>, E>(
+        &mut self,
+        output: &mut impl Output<T, E>,
+    ) {
+        let settings = self.config.metronome;
+        let pos = match (self.metronome.count_in, &self.state) {
+            (Some(pos), _) => pos,
+            (None, State::Recording(pos)) if settings.while_recording => *pos,
+            _ => return,
+        };
+        if let (Some(_), Some(gate)) = (self.metronome.due_click(pos), settings.output.channel()) {
+            output.pulse_gate(gate, CLICK_MS);
+        }
+    }
+}
@@ -3,15 +3,28 @@ use core::{
 };
 
 use alloc::{format, boxed::Box};
+use ciborium::{de::from_reader, value::Value};
 use embedded_graphics::{pixelcolor::Rgb565, prelude::*};
 use embedded_midi::{MidiMessage};
 use embedded_sdmmc::{BlockDevice, TimeSource};
 use heapless::{spsc::Queue, String};
 
 use self::{
-    recorder::MonoRecorderBox,
+    arp::Arp,
+    clock::Clock,
+    data::{FileFormat, SequenceFile},
+    generator::GeneratorPage,
+    metronome::Metronome,
+    modulation::ModLane,
+    playback::Playback,
+    recorder::{MonoRecorderBox, RecordMode},
+    song::{Patterns, MAX_TRACKS_SIZE},
+    step_edit::StepEdit,
+    tracks::{Tracks, NUM_TRACKS},
+    transport::{Bpm, Position, TapTempo},
     ui::{
         actions::{UIAction, NUM_UI_ACTIONS},
+        overlays::MainMenu,
     }, config::Config
 };
 use crate::{
@@ -19,17 +32,32 @@ use crate::{
     stdlib::{
         ui::{UIInputEvent, OverlayManager},
         StdlibError,
-        TaskInterface, TaskType, Output, GateChannelId, CVChannelId, TaskResult, FSError, FileContent,
+        TaskInterface, TaskType, Output, TaskResult, FSError, FileContent,
     },
-    util::{midi_note_to_lib, DiscreetUnwrap, QueuePoppingIter},
+    util::{midi_note_to_lib, DiscreetUnwrap},
 };
-use voice_lib::{Note, NoteFlag, NotePair};
+use voice_lib::{Note, NoteFlag, NotePair, VoiceTrack};
 
 use super::Program;
 
+mod arp;
+mod clock;
 mod config;
 mod data;
+mod generator;
+mod groove;
+mod history;
+mod metronome;
+mod modulation;
+mod playback;
+mod quantize;
 mod recorder;
+mod smf;
+mod song;
+mod step_edit;
+mod text;
+mod tracks;
+mod transport;
 mod ui;
 
 
@@ -37,22 +65,31 @@ mod ui;
 pub(crate) enum State {
     Loading,
     Stopped,
-    Paused(/* at_time: */ u32, /* at_beat: */ u32),
-    Playing(/* time: */ u32, /* beat: */ u32),
-    Recording(/* time: */ u32, /* beat: */ u32),
+    Paused(/* at: */ Position),
+    Playing(Position),
+    Recording(Position),
 }
 
 impl State {
-    pub(crate) fn get_time(&self) -> (u32, u32) {
+    pub(crate) fn position(&self) -> Position {
         match self {
-            State::Loading => (0, 0),
-            State::Stopped => (0, 0),
-            State::Paused(time, beat) => (*time, *beat),
-            State::Playing(time, beat) | State::Recording(time, beat) => (*time, *beat),
+            State::Loading => Position::default(),
+            State::Stopped => Position::default(),
+            State::Paused(pos) => *pos,
+            State::Playing(pos) | State::Recording(pos) => *pos,
         }
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum TempoEdit {
+    Off,
+    // whole BPMs
+    Coarse,
+    // tenths of BPM
+    Fine,
+}
+
 
 pub struct SequencerProgram<
     't,
@@ -69,12 +106,31 @@ pub struct SequencerProgram<
     prev_program_time: Option<u32>,
 
     midi_queue: Queue<MidiMessage, 16>,
-    pub(crate) bpm: u16,
+    pub(crate) bpm: Bpm,
+    tap_tempo: TapTempo,
     pub(crate) recorder: MonoRecorderBox<'t>,
+    pub(crate) patterns: Patterns,
+    pub(crate) tracks: Tracks,
     pub(crate) state: State,
+    pub(crate) config: Config,
+    // one per track
+    playback: [Playback; NUM_TRACKS],
+    clock: Clock,
+    pub(crate) arp: Arp,
+    mod_lane: ModLane,
+    metronome: Metronome,
 
     // UI
     pub(crate) selected_action: UIAction,
+    pub(crate) tempo_edit: TempoEdit,
+    pub(crate) step_edit: Option<StepEdit>,
+    pub(crate) generator: Option<GeneratorPage>,
+    // Switch2 is held down (and whether the encoder was turned meanwhile)
+    switch2_held: Option<bool>,
+    // a file was requested, to replace the track with
+    pending_import: Option<FileFormat>,
+    // a project file was requested, to replace all patterns with
+    pending_load: bool,
     pub(crate) overlay_manager: Option<OverlayManager<'t, Self, B, TS, D, TI>>,
 
     _d: PhantomData<D>,
@@ -87,7 +143,87 @@ where
 {
     fn save(&mut self, file_name: String<8>) -> Result<TaskType, StdlibError> {
         self.recorder.set_file_name(&file_name);
-        self.recorder.save_file()
+        self.recorder.save_file(&self.patterns, &self.tracks, self.bpm)
+    }
+
+    fn load(&mut self, file_name: String<8>) -> TaskType {
+        self.pending_load = true;
+        self.recorder.load_file(&file_name)
+    }
+
+    fn loaded(&mut self, file: SequenceFile<VoiceTrack>) {
+        let size: usize = file.patterns.iter().flatten().flatten().map(VoiceTrack::size).sum();
+        if size > MAX_TRACKS_SIZE {
+            error("Project doesn't fit in memory");
+            return;
+        }
+        let tracks = self.patterns.replace(file.patterns, file.current, file.song);
+        self.tracks.settings = file.tracks;
+        let track = self.tracks.replace_all(tracks);
+        // before the track, which the punch points are clamped to
+        self.recorder.punch = file.punch;
+        self.recorder.replace_track(track);
+        self.recorder.mode = file.record_mode;
+        if let Some(bpm) = file.bpm {
+            self.bpm = bpm;
+        }
+        self.step_edit = None;
+        self.state = State::Stopped;
+    }
+
+    fn export(&mut self, file_name: String<8>, format: FileFormat) -> Result<TaskType, StdlibError> {
+        self.recorder.export(&file_name, format, self.bpm)
+    }
+
+    fn import(&mut self, file_name: String<8>, format: FileFormat) -> TaskType {
+        self.pending_import = Some(format);
+        format.load(&file_name)
+    }
+
+    fn imported(&mut self, format: FileFormat, result: TaskResult) {
+        match format.read(result) {
+            Ok((track, bpm)) => {
+                let grow = track.size().saturating_sub(self.recorder.voice_state.size());
+                if !self.tracks_fit(grow) {
+                    error("Not enough memory for the imported track");
+                    return;
+                }
+                self.recorder.replace_track(track);
+                if let Some(bpm) = bpm {
+                    self.bpm = bpm;
+                }
+                self.state = State::Stopped;
+            }
+            Err(e) => error(&format!("Invalid {:?} file: {:?}", format, e)),
+        }
+    }
+
+    /// Start, stop or pause the transport
+    pub(crate) fn set_state(&mut self, state: State) {
+        if matches!(state, State::Playing(pos) | State::Recording(pos) if pos == Position::default()) {
+            self.clock.start();
+            self.tracks.rewind();
+            if let Some(pattern) = self.patterns.restart_song() {
+                self.switch_pattern(pattern);
+            }
+        }
+
+        // a whole recording pass can be undone at once
+        match (&self.state, &state) {
+            (State::Recording(_), State::Recording(_)) => {}
+            (_, State::Recording(_)) => self.recorder.start_pass(),
+            (State::Recording(_), _) => self.recorder.end_pass(),
+            _ => {}
+        }
+        self.state = state;
+    }
+
+    pub(crate) fn save_config(&self) -> Result<TaskType, StdlibError> {
+        Ok(TaskType::FileSave(
+            "cfg".into(),
+            "config.cbr".into(),
+            Box::new(Value::serialized(&self.config)?),
+        ))
     }
 
     fn _first_run(&mut self, task_iface: &mut TI) {
@@ -110,13 +246,28 @@ where
             current_note: 70, // C5,
             prev_program_time: None,
             program_time: 0,
-            bpm: 50,
+            bpm: Bpm::from_tenths(1200),
+            tap_tempo: TapTempo::new(),
             midi_queue: Queue::new(),
             recorder: MonoRecorderBox::new(),
+            patterns: Patterns::default(),
+            tracks: Tracks::default(),
             state: State::Loading,
+            config: Config::default(),
+            playback: Default::default(),
+            clock: Clock::default(),
+            arp: Arp::default(),
+            mod_lane: ModLane::default(),
+            metronome: Metronome::default(),
 
             // UI
             selected_action: UIAction::PlayPause,
+            tempo_edit: TempoEdit::Off,
+            step_edit: None,
+            generator: None,
+            switch2_held: None,
+            pending_import: None,
+            pending_load: false,
             overlay_manager: Some(OverlayManager::new()),
             // Icons
             _d: PhantomData,
@@ -134,19 +285,116 @@ where
     where
         't: 'u,
     {
-        let (state_time, state_beat) = self.state.get_time();
+        let position = self.state.position();
 
         let mut overlay_manager = self.overlay_manager.take().unwrap();
 
         let stop_here = overlay_manager.process_input(msg)?;
 
+        if stop_here {
+            self.overlay_manager.replace(overlay_manager);
+            return Ok(());
+        }
+
+        if let (UIAction::Menu, UIInputEvent::EncoderSwitch(true)) = (self.selected_action, msg) {
+            overlay_manager.open(Box::new(MainMenu::default()));
+            self.overlay_manager.replace(overlay_manager);
+            return Ok(());
+        }
+
         self.overlay_manager.replace(overlay_manager);
 
-        if stop_here {
+        // undo/redo: hold Switch2 and turn the encoder
+        match msg {
+            UIInputEvent::Switch2(true) => {
+                self.switch2_held = Some(false);
+            }
+            UIInputEvent::Switch2(false) => {
+                if let Some(true) = self.switch2_held.take() {
+                    // it was an undo/redo gesture, not a button press
+                    return Ok(());
+                }
+            }
+            UIInputEvent::EncoderTurn(v) if self.switch2_held.is_some() => {
+                self.switch2_held = Some(true);
+                if !matches!(self.state, State::Recording(_)) {
+                    for _ in 0..v.unsigned_abs() {
+                        if *v < 0 {
+                            self.recorder.undo();
+                        } else {
+                            self.recorder.redo();
+                        }
+                    }
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        if self.step_edit.is_some() {
+            if !self.process_step_edit_input(msg) {
+                self.step_edit = None;
+            }
+            return Ok(());
+        }
+
+        if self.generator.is_some() {
+            if !self.process_generator_input(msg) {
+                self.generator = None;
+            }
+            return Ok(());
+        }
+
+        if self.tempo_edit != TempoEdit::Off {
+            match msg {
+                UIInputEvent::EncoderTurn(v) => {
+                    self.bpm.change(if self.tempo_edit == TempoEdit::Coarse {
+                        *v as i32 * 10
+                    } else {
+                        *v as i32
+                    });
+                }
+                UIInputEvent::EncoderSwitch(true) => {
+                    self.tempo_edit = match self.tempo_edit {
+                        TempoEdit::Coarse => TempoEdit::Fine,
+                        _ => TempoEdit::Off,
+                    };
+                }
+                _ => {}
+            }
             return Ok(());
         }
 
         match msg {
+            UIInputEvent::Switch1(true) => {
+                if let Some(bpm) = self.tap_tempo.tap(self.program_time) {
+                    self.bpm = bpm;
+                }
+            }
+            UIInputEvent::Switch2(false) => {
+                self.patterns.queue_next();
+                self.switch_if_stopped();
+            }
+            UIInputEvent::EncoderSwitch(true) if matches!(self.selected_action, UIAction::Tempo) => {
+                self.tempo_edit = TempoEdit::Coarse;
+            }
+            UIInputEvent::EncoderSwitch(true) if matches!(self.selected_action, UIAction::Edit) => {
+                // step-recording goes on from the same cursor
+                let step = match self.recorder.mode {
+                    RecordMode::Step => self.recorder.cursor,
+                    _ => position.beat as usize,
+                };
+                self.step_edit = Some(StepEdit::new(step % self.recorder.voice_state.len()));
+            }
+            UIInputEvent::EncoderSwitch(true) if matches!(self.selected_action, UIAction::Generate) => {
+                self.generator = Some(GeneratorPage::euclid(
+                    self.recorder.voice_state.len(),
+                    &self.tracks.key(),
+                ));
+            }
+            UIInputEvent::EncoderSwitch(true) if matches!(self.selected_action, UIAction::Track) => {
+                self.select_track((self.tracks.selected + 1) % NUM_TRACKS);
+            }
             UIInputEvent::EncoderTurn(v) => {
                 self.selected_action = ((self.selected_action as i8)
                     .wrapping_add(*v)
@@ -155,17 +403,30 @@ where
                     .into();
             }
             UIInputEvent::EncoderSwitch(true) => {
-                self.state = match self.selected_action {
+                // any transport button stops the count-in
+                if self.metronome.cancel() {
+                    return Ok(());
+                }
+                let state = match self.selected_action {
                     UIAction::PlayPause => match self.state {
-                        State::Playing(_, _) => State::Paused(state_time, state_beat),
-                        State::Paused(time, beat) => State::Playing(time, beat),
-                        State::Loading | State::Stopped | State::Recording(_, _) => State::Playing(0, 0),
+                        State::Playing(_) => State::Paused(position),
+                        State::Paused(pos) => State::Playing(pos),
+                        State::Loading | State::Stopped | State::Recording(_) => State::Playing(Position::default()),
                     },
                     UIAction::Stop => State::Stopped,
-                    UIAction::Record => State::Recording(state_time, state_beat),
+                    UIAction::Record => {
+                        self.record(position);
+                        return Ok(());
+                    }
                     UIAction::Beginning => State::Stopped,
                     UIAction::Seek => todo!(),
-                }
+                    UIAction::Menu
+                    | UIAction::Tempo
+                    | UIAction::Edit
+                    | UIAction::Generate
+                    | UIAction::Track => unreachable!(),
+                };
+                self.set_state(state);
             }
             _ => {}
         }
@@ -177,18 +438,25 @@ where
     }
 
     fn update_output<T: for<'u> TryFrom<&'u NotePair, Error = E>, E: Debug, O: Deref<Target = impl Output<T, E>> + DerefMut>(
-        &self,
+        &mut self,
         mut output: O,
     ) -> Result<(), E> {
         // TODO: polyphonic
-        match self.recorder.last_note() {
-            None => {
-                output.set_gate(GateChannelId::Gate0, false);
-            }
-            Some(np) => {
-                output.set_gate(GateChannelId::Gate0, true);
-                output.set_cv(CVChannelId::CV0, np.try_into()?);
+
+        let transport = match self.state {
+            State::Playing(pos) | State::Recording(pos) => Some(pos),
+            _ => None,
+        };
+        self.clock.update(&self.config.clock, transport, &mut *output);
+        self.update_modulation(&mut *output);
+        self.update_metronome(&mut *output);
+
+        let live = self.play_live(&mut *output)?;
+        for n in 0..NUM_TRACKS {
+            if live && n == self.tracks.selected {
+                continue;
             }
+            self.play_track(n, &mut *output)?;
         }
         Ok(())
     }
@@ -236,45 +504,80 @@ where
             },
         };
 
-        match self.state {
-            State::Recording(time, beat) => {
-                let new_time = time + time_diff;
-                let new_beat = new_time * self.bpm as u32 / 60_000;
-                self.state = State::Recording(new_time, new_beat);
+        self.count_in(time_diff);
 
-                if beat != new_beat {
-                    self.recorder.beat(beat as usize);
-                }
-            }
-            State::Playing(time, _) => {
-                let new_time = time + time_diff;
-                let new_beat = new_time * self.bpm as u32 / 60_000;
-                self.state = State::Playing(new_time, new_beat);
+        // the recorder follows the selected track, which has a rate of its own
+        let selected = self.tracks.selected;
+        let before = self.track_position(selected);
+        let new_step = match &mut self.state {
+            State::Playing(pos) | State::Recording(pos) => pos.advance(time_diff, self.bpm),
+            _ => false,
+        };
+        if let (State::Recording(_), Some(before), Some(after)) =
+            (&self.state, before, self.track_position(selected))
+        {
+            if after.beat != before.beat {
+                self.recorder.beat(before.beat as usize);
             }
-            _ => {}
+        }
+        if new_step {
+            self.tracks.master_step(self.state.position().beat);
+            self.pattern_step();
         }
 
-        self.prev_program_time = Some(self.program_time);
+        self.clock.advance(time_diff, self.bpm);
 
-        let (_, beats) = self.state.get_time();
+        self.prev_program_time = Some(self.program_time);
 
-        for msg in QueuePoppingIter::new(&mut self.midi_queue) {
-            match msg {
-                MidiMessage::NoteOff(_, n, _) => {
-                    self.recorder
-                        .key_released(beats as usize, midi_note_to_lib(n));
+        let time = self
+            .tracks
+            .position(self.tracks.selected, self.state.position())
+            .thousandths();
+
+        let arp = self.config.arp;
+        // notes are recorded on the selected track, in its key
+        let lock = self.tracks.settings[self.tracks.selected].scale_lock;
+
+        while let Some(msg) = self.midi_queue.dequeue() {
+            // (note, pressed)
+            let (channel, key) = match msg {
+                MidiMessage::ControlChange(_, cc, value) => {
+                    self.mod_cc(cc.into(), value.into());
+                    continue;
                 }
-                MidiMessage::NoteOn(_, n, v) => {
-                    if v == 0.into() {
-                        // equivalent to NoteOff
-                        self.recorder
-                            .key_released(beats as usize, midi_note_to_lib(n));
-                    } else {
-                        self.recorder
-                            .key_pressed(beats as usize, midi_note_to_lib(n));
-                    }
+                MidiMessage::NoteOff(ch, n, _) => (ch, (midi_note_to_lib(n), false)),
+                // velocity 0 is equivalent to NoteOff
+                MidiMessage::NoteOn(ch, n, v) => (ch, (midi_note_to_lib(n), v != 0.into())),
+                _ => continue,
+            };
+            if !self.tracks.listens(channel.into()) {
+                continue;
+            }
+            // with the arpeggiator on, keys go to it instead of the track
+            match (arp.enabled, key) {
+                (true, (n, true)) => self.arp.key_pressed(&arp, n),
+                (true, (n, false)) => self.arp.key_released(&arp, n),
+                (false, (n, true)) if self.recorder.mode == RecordMode::Step => {
+                    self.step_record(lock.on_record(n))
+                }
+                (false, (n, true)) => self.recorder.key_pressed(time, lock.on_record(n)),
+                (false, (n, false)) => self.recorder.key_released(time, lock.on_record(n)),
+            }
+        }
+
+        if arp.enabled {
+            let pos = match self.state {
+                State::Playing(pos) | State::Recording(pos) => pos,
+                _ => self.clock.free_position(),
+            };
+            if let Some(np) = self.arp.tick(&arp, &pos) {
+                self.arp.pending = Some(np);
+                if matches!(self.state, State::Recording(_)) && self.recorder.mode != RecordMode::Step {
+                    // recorded like a short key press
+                    let np = lock.on_record(np);
+                    self.recorder.key_pressed(time, np);
+                    self.recorder.key_released(time, np);
                 }
-                _ => {}
             }
         }
 
@@ -287,6 +590,14 @@ where
                 match result {
                     TaskResult::FileContent(content) => {
                         info(&format!("Config loaded: {:?}", content));
+                        match content.deserialized() {
+                            Ok(config) => {
+                                self.config = config;
+                            }
+                            Err(e) => {
+                                error(&format!("Invalid config file, using defaults: {:?}", e));
+                            }
+                        }
                         // set state to stopped
                         self.state = State::Stopped;
                     },
@@ -305,6 +616,29 @@ where
                         error(&format!("Completely unexpected task result: {:?}", res));
                     }
                 }
+            } else if let Some(format) = self.pending_import {
+                match result {
+                    TaskResult::FileBytes(_) | TaskResult::FileStream(_) => {
+                        self.pending_import = None;
+                        self.imported(format, result);
+                    }
+                    TaskResult::Error(_) => self.pending_import = None,
+                    _ => {}
+                }
+            } else if self.pending_load {
+                match result {
+                    TaskResult::FileBytes(data) => {
+                        self.pending_load = false;
+                        // make room for the tracks of the project
+                        self.recorder.clear_history();
+                        match from_reader(&data[..]) {
+                            Ok(file) => self.loaded(file),
+                            Err(e) => error(&format!("Invalid project file: {:?}", e)),
+                        }
+                    }
+                    TaskResult::Error(_) => self.pending_load = false,
+                    _ => {}
+                }
             }
         }
     
@@ -0,0 +1,165 @@
+use core::fmt::Debug;
+
+use embedded_graphics::{draw_target::DrawTarget, pixelcolor::Rgb565};
+use embedded_sdmmc::{BlockDevice, TimeSource};
+use serde::{Deserialize, Serialize};
+use voice_lib::{NotePair, VoiceTrack, MAX_MOD_VALUE};
+
+use super::{transport::Position, SequencerProgram, State};
+use crate::stdlib::{CVChannelId, GlideMode, Output, TaskInterface};
+
+/// Where the modulation lane is sent
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum ModOutput {
+    Off,
+    CV1,
+    /// Takes over the pitch output
+    CV0,
+}
+
+impl ModOutput {
+    pub(crate) fn channel(&self) -> Option<CVChannelId> {
+        match self {
+            ModOutput::Off => None,
+            ModOutput::CV1 => Some(CVChannelId::CV1),
+            ModOutput::CV0 => Some(CVChannelId::CV0),
+        }
+    }
+}
+
+/// What happens between the steps of the modulation lane
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum ModInterpolation {
+    /// Each step outputs its own value, steps without one go back to 0
+    Step,
+    /// Steps without a value keep the last one
+    Hold,
+    /// Like `Hold`, but gliding towards the next value over the step
+    Slew,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ModLaneSettings {
+    pub(crate) output: ModOutput,
+    pub(crate) interpolation: ModInterpolation,
+    /// MIDI CC which is recorded into the lane
+    pub(crate) cc: u8,
+}
+
+impl Default for ModLaneSettings {
+    fn default() -> Self {
+        Self {
+            output: ModOutput::Off,
+            interpolation: ModInterpolation::Hold,
+            // usually filter cutoff
+            cc: 74,
+        }
+    }
+}
+
+/// Value of the lane of `track` at `step`
+pub(crate) fn value_at(track: &VoiceTrack, step: usize, interpolation: ModInterpolation) -> u16 {
+    let len = track.len();
+    match interpolation {
+        ModInterpolation::Step => track.get_modulation(step % len),
+        // the last value before `step`, wrapping around
+        ModInterpolation::Hold | ModInterpolation::Slew => (0..len)
+            .map(|n| (step + len - n) % len)
+            .find_map(|n| track.get_modulation(n)),
+    }
+    .unwrap_or(0)
+}
+
+/// Scale a 7-bit MIDI CC value to the range of the lane
+pub(crate) fn from_cc(value: u8) -> u16 {
+    value.min(127) as u16 * MAX_MOD_VALUE / 127
+}
+
+/// Keeps track of what was last sent to the modulation output
+#[derive(Default)]
+pub(crate) struct ModLane {
+    last_step: Option<u32>,
+    /// Value which just came in over MIDI, to be output right away
+    pub(crate) live: Option<u16>,
+}
+
+impl ModLane {
+    /// The step whose value should be output now (once per step)
+    fn due_step(&mut self, pos: Position) -> Option<u32> {
+        if self.last_step == Some(pos.beat) {
+            None
+        } else {
+            self.last_step = Some(pos.beat);
+            Some(pos.beat)
+        }
+    }
+}
+
+impl<'t, B: BlockDevice, TS: TimeSource, D: DrawTarget<Color = Rgb565>, TI: TaskInterface>
+    SequencerProgram<'t, B, TS, D, TI>
+where
+    <D as DrawTarget>::Error: Debug,
+{
+    /// A MIDI CC came in. The lane's CC is monitored, and recorded while recording.
+    pub(crate) fn mod_cc(&mut self, cc: u8, value: u8) {
+        if cc != self.config.modulation.cc {
+            return;
+        }
+        let value = from_cc(value);
+        self.mod_lane.live = Some(value);
+
+        let pos = self.track_position(self.tracks.selected);
+        if let (State::Recording(_), Some(pos)) = (&self.state, pos) {
+            let step = pos.beat as usize % self.recorder.voice_state.len();
+            if !self.recorder.punched_in(step) {
+                return;
+            }
+            self.recorder
+                .edit_step(step, |track, step| track.set_modulation(step, Some(value)));
+        }
+    }
+
+    pub(crate) fn update_modulation<T: for<'u> TryFrom<&'u NotePair, Error = E>, E>(
+        &mut self,
+        output: &mut impl Output<T, E>,
+    ) {
+        let settings = self.config.modulation;
+        let channel = match settings.output.channel() {
+            Some(channel) => channel,
+            None => return,
+        };
+
+        if let Some(value) = self.mod_lane.live.take() {
+            output.set_glide(channel, None);
+            output.set_cv_raw(channel, value);
+            return;
+        }
+
+        // the lane belongs to the selected track, and moves along with it
+        let selected = self.tracks.selected;
+        let beat = match self.track_position(selected) {
+            Some(pos) => match self.mod_lane.due_step(pos) {
+                Some(beat) => beat as usize,
+                None => return,
+            },
+            None => {
+                self.mod_lane.last_step = None;
+                return;
+            }
+        };
+
+        let track = &self.recorder.voice_state;
+        let (value, glide) = match settings.interpolation {
+            // arrive at the value of the next step right when it starts
+            ModInterpolation::Slew => (
+                value_at(track, beat + 1, settings.interpolation),
+                Some(GlideMode::Time(
+                    self.tracks.step_ms(selected, self.bpm.beat_ms()).min(u16::MAX as u32) as u16,
+                )),
+            ),
+            interpolation => (value_at(track, beat, interpolation), None),
+        };
+        output.set_glide(channel, glide);
+        output.set_cv_raw(channel, value);
+    }
+}
@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+use voice_lib::{NoteFlag, Trigs, VoiceTrack};
+
+use super::{groove::Groove, transport::Position};
+
+const TRIGGER_LENGTH_MS: u32 = 10;
+/// Probability conditions play out the same way every time the transport starts from the top
+const TRIG_SEED: u32 = 0x5eed;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum GateMode {
+    /// % of the step
+    Length(u8),
+    /// Fixed length (ms)
+    Fixed(u16),
+    /// Short fixed pulse
+    Trigger,
+}
+
+impl Default for GateMode {
+    fn default() -> Self {
+        GateMode::Length(75)
+    }
+}
+
+impl GateMode {
+    /// How long (ms) the gate should stay up for sub-trigger `ratchet` of step `beat` of `track`
+    /// (there is only sub-trigger 0, unless the step has ratchets).
+    /// There is no velocity output, so `velocity` (%) shortens/lengthens the gate instead.
+    pub(crate) fn gate_length(
+        &self,
+        track: &VoiceTrack,
+        beat: usize,
+        step_ms: u32,
+        velocity: u8,
+        ratchet: u8,
+    ) -> u32 {
+        let ratchets = track.get_ratchets(beat);
+        let sub_ms = step_ms / ratchets as u32;
+
+        // tied to the next step? then stay up until that one is played
+        if let Some((_, NoteFlag::Legato)) = track.get_note((beat + 1) % track.len()) {
+            if ratchet + 1 == ratchets {
+                return sub_ms * 2;
+            }
+        }
+
+        let length = match (track.get_gate(beat), *self) {
+            (Some(pct), _) | (None, GateMode::Length(pct)) => sub_ms * pct as u32 / 100,
+            // leave a gap between sub-triggers
+            (None, GateMode::Fixed(ms)) if ratchets > 1 => (ms as u32).min(sub_ms * 3 / 4),
+            (None, GateMode::Fixed(ms)) => ms as u32,
+            (None, GateMode::Trigger) => return TRIGGER_LENGTH_MS,
+        };
+        (length * velocity as u32 / 100).max(1)
+    }
+}
+
+/// `velocity` (%) of sub-trigger `ratchet` of a step, each one `decay` % quieter than the one before
+pub(crate) fn ratchet_velocity(velocity: u8, decay: u8, ratchet: u8) -> u8 {
+    let mut velocity = velocity as u32;
+    for _ in 0..ratchet {
+        velocity = velocity * (100 - decay.min(100) as u32) / 100;
+    }
+    velocity.max(1) as u8
+}
+
+/// Sub-triggers of the step being played
+struct Ratchets {
+    // when the step started, in thousandths of a step
+    start: i64,
+    count: u8,
+    next: u8,
+}
+
+/// Keeps track of what to the outputs, so that
+/// events are only fired once per step.
+pub(crate) struct Playback {
+    pub(crate) last_step: Option<u32>,
+    pub(crate) live: bool,
+    trigs: Trigs,
+    // the last note didn't fire, so the steps tied to it are silent too
+    muted: bool,
+    // when the last step was due, in thousandths of a step
+    due: i64,
+    ratchets: Option<Ratchets>,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            last_step: None,
+            live: false,
+            trigs: Trigs::new(TRIG_SEED),
+            muted: false,
+            due: 0,
+            ratchets: None,
+        }
+    }
+}
+
+impl Playback {
+    /// The step which should be played now (if any), taking the groove into account.
+    /// The recorded data is never touched, only the moment the step is played changes.
+    pub(crate) fn due_step(&mut self, pos: Position, groove: &Groove) -> Option<u32> {
+        let next = match self.last_step {
+            Some(step) if step <= pos.beat + 1 => step + 1,
+            // just started, or jumped back
+            _ => {
+                self.ratchets = None;
+                if pos.beat == 0 {
+                    self.trigs.reset();
+                }
+                pos.beat
+            }
+        };
+
+        // in thousandths of a step
+        let now = pos.thousandths() as i64;
+        let due = next as i64 * 1000 + groove.offset(next) as i64;
+
+        if now >= due {
+            self.last_step = Some(next);
+            self.due = due;
+            self.ratchets = None;
+            Some(next)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the note on step `beat` (of the transport) of `track` should be played,
+    /// according to its trig condition
+    pub(crate) fn fires(&mut self, track: &VoiceTrack, beat: u32, flag: NoteFlag) -> bool {
+        let len = track.len() as u32;
+        if flag == NoteFlag::Note {
+            let step = (beat % len) as usize;
+            self.muted = !self.trigs.fires(track.get_condition(step), beat / len);
+        }
+        !self.muted
+    }
+
+    /// Schedule the remaining sub-triggers of the step which was just played
+    pub(crate) fn start_ratchets(&mut self, count: u8) {
+        self.ratchets = (count > 1).then(|| Ratchets {
+            start: self.due,
+            count,
+            next: 1,
+        });
+    }
+
+    /// The sub-trigger of the current step which should be fired now, if any
+    pub(crate) fn due_ratchet(&mut self, pos: Position) -> Option<(u32, u8)> {
+        let ratchets = self.ratchets.as_mut()?;
+        let due = ratchets.start + ratchets.next as i64 * 1000 / ratchets.count as i64;
+        if (pos.thousandths() as i64) < due {
+            return None;
+        }
+
+        let n = ratchets.next;
+        ratchets.next += 1;
+        if ratchets.next >= ratchets.count {
+            self.ratchets = None;
+        }
+        Some((self.last_step?, n))
+    }
+}
@@ -0,0 +1,174 @@
+use heapless::Vec;
+use voice_lib::{NoteFlag, NotePair};
+
+use super::tracks::MAX_TRACK_LENGTH;
+
+// times are kept in thousandths of a step
+const STEP: u32 = 1000;
+
+const MAX_TAKE_EVENTS: usize = 128;
+/// Each note of a take starts with a key press
+pub(crate) const MAX_TAKE_NOTES: usize = MAX_TAKE_EVENTS;
+const MAX_HELD_KEYS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Quantize {
+    /// How far (%) notes are moved towards the grid
+    pub(crate) strength: u8,
+    /// How early (% of a step) a note may be played and still land on the next step
+    pub(crate) window: u8,
+}
+
+impl Default for Quantize {
+    fn default() -> Self {
+        Self {
+            strength: 100,
+            window: 25,
+        }
+    }
+}
+
+impl Quantize {
+    /// Step a note played at `time` should be written to
+    pub(crate) fn step(&self, time: u32) -> usize {
+        let beat = time / STEP;
+        let to_next = STEP - time % STEP;
+
+        let grid = if to_next <= self.window as u32 * STEP / 100 {
+            beat + 1
+        } else {
+            beat
+        } * STEP;
+
+        // partway to the grid line, then to whichever step is the closest
+        let moved = time as i64 + (grid as i64 - time as i64) * self.strength as i64 / 100;
+        ((moved as u32 + STEP / 2) / STEP) as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum TakeEventKind {
+    Press(NotePair),
+    Release(NotePair),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TakeEvent {
+    pub(crate) time: u32,
+    pub(crate) kind: TakeEventKind,
+}
+
+/// Unquantized key events of the last recording pass, so that it
+/// can be quantized again later on.
+#[derive(Default)]
+pub(crate) struct Take {
+    pub(crate) events: Vec<TakeEvent, MAX_TAKE_EVENTS>,
+    /// When the pass ended
+    pub(crate) end: u32,
+    /// The steps the take was written to, with what they held before
+    pub(crate) overwritten: Vec<(usize, (Option<NotePair>, NoteFlag)), MAX_TRACK_LENGTH>,
+}
+
+impl Take {
+    pub(crate) fn clear(&mut self) {
+        self.events.clear();
+        self.end = 0;
+        self.overwritten.clear();
+    }
+
+    pub(crate) fn push(&mut self, time: u32, kind: TakeEventKind) {
+        // once full, the rest of the take just won't be re-quantizable
+        self.events.push(TakeEvent { time, kind }).ok();
+        self.end = self.end.max(time);
+    }
+
+    /// The take writes over `step`, which holds `before`
+    pub(crate) fn overwrite(&mut self, step: usize, before: (Option<NotePair>, NoteFlag)) {
+        if self.overwritten.iter().all(|(s, _)| *s != step) {
+            self.overwritten.push((step, before)).ok();
+        }
+    }
+
+    /// Notes of the take, quantized with `quantize`, as (first step, end step, note)
+    pub(crate) fn notes<'a>(
+        &'a self,
+        quantize: &'a Quantize,
+    ) -> impl Iterator<Item = (usize, usize, NotePair)> + 'a {
+        let mut held: Vec<NotePair, MAX_HELD_KEYS> = Vec::new();
+        let mut segment: Option<(usize, NotePair)> = None;
+
+        self.events
+            .iter()
+            .map(Some)
+            // `None` closes whatever is still being held
+            .chain(core::iter::once(None))
+            .filter_map(move |event| {
+                let (step, sounding) = match event {
+                    Some(TakeEvent { time, kind }) => {
+                        match kind {
+                            TakeEventKind::Press(n) => {
+                                held.push(*n).ok();
+                            }
+                            TakeEventKind::Release(n) => {
+                                held = held.iter().filter(|e| *e != n).cloned().collect();
+                            }
+                        }
+                        (quantize.step(*time), held.last().copied())
+                    }
+                    None => (quantize.step(self.end), None),
+                };
+
+                if segment.map(|(_, n)| n) == sounding {
+                    return None;
+                }
+
+                let finished = segment.map(|(start, n)| (start, step.max(start + 1), n));
+                segment = sounding.map(|n| (step, n));
+                finished
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Quantize;
+
+    fn quantize(strength: u8) -> Quantize {
+        Quantize {
+            strength,
+            window: 25,
+        }
+    }
+
+    #[test]
+    fn test_step_full_strength() {
+        // early, within the window: on the next step
+        assert!(quantize(100).step(1_800) == 2);
+        // early, but too much so
+        assert!(quantize(100).step(1_700) == 1);
+        // late
+        assert!(quantize(100).step(2_300) == 2);
+        assert!(quantize(100).step(2_600) == 2);
+    }
+
+    #[test]
+    fn test_step_half_strength() {
+        // 1_900 ends up at 1_950, past the middle of step 1
+        assert!(quantize(50).step(1_900) == 2);
+        // 2_400 ends up at 2_200
+        assert!(quantize(50).step(2_400) == 2);
+        // 2_800 is within the window of step 3, and ends up at 2_900
+        assert!(quantize(50).step(2_800) == 3);
+        // 2_700 is late for step 2, and ends up at 2_350 instead of the closer step 3
+        assert!(quantize(50).step(2_700) == 2);
+    }
+
+    #[test]
+    fn test_step_no_strength() {
+        // notes stay where they are played, on the closest step
+        assert!(quantize(0).step(1_900) == 2);
+        assert!(quantize(0).step(1_400) == 1);
+        assert!(quantize(0).step(2_300) == 2);
+        assert!(quantize(0).step(2_700) == 3);
+    }
+}
@@ -1,22 +1,102 @@
-use core::marker::PhantomData;
-use alloc::boxed::Box;
+use core::{marker::PhantomData, mem, ops::Range};
 use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
 use ufmt::uwrite;
-use voice_lib::{NoteFlag, NotePair, VoiceTrack};
+use voice_lib::{Key, NoteFlag, NotePair, VoiceTrack};
 
-use crate::{log, util::DiscreetUnwrap, stdlib::{StdlibError, TaskType}};
+use crate::{log, util::DiscreetUnwrap, stdlib::{to_bytes, StdlibError, TaskType}};
 
-use super::data::SequenceFile;
+use super::{
+    data::{FileFormat, SequenceFile},
+    history::History,
+    quantize::{Quantize, Take, TakeEventKind, MAX_TAKE_NOTES},
+    song::Patterns,
+    tracks::Tracks,
+    transport::Bpm,
+};
 
 
 const NUM_VOICES: usize = 2;
-const DEFAULT_SIZE: usize = 16;
+pub(super) const DEFAULT_SIZE: usize = 16;
+
+/// Keep notes in a key, when recording and/or when playing back
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ScaleLock {
+    pub(crate) key: Key,
+    pub(crate) record: bool,
+    pub(crate) playback: bool,
+}
+
+impl ScaleLock {
+    pub(crate) fn on_record(&self, n: NotePair) -> NotePair {
+        if self.record {
+            self.key.quantize(&n)
+        } else {
+            n
+        }
+    }
+
+    pub(crate) fn on_playback(&self, n: NotePair) -> NotePair {
+        if self.playback {
+            self.key.quantize(&n)
+        } else {
+            n
+        }
+    }
+}
+
+/// What recording does with what's already on the track
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum RecordMode {
+    /// New notes are merged with the existing ones
+    Overdub,
+    /// Steps are cleared as the playhead passes them, unless something is played
+    Replace,
+    /// Each key press writes to the cursor step and moves on to the next one,
+    /// whether the transport runs or not
+    Step,
+}
+
+impl Default for RecordMode {
+    fn default() -> Self {
+        RecordMode::Overdub
+    }
+}
+
+/// Steps of the track (both included) recording is limited to.
+/// Wraps around the end of the track if `end` comes before `start`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Punch {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+}
+
+impl Punch {
+    pub(crate) fn contains(&self, step: usize) -> bool {
+        if self.start <= self.end {
+            (self.start..=self.end).contains(&step)
+        } else {
+            step >= self.start || step <= self.end
+        }
+    }
+}
 
 pub(crate) struct MonoRecorderBox<'t> {
     file_name: String<8>,
     pub voice_state: VoiceTrack,
+    pub(crate) history: History,
+    pub(crate) quantize: Quantize,
+    pub(crate) mode: RecordMode,
+    /// Where the next step-recorded note goes
+    pub(crate) cursor: usize,
+    pub(crate) punch: Option<Punch>,
+    take: Take,
+    // a recording pass is ongoing
+    in_pass: bool,
     current_note: Vec<NotePair, NUM_VOICES>,
     keys_changed: bool,
+    // a note was played early and already written to the next step
+    early_step: Option<usize>,
     _t: &'t PhantomData<()>,
 }
 
@@ -25,8 +105,16 @@ impl<'t> MonoRecorderBox<'t> {
         Self {
             file_name: "unnamed".into(),
             voice_state: VoiceTrack::new(DEFAULT_SIZE),
+            history: History::default(),
+            quantize: Quantize::default(),
+            mode: RecordMode::default(),
+            cursor: 0,
+            punch: None,
+            take: Take::default(),
+            in_pass: false,
             current_note: Vec::new(),
             keys_changed: false,
+            early_step: None,
             _t: &PhantomData,
         }
     }
@@ -35,16 +123,40 @@ impl<'t> MonoRecorderBox<'t> {
         self.current_note.last()
     }
 
-    pub(crate) fn key_pressed(&mut self, beat: usize, n: NotePair) {
+    pub(crate) fn num_keys_held(&self) -> usize {
+        self.current_note.len()
+    }
+
+    /// `time` is in thousandths of a step
+    pub(crate) fn key_pressed(&mut self, time: u32, n: NotePair) {
+        let beat = (time / 1000) as usize;
+        let step = self.quantize.step(time);
+
         self.current_note.push(n).unwrap();
-        self.voice_state.set_note(beat, (Some(n), NoteFlag::Note)).duwrp();
         self.keys_changed = true;
+        // outside of the punch region, keys are only played
+        if !self.punched_in(step) {
+            return;
+        }
+        if self.in_pass {
+            self.take.push(time, TakeEventKind::Press(n));
+        }
+        self.record_note(step, (Some(n), NoteFlag::Note));
+        self.early_step = (step > beat).then_some(step);
+
         let mut text = String::<32>::new();
-        uwrite!(text, "KEY PRESS {}: {:?}", beat, n).unwrap();
+        uwrite!(text, "KEY PRESS {}: {:?}", step, n).unwrap();
         log::debug(&text);
     }
 
-    pub(crate) fn key_released(&mut self, _beat: usize, n: NotePair) {
+    /// Step-record `n` on step `step`
+    pub(crate) fn step_pressed(&mut self, step: usize, n: NotePair) {
+        self.current_note.push(n).unwrap();
+        self.set_note(step, (Some(n), NoteFlag::Note));
+        self.keys_changed = true;
+    }
+
+    pub(crate) fn key_released(&mut self, time: u32, n: NotePair) {
         self.current_note = self
             .current_note
             .iter()
@@ -52,36 +164,334 @@ impl<'t> MonoRecorderBox<'t> {
             .cloned()
             .collect();
         self.keys_changed = true;
+
+        // step-recorded notes aren't timed, there's nothing to re-quantize.
+        // Neither are keys pressed before the punch-in.
+        if self.in_pass && self.mode != RecordMode::Step && !self.take.events.is_empty() {
+            self.take.push(time, TakeEventKind::Release(n));
+        }
     }
 
     pub(crate) fn beat(&mut self, beat: usize) {
-        if !self.keys_changed && let Some(n) = self.current_note.last() {
-            self.voice_state.set_note(beat, (Some(*n), NoteFlag::Legato)).duwrp();
+        // step-recording doesn't follow the transport
+        if self.mode == RecordMode::Step {
+            return;
+        }
+
+        if !self.keys_changed && self.punched_in(beat) {
+            if let Some(n) = self.current_note.last() {
+                let n = *n;
+                self.record_note(beat, (Some(n), NoteFlag::Legato));
+            }
+        }
+
+        // the next step already holds a note which was played early
+        let early = self.early_step.take() == Some(beat + 1);
+
+        if !early && self.punched_in(beat + 1) {
+            match self.current_note.last() {
+                // initialize already next note if there is at least a pressed one.
+                // A note held since before the punch-in starts there.
+                Some(n) => {
+                    let n = *n;
+                    let flag = if self.punched_in(beat) {
+                        NoteFlag::Legato
+                    } else {
+                        NoteFlag::Note
+                    };
+                    self.record_note(beat + 1, (Some(n), flag));
+                }
+                // whatever was there before is replaced by silence
+                None if self.mode == RecordMode::Replace => {
+                    self.set_note(beat + 1, (None, NoteFlag::None));
+                }
+                None => {}
+            }
+        }
+        self.keys_changed = early;
+
+        if self.in_pass {
+            self.take.end = (beat as u32 + 1) * 1000;
+        }
+    }
+
+    /// Whether recording takes effect on `step`: anywhere, unless a recording pass is
+    /// limited to the punch region
+    pub(crate) fn punched_in(&self, step: usize) -> bool {
+        match self.punch {
+            Some(punch) if self.in_pass => punch.contains(step % self.voice_state.len().max(1)),
+            _ => true,
+        }
+    }
+
+    /// Start a recording pass, which can be undone (and re-quantized) as a whole
+    pub(crate) fn start_pass(&mut self) {
+        self.history.begin();
+        self.take.clear();
+        self.in_pass = true;
+    }
+
+    pub(crate) fn end_pass(&mut self) {
+        self.history.commit();
+        self.in_pass = false;
+    }
+
+    /// Quantize the last recording pass again, from the original timing
+    pub(crate) fn requantize(&mut self) {
+        let len = self.voice_state.len();
+        if self.take.events.is_empty() || len == 0 {
+            return;
+        }
+        // the punch region limits the re-quantized take just like the recorded one
+        let punch = self.punch;
+        let punched_in = |beat: usize| punch.is_none_or(|p| p.contains(beat % len));
+        let notes: Vec<_, MAX_TAKE_NOTES> = self.take.notes(&self.quantize).collect();
+
+        self.history.begin();
+        // only the steps the take was written to change, anything else recorded over stays
+        for (step, before) in mem::take(&mut self.take.overwritten) {
+            self.set_note(step, before);
+        }
+        // the take may have gone on over several loops of the track
+        for (start, end, n) in notes {
+            for beat in (start..end).filter(|beat| punched_in(*beat)) {
+                let flag = if beat == start || !punched_in(beat - 1) {
+                    NoteFlag::Note
+                } else {
+                    NoteFlag::Legato
+                };
+                self.take_note(beat, (Some(n), flag));
+            }
         }
+        self.history.commit();
+    }
+
+    /// Record a note on `beat`. Within a take, what the step held before is kept,
+    /// so that re-quantizing can put it back.
+    fn record_note(&mut self, beat: usize, state: (Option<NotePair>, NoteFlag)) {
+        if self.in_pass && !self.take.events.is_empty() {
+            self.take_note(beat, state);
+        } else {
+            self.set_note(beat, state);
+        }
+    }
+
+    /// Write a note of the take on `beat`, keeping what the step held before
+    fn take_note(&mut self, beat: usize, state: (Option<NotePair>, NoteFlag)) {
+        let len = self.voice_state.len();
+        if len == 0 {
+            return;
+        }
+        if let Some(before) = self.voice_state.get_note(beat % len) {
+            self.take.overwrite(beat % len, before);
+        }
+        self.set_note(beat, state);
+    }
+
+    /// Change a step, keeping it in the undo history. Beats past the end of the track wrap around.
+    pub(crate) fn set_note(&mut self, beat: usize, state: (Option<NotePair>, NoteFlag)) {
+        let len = self.voice_state.len();
+        if len > 0 {
+            self.history.set_note(&mut self.voice_state, beat % len, state);
+        }
+    }
+
+    /// Change anything else about a step (condition, ratchets, ...) with `f`, keeping it in the
+    /// undo history. Beats past the end of the track wrap around.
+    pub(crate) fn edit_step(&mut self, beat: usize, f: impl FnOnce(&mut VoiceTrack, usize)) {
+        let len = self.voice_state.len();
+        if len > 0 {
+            let step = beat % len;
+            self.history.edit(&mut self.voice_state, step, |track| f(track, step));
+        }
+    }
 
-        // initialize already next note if there is at least a pressed one
-        if let Some(n) = self.current_note.last() {
-            self.voice_state
-                .set_note(beat + 1, (Some(*n), NoteFlag::Legato)).duwrp();
+    /// Remove all notes in `range`, as a single operation
+    pub(crate) fn clear_range(&mut self, range: Range<usize>) {
+        self.history.begin();
+        for beat in range {
+            self.set_note(beat, (None, NoteFlag::None));
         }
-        self.keys_changed = false;
+        self.history.commit();
+    }
+
+    /// Transform the whole track, as a single operation
+    pub(crate) fn transform<R>(&mut self, f: impl FnOnce(&mut VoiceTrack) -> R) -> R {
+        self.history.transform(&mut self.voice_state, f)
+    }
+
+    pub(crate) fn undo(&mut self) -> bool {
+        self.history.undo(&mut self.voice_state)
+    }
+
+    pub(crate) fn redo(&mut self) -> bool {
+        self.history.redo(&mut self.voice_state)
+    }
+
+    /// Forget the undo history, e.g. to make room for a project being loaded
+    pub(crate) fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Replace the whole track (e.g. with an imported one). This can't be undone.
+    pub(crate) fn replace_track(&mut self, track: VoiceTrack) {
+        self.voice_state = track;
+        self.history.clear();
+        self.take.clear();
+        self.clamp_punch();
+    }
+
+    /// Change the length of the track. The undo history (and the take) only cover a fixed length,
+    /// so they start over.
+    pub(crate) fn resize(&mut self, len: usize) {
+        self.voice_state.resize(len);
+        self.history.clear();
+        self.take.clear();
+        self.clamp_punch();
     }
 
-    pub(crate) fn iter_notes_since(
-        &'t self,
-        t: usize,
-        num: usize,
-    ) -> impl Iterator<Item = (usize, Option<(Option<NotePair>, NoteFlag)>)> + 't {
-        self.voice_state.since(t, num)
+    /// Keep the punch points on steps of the track, as its length changes
+    fn clamp_punch(&mut self) {
+        let last = self.voice_state.len().saturating_sub(1);
+        if let Some(punch) = self.punch.as_mut() {
+            punch.start = punch.start.min(last);
+            punch.end = punch.end.min(last);
+        }
     }
 
     pub(crate) fn set_file_name(&mut self, file_name: &String<8>) {
         self.file_name = file_name.clone();
     }
 
-    pub(crate) fn save_file(&mut self) -> Result<TaskType, StdlibError> {
-        let mut file_name: String<12> = String::from(&self.file_name as &str);
-        file_name.push_str(".seq").duwrp();
-        Ok(TaskType::FileSave("data".into(), file_name, Box::new(SequenceFile::new(&self.file_name))))
+    fn seq_file_name(file_name: &str) -> String<12> {
+        let mut seq_file_name: String<12> = String::from(file_name);
+        seq_file_name.push_str(".seq").duwrp();
+        seq_file_name
+    }
+
+    /// Save the project: the track, along with the other tracks and patterns, and the song.
+    /// Projects too large to be saved at once (`MAX_FILE_BYTES`) are refused.
+    pub(crate) fn save_file(
+        &mut self,
+        patterns: &Patterns,
+        tracks: &Tracks,
+        bpm: Bpm,
+    ) -> Result<TaskType, StdlibError> {
+        let file = SequenceFile::new(
+            &self.file_name,
+            bpm,
+            patterns.all(tracks.all(&self.voice_state)),
+            patterns.current,
+            patterns.song.clone(),
+            tracks.settings,
+            self.mode,
+            self.punch,
+        );
+        Ok(TaskType::FileSaveBytes(
+            "data".into(),
+            Self::seq_file_name(&self.file_name),
+            to_bytes(&file)?,
+        ))
+    }
+
+    pub(crate) fn load_file(&mut self, file_name: &String<8>) -> TaskType {
+        self.set_file_name(file_name);
+        TaskType::FileLoadBytes("data".into(), Self::seq_file_name(file_name))
+    }
+
+    pub(crate) fn export(&self, file_name: &str, format: FileFormat, bpm: Bpm) -> Result<TaskType, StdlibError> {
+        format.save(file_name, &self.voice_state, bpm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use voice_lib::{Note, NoteFlag, NotePair, VoiceTrack};
+
+    use super::{MonoRecorderBox, Punch};
+
+    const A: NotePair = NotePair(Note::A, 3);
+    const C: NotePair = NotePair(Note::C, 4);
+
+    /// Record a pass with `n` held from `press` to `release`, until `end` (in thousandths of a step)
+    fn record(recorder: &mut MonoRecorderBox, n: NotePair, press: u32, release: u32, end: u32) {
+        recorder.start_pass();
+        recorder.key_pressed(press, n);
+        for beat in press / 1000..end / 1000 {
+            if beat == release / 1000 {
+                recorder.key_released(release, n);
+            }
+            recorder.beat(beat as usize);
+        }
+        recorder.end_pass();
+    }
+
+    #[test]
+    fn test_requantize_later_loop() {
+        let mut recorder = MonoRecorderBox::new();
+        recorder.quantize.strength = 0;
+        // the second time around the 16 steps
+        record(&mut recorder, A, 17_600, 18_300, 20_000);
+        assert!(recorder.voice_state.get_note(1) == Some((None, NoteFlag::None)));
+        assert!(recorder.voice_state.get_note(2) == Some((Some(A), NoteFlag::Note)));
+
+        recorder.quantize.strength = 100;
+        recorder.requantize();
+        assert!(recorder.voice_state.get_note(1) == Some((Some(A), NoteFlag::Note)));
+        assert!(recorder.voice_state.get_note(2) == Some((None, NoteFlag::None)));
+    }
+
+    #[test]
+    fn test_requantize_overdub() {
+        let mut recorder = MonoRecorderBox::new();
+        recorder.set_note(2, (Some(C), NoteFlag::Note));
+        recorder.set_note(5, (Some(C), NoteFlag::Note));
+        recorder.quantize.strength = 0;
+        record(&mut recorder, A, 1_600, 2_300, 7_000);
+        assert!(recorder.voice_state.get_note(2) == Some((Some(A), NoteFlag::Note)));
+
+        recorder.quantize.strength = 100;
+        recorder.requantize();
+        assert!(recorder.voice_state.get_note(1) == Some((Some(A), NoteFlag::Note)));
+        // what the take was recorded over is back, the rest wasn't touched
+        assert!(recorder.voice_state.get_note(2) == Some((Some(C), NoteFlag::Note)));
+        assert!(recorder.voice_state.get_note(5) == Some((Some(C), NoteFlag::Note)));
+
+        // and it is still there when re-quantizing again
+        recorder.quantize.strength = 0;
+        recorder.requantize();
+        assert!(recorder.voice_state.get_note(1) == Some((None, NoteFlag::None)));
+        assert!(recorder.voice_state.get_note(2) == Some((Some(A), NoteFlag::Note)));
+        assert!(recorder.voice_state.get_note(5) == Some((Some(C), NoteFlag::Note)));
+    }
+
+    #[test]
+    fn test_requantize_punch() {
+        let mut recorder = MonoRecorderBox::new();
+        recorder.set_note(3, (Some(C), NoteFlag::Note));
+        recorder.punch = Some(Punch { start: 1, end: 2 });
+        recorder.quantize.strength = 0;
+        // the second time around, held past the punch-out
+        record(&mut recorder, A, 17_600, 20_300, 21_000);
+        assert!(recorder.voice_state.get_note(1) == Some((None, NoteFlag::None)));
+        assert!(recorder.voice_state.get_note(2) == Some((Some(A), NoteFlag::Note)));
+        assert!(recorder.voice_state.get_note(3) == Some((Some(C), NoteFlag::Note)));
+
+        recorder.quantize.strength = 100;
+        recorder.requantize();
+        assert!(recorder.voice_state.get_note(1) == Some((Some(A), NoteFlag::Note)));
+        assert!(recorder.voice_state.get_note(2) == Some((Some(A), NoteFlag::Legato)));
+        assert!(recorder.voice_state.get_note(3) == Some((Some(C), NoteFlag::Note)));
+    }
+
+    #[test]
+    fn test_punch_clamped() {
+        let mut recorder = MonoRecorderBox::new();
+        recorder.resize(16);
+        recorder.punch = Some(Punch { start: 12, end: 4 });
+        recorder.resize(8);
+        assert!(recorder.punch == Some(Punch { start: 7, end: 4 }));
+        recorder.replace_track(VoiceTrack::new(4));
+        assert!(recorder.punch == Some(Punch { start: 3, end: 3 }));
     }
 }
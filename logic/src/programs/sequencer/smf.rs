@@ -0,0 +1,611 @@
+//! Standard MIDI File (SMF) import/export.
+//! One step of a `VoiceTrack` is one quarter note.
+//!
+//! Files are written and read a part at a time (see `FileWriter`/`FileReader`),
+//! so that they never are in memory as a whole.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::{any::Any, mem};
+use voice_lib::{NoteFlag, NotePair, VoiceTrack};
+
+use super::{tracks::MAX_TRACK_LENGTH, transport::Bpm};
+use crate::stdlib::{FileReader, FileWriter, StdlibError};
+
+/// Ticks per quarter note (= per step) in exported files
+const PPQ: u16 = 96;
+const CHANNEL: u8 = 0;
+const VELOCITY: u8 = 100;
+const MIN_TRACK_LEN: usize = 16;
+/// "MThd", its length, format, number of tracks and division
+const FILE_HEADER_LEN: usize = 14;
+/// Kind and length of a chunk
+const CHUNK_HEADER_LEN: usize = 8;
+/// Payload of a tempo meta event
+const TEMPO_LEN: usize = 3;
+/// Most notes kept when reading a file (a track holds one per step at most)
+const MAX_NOTES: usize = 4 * MAX_TRACK_LENGTH;
+
+fn write_vlq(out: &mut Vec<u8>, mut value: u32) {
+    let mut buf = [0u8; 4];
+    let mut n = 0;
+    loop {
+        buf[n] = (value & 0x7f) as u8;
+        value >>= 7;
+        n += 1;
+        if value == 0 {
+            break;
+        }
+    }
+    for i in (0..n).rev() {
+        out.push(buf[i] | if i > 0 { 0x80 } else { 0 });
+    }
+}
+
+#[derive(Debug)]
+struct TrackWriter {
+    data: Vec<u8>,
+    last_tick: u32,
+}
+
+impl TrackWriter {
+    fn event(&mut self, tick: u32, bytes: &[u8]) {
+        write_vlq(&mut self.data, tick - self.last_tick);
+        self.data.extend_from_slice(bytes);
+        self.last_tick = tick;
+    }
+
+    fn note_on(&mut self, tick: u32, key: u8) {
+        self.event(tick, &[0x90 | CHANNEL, key, VELOCITY]);
+    }
+
+    fn note_off(&mut self, tick: u32, key: u8) {
+        self.event(tick, &[0x80 | CHANNEL, key, 0x40]);
+    }
+}
+
+/// Writes a track as a type 0 file, a step at a time
+#[derive(Debug)]
+pub(crate) struct SmfWriter {
+    /// Flag and MIDI key of each step of the track
+    steps: Vec<(NoteFlag, Option<u8>)>,
+    tempo: u32,
+    /// Events of the steps written so far, which haven't been written out yet
+    events: TrackWriter,
+    // bytes of `events` already written out
+    written: usize,
+    /// Next step to write: past the last one for the end of the track, and then done
+    step: usize,
+    sounding: Option<u8>,
+}
+
+impl SmfWriter {
+    pub(crate) fn new(track: &VoiceTrack, bpm: Bpm) -> Result<Self, StdlibError> {
+        let steps = (0..track.len())
+            .map(|step| {
+                let (note, flag) = track.get_note(step).ok_or(StdlibError::Serialization)?;
+                let key = match note {
+                    Some(np) => Some(u8::try_from(&np).map_err(|_| StdlibError::Serialization)?),
+                    None => None,
+                };
+                Ok((flag, key))
+            })
+            .collect::<Result<Vec<_>, StdlibError>>()?;
+
+        let mut writer = Self {
+            steps,
+            tempo: 600_000_000 / bpm.tenths(),
+            events: TrackWriter {
+                data: Vec::new(),
+                last_tick: 0,
+            },
+            written: 0,
+            step: 0,
+            sounding: None,
+        };
+
+        // the length of the track chunk comes first, go through it once to get it
+        let mut len = 0;
+        writer.start_track();
+        loop {
+            len += writer.events.data.len();
+            writer.events.data.clear();
+            if !writer.next_step() {
+                break;
+            }
+        }
+        writer.step = 0;
+        writer.events.last_tick = 0;
+
+        let header = &mut writer.events.data;
+        header.extend_from_slice(b"MThd");
+        header.extend_from_slice(&6u32.to_be_bytes());
+        // format 0, 1 track
+        header.extend_from_slice(&0u16.to_be_bytes());
+        header.extend_from_slice(&1u16.to_be_bytes());
+        header.extend_from_slice(&PPQ.to_be_bytes());
+        header.extend_from_slice(b"MTrk");
+        header.extend_from_slice(&(len as u32).to_be_bytes());
+        writer.start_track();
+        Ok(writer)
+    }
+
+    fn start_track(&mut self) {
+        let tempo = self.tempo;
+        self.events.event(
+            0,
+            &[0xff, 0x51, 0x03, (tempo >> 16) as u8, (tempo >> 8) as u8, tempo as u8],
+        );
+    }
+
+    /// Add the events of the next step to `events`. Returns `false` once the track is over.
+    fn next_step(&mut self) -> bool {
+        let len = self.steps.len();
+        if self.step > len {
+            return false;
+        }
+
+        let step = self.step;
+        self.step += 1;
+        let tick = step as u32 * PPQ as u32;
+        if step == len {
+            if let Some(old) = self.sounding.take() {
+                self.events.note_off(tick, old);
+            }
+            // end of track
+            self.events.event(tick, &[0xff, 0x2f, 0x00]);
+            return true;
+        }
+
+        match self.steps[step] {
+            // tied to the same note, keep it going
+            (NoteFlag::Legato, Some(key)) if self.sounding == Some(key) => {}
+            (flag, key) => {
+                if let Some(old) = self.sounding.take() {
+                    self.events.note_off(tick, old);
+                }
+                if let (NoteFlag::Note | NoteFlag::Legato, Some(key)) = (flag, key) {
+                    self.events.note_on(tick, key);
+                    self.sounding = Some(key);
+                }
+            }
+        }
+        true
+    }
+}
+
+impl FileWriter for SmfWriter {
+    fn write_part(&mut self, buf: &mut [u8]) -> Result<usize, StdlibError> {
+        let mut n = 0;
+        while n < buf.len() {
+            if self.written == self.events.data.len() {
+                self.events.data.clear();
+                self.written = 0;
+                if !self.next_step() {
+                    break;
+                }
+            }
+            let pending = &self.events.data[self.written..];
+            let size = pending.len().min(buf.len() - n);
+            buf[n..n + size].copy_from_slice(&pending[..size]);
+            self.written += size;
+            n += size;
+        }
+        Ok(n)
+    }
+}
+
+/// A variable-length quantity, as it is read
+#[derive(Clone, Copy, Debug, Default)]
+struct Vlq {
+    value: u32,
+    len: u8,
+}
+
+impl Vlq {
+    /// Take in the next byte, returning the value once complete
+    fn push(&mut self, b: u8) -> Result<Option<u32>, StdlibError> {
+        self.value = (self.value << 7) | (b & 0x7f) as u32;
+        self.len += 1;
+        if b & 0x80 == 0 {
+            Ok(Some(self.value))
+        } else if self.len == 4 {
+            Err(StdlibError::Deserialization)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Where the reader is in the file
+#[derive(Clone, Copy, Debug)]
+enum ReadState {
+    /// The header chunk, up to the division (the rest of it is skipped)
+    FileHeader,
+    /// Kind and length of the next chunk
+    ChunkHeader,
+    /// The rest of a chunk which isn't read
+    Skip,
+    /// Delta time of the next event of a track
+    Delta(Vlq),
+    /// First byte of an event: its status, or its first data byte (running status)
+    Status,
+    /// First data byte of a channel message
+    FirstData,
+    /// Second data byte of a channel message, after the first one
+    SecondData(u8),
+    MetaKind,
+    MetaLength(u8, Vlq),
+    /// Payload of a meta event (kind, bytes left), which is only kept for the tempo
+    MetaData(u8, u32),
+    /// Length of a SysEx event, whose payload is skipped like a meta event's
+    SysExLength(Vlq),
+}
+
+/// A note, as (start tick, end tick, MIDI key)
+type SmfNote = (u32, u32, u8);
+
+/// Reads a type 0/1 file, a part at a time. Notes of all tracks are merged into
+/// a single (monophonic) `VoiceTrack`.
+#[derive(Debug)]
+pub(crate) struct SmfReader {
+    state: ReadState,
+    /// Bytes of the header being read
+    header: heapless::Vec<u8, FILE_HEADER_LEN>,
+    /// Bytes of the tempo being read
+    meta: heapless::Vec<u8, TEMPO_LEN>,
+    division: u32,
+    /// Bytes left in the current chunk
+    left: u32,
+    // running status of the track being read
+    status: u8,
+    tick: u32,
+    /// Notes which are on, as (start tick, key)
+    on: Vec<(u32, u8)>,
+    notes: Vec<SmfNote>,
+    tempo: Option<u32>,
+}
+
+impl Default for SmfReader {
+    fn default() -> Self {
+        Self {
+            state: ReadState::FileHeader,
+            header: heapless::Vec::new(),
+            meta: heapless::Vec::new(),
+            division: 0,
+            left: 0,
+            status: 0,
+            tick: 0,
+            on: Vec::new(),
+            notes: Vec::new(),
+            tempo: None,
+        }
+    }
+}
+
+impl SmfReader {
+    /// The step `tick` is the closest to
+    fn to_step(&self, tick: u32) -> usize {
+        (tick.saturating_add(self.division / 2) / self.division) as usize
+    }
+
+    /// The rest of the chunk, if any, is skipped
+    fn end_chunk(&self) -> ReadState {
+        if self.left > 0 {
+            ReadState::Skip
+        } else {
+            ReadState::ChunkHeader
+        }
+    }
+
+    fn file_header(&mut self) -> Result<ReadState, StdlibError> {
+        let h = &self.header;
+        let len = u32::from_be_bytes([h[4], h[5], h[6], h[7]]);
+        let format = u16::from_be_bytes([h[8], h[9]]);
+        let division = u16::from_be_bytes([h[12], h[13]]);
+        // SMPTE timing isn't supported
+        if &h[..4] != b"MThd" || len < 6 || format > 1 || division & 0x8000 != 0 || division == 0 {
+            return Err(StdlibError::Deserialization);
+        }
+        self.division = division as u32;
+        self.left = len - 6;
+        Ok(self.end_chunk())
+    }
+
+    fn chunk_header(&mut self) -> ReadState {
+        let h = &self.header;
+        self.left = u32::from_be_bytes([h[4], h[5], h[6], h[7]]);
+        // unknown chunks are to be skipped
+        if &h[..4] == b"MTrk" && self.left > 0 {
+            self.status = 0;
+            self.tick = 0;
+            ReadState::Delta(Vlq::default())
+        } else {
+            self.end_chunk()
+        }
+    }
+
+    fn end_track(&mut self) -> ReadState {
+        // notes which are never released last until the end of the track
+        for (start, key) in mem::take(&mut self.on) {
+            self.notes.push((start, self.tick.max(start.saturating_add(1)), key));
+        }
+        self.end_chunk()
+    }
+
+    fn note_on(&mut self, key: u8) {
+        // notes past the longest track there can be are dropped right away,
+        // as are the ones of files too dense to make sense of
+        if self.to_step(self.tick) < MAX_TRACK_LENGTH && self.on.len() + self.notes.len() < MAX_NOTES {
+            self.on.push((self.tick, key));
+        }
+    }
+
+    fn channel_message(&mut self, first: u8, second: u8) -> ReadState {
+        match self.status & 0xf0 {
+            0x90 if second > 0 => self.note_on(first),
+            0x80 | 0x90 => {
+                if let Some(n) = self.on.iter().position(|(_, key)| *key == first) {
+                    let (start, key) = self.on.remove(n);
+                    self.notes.push((start, self.tick, key));
+                }
+            }
+            _ => {}
+        }
+        ReadState::Delta(Vlq::default())
+    }
+
+    fn first_data(&mut self, b: u8) -> ReadState {
+        match self.status & 0xf0 {
+            0xc0 | 0xd0 => self.channel_message(b, 0),
+            _ => ReadState::SecondData(b),
+        }
+    }
+
+    fn meta_data(&mut self, kind: u8, left: u32) -> ReadState {
+        if left > 0 {
+            return ReadState::MetaData(kind, left);
+        }
+        // meta/sysex events cancel running status
+        self.status = 0;
+        match kind {
+            0x51 if self.meta.len() == TEMPO_LEN => {
+                let t = &self.meta;
+                self.tempo = Some(((t[0] as u32) << 16) | ((t[1] as u32) << 8) | t[2] as u32);
+                ReadState::Delta(Vlq::default())
+            }
+            // end of track
+            0x2f => self.end_track(),
+            _ => ReadState::Delta(Vlq::default()),
+        }
+    }
+
+    /// Take in the next byte of a track
+    fn track_byte(&mut self, b: u8) -> Result<ReadState, StdlibError> {
+        Ok(match self.state {
+            ReadState::Delta(mut delta) => match delta.push(b)? {
+                Some(ticks) => {
+                    self.tick = self.tick.saturating_add(ticks);
+                    ReadState::Status
+                }
+                None => ReadState::Delta(delta),
+            },
+            ReadState::Status if b & 0x80 != 0 => {
+                self.status = b;
+                match b {
+                    0xff => ReadState::MetaKind,
+                    0xf0 | 0xf7 => ReadState::SysExLength(Vlq::default()),
+                    // system common/real-time messages don't belong in files
+                    0xf1..=0xfe => return Err(StdlibError::Deserialization),
+                    _ => ReadState::FirstData,
+                }
+            }
+            ReadState::Status if self.status == 0 => return Err(StdlibError::Deserialization),
+            ReadState::Status | ReadState::FirstData => self.first_data(b),
+            ReadState::SecondData(first) => self.channel_message(first, b),
+            ReadState::MetaKind => ReadState::MetaLength(b, Vlq::default()),
+            ReadState::MetaLength(kind, mut len) => match len.push(b)? {
+                Some(len) => {
+                    self.meta.clear();
+                    // only the first tempo counts
+                    let kind = match kind {
+                        0x51 if len as usize != TEMPO_LEN || self.tempo.is_some() => 0,
+                        kind => kind,
+                    };
+                    self.meta_data(kind, len)
+                }
+                None => ReadState::MetaLength(kind, len),
+            },
+            ReadState::MetaData(kind, left) => {
+                if kind == 0x51 {
+                    self.meta.push(b).ok();
+                }
+                self.meta_data(kind, left - 1)
+            }
+            ReadState::SysExLength(mut len) => match len.push(b)? {
+                Some(len) => self.meta_data(0, len),
+                None => ReadState::SysExLength(len),
+            },
+            ReadState::FileHeader | ReadState::ChunkHeader | ReadState::Skip => unreachable!(),
+        })
+    }
+
+    /// The track read, plus the tempo of the file (if set)
+    pub(crate) fn track(mut self) -> Result<(VoiceTrack, Option<Bpm>), StdlibError> {
+        let mut notes = mem::take(&mut self.notes);
+        notes.sort_unstable_by_key(|(start, _, _)| *start);
+
+        // longer files are cut off at the longest track there can be
+        let last_step = notes
+            .iter()
+            .map(|(_, end, _)| self.to_step(*end))
+            .max()
+            .unwrap_or(0)
+            .min(MAX_TRACK_LENGTH);
+        let len = (last_step.div_ceil(4) * 4).max(MIN_TRACK_LEN);
+        let mut track = VoiceTrack::new(len);
+
+        // later notes take over from the ones which are still held (like when recording)
+        for (start, end, key) in notes {
+            // not a valid MIDI note, in a broken file
+            if key > 127 {
+                continue;
+            }
+            let np = NotePair::from(key);
+            let start = self.to_step(start);
+            if start >= len {
+                continue;
+            }
+            let end = self.to_step(end).max(start + 1).min(len);
+
+            for step in start..end {
+                let flag = if step == start {
+                    NoteFlag::Note
+                } else {
+                    NoteFlag::Legato
+                };
+                track
+                    .set_note(step, (Some(np), flag))
+                    .map_err(|_| StdlibError::Deserialization)?;
+            }
+        }
+
+        let bpm = self
+            .tempo
+            .filter(|t| *t > 0)
+            .map(|t| Bpm::from_tenths(600_000_000 / t));
+
+        Ok((track, bpm))
+    }
+}
+
+impl FileReader for SmfReader {
+    fn read_part(&mut self, mut data: &[u8]) -> Result<(), StdlibError> {
+        while let Some((&b, rest)) = data.split_first() {
+            match self.state {
+                ReadState::FileHeader | ReadState::ChunkHeader => {
+                    self.header.push(b).ok();
+                    data = rest;
+                    self.state = match self.state {
+                        ReadState::FileHeader if self.header.len() == FILE_HEADER_LEN => {
+                            self.file_header()?
+                        }
+                        ReadState::ChunkHeader if self.header.len() == CHUNK_HEADER_LEN => {
+                            self.chunk_header()
+                        }
+                        _ => continue,
+                    };
+                    self.header.clear();
+                }
+                // as much as there is of it in this part, at once
+                ReadState::Skip => {
+                    let n = data.len().min(self.left as usize);
+                    self.left -= n as u32;
+                    data = &data[n..];
+                    self.state = self.end_chunk();
+                }
+                _ => {
+                    self.left -= 1;
+                    data = rest;
+                    self.state = self.track_byte(b)?;
+                    if self.left == 0 {
+                        self.state = match self.state {
+                            ReadState::ChunkHeader => ReadState::ChunkHeader,
+                            ReadState::Delta(Vlq { len: 0, .. }) => self.end_track(),
+                            // the chunk ends within an event
+                            _ => return Err(StdlibError::Deserialization),
+                        };
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn end(&mut self) -> Result<(), StdlibError> {
+        // the file ends between chunks, or it was cut short
+        match self.state {
+            ReadState::ChunkHeader if self.header.is_empty() => Ok(()),
+            _ => Err(StdlibError::Deserialization),
+        }
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use voice_lib::{Note, NoteFlag, NotePair, VoiceTrack};
+
+    use super::{SmfReader, SmfWriter};
+    use crate::programs::sequencer::transport::Bpm;
+    use crate::stdlib::{FileReader, FileWriter};
+
+    /// Write `track` out in parts of `part` bytes
+    fn write(track: &VoiceTrack, part: usize) -> Vec<u8> {
+        let mut writer = SmfWriter::new(track, Bpm::from_tenths(1200)).unwrap();
+        let mut data = Vec::new();
+        let mut buf = [0; 64];
+        loop {
+            let n = writer.write_part(&mut buf[..part]).unwrap();
+            if n == 0 {
+                return data;
+            }
+            data.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    /// Read `data` in parts of `part` bytes
+    fn read(data: &[u8], part: usize) -> (VoiceTrack, Option<Bpm>) {
+        let mut reader = SmfReader::default();
+        for chunk in data.chunks(part) {
+            reader.read_part(chunk).unwrap();
+        }
+        reader.end().unwrap();
+        reader.track().unwrap()
+    }
+
+    fn track() -> VoiceTrack {
+        let mut track = VoiceTrack::new(16);
+        track.set_note(0, (Some(NotePair(Note::C, 4)), NoteFlag::Note)).unwrap();
+        track.set_note(1, (Some(NotePair(Note::C, 4)), NoteFlag::Legato)).unwrap();
+        track.set_note(2, (Some(NotePair(Note::D, 4)), NoteFlag::Note)).unwrap();
+        track.set_note(5, (Some(NotePair(Note::G, 4)), NoteFlag::Note)).unwrap();
+        track.set_note(15, (Some(NotePair(Note::C, 3)), NoteFlag::Note)).unwrap();
+        track
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let track = track();
+        let data = write(&track, 64);
+        for part in [1, 3, 7, 64] {
+            assert!(write(&track, part) == data);
+            let (read, bpm) = read(&data, part);
+            assert!(bpm == Some(Bpm::from_tenths(1200)));
+            assert!(read.len() == track.len());
+            for step in 0..track.len() {
+                assert!(read.get_note(step) == track.get_note(step));
+            }
+        }
+    }
+
+    #[test]
+    fn test_skip_chunks() {
+        let data = write(&track(), 64);
+        // an unknown chunk between the header and the track, and the track twice over
+        let mut file = data[..14].to_vec();
+        file.extend_from_slice(b"XTRA\x00\x00\x00\x05hello");
+        file.extend_from_slice(&data[14..]);
+        file.extend_from_slice(&data[14..]);
+        let (read, _) = read(&file, 5);
+        assert!(read.get_note(5) == track().get_note(5));
+
+        // cut short, within the track
+        let mut reader = SmfReader::default();
+        let cut = reader.read_part(&data[..data.len() - 2]).and_then(|_| reader.end());
+        assert!(cut.is_err());
+    }
+}
@@ -0,0 +1,234 @@
+use core::fmt::Debug;
+
+use alloc::vec::Vec;
+use embedded_graphics::{draw_target::DrawTarget, pixelcolor::Rgb565};
+use embedded_sdmmc::{BlockDevice, TimeSource};
+use serde::{Deserialize, Serialize};
+use voice_lib::VoiceTrack;
+
+use super::{tracks::NUM_TRACKS, SequencerProgram, State};
+use crate::{log::error, stdlib::TaskInterface};
+
+/// Patterns in a project
+pub(crate) const NUM_PATTERNS: usize = 16;
+/// Entries in the song arrangement
+pub(crate) const MAX_SONG_ENTRIES: usize = 8;
+/// Pattern switches wait for the next bar
+pub(crate) const STEPS_PER_BAR: u32 = 4;
+/// How much of the heap the tracks of all patterns may take up (it's 16 KB in total,
+/// the undo history and files being saved or loaded need their share too)
+pub(crate) const MAX_TRACKS_SIZE: usize = 4 * 1024;
+
+/// A pattern of the song, played through `repeats` times
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SongEntry {
+    pub(crate) pattern: u8,
+    pub(crate) repeats: u8,
+}
+
+/// The patterns of the project (each with all the tracks), and the song they are arranged in.
+/// The current pattern is in `Tracks`, its slot is empty meanwhile.
+pub(crate) struct Patterns {
+    slots: Vec<Option<Vec<VoiceTrack>>>,
+    pub(crate) current: u8,
+    /// Pattern to switch to, on the next bar
+    pub(crate) queued: Option<u8>,
+    pub(crate) song: heapless::Vec<SongEntry, MAX_SONG_ENTRIES>,
+    /// The song decides which pattern comes next
+    pub(crate) song_mode: bool,
+    // entry of the song being played, and how many times it has been played through
+    pub(crate) entry: usize,
+    played: u8,
+}
+
+impl Default for Patterns {
+    fn default() -> Self {
+        Self {
+            slots: (0..NUM_PATTERNS).map(|_| None).collect(),
+            current: 0,
+            queued: None,
+            song: heapless::Vec::new(),
+            song_mode: false,
+            entry: 0,
+            played: 0,
+        }
+    }
+}
+
+impl Patterns {
+    /// All the patterns, `current` being the tracks of the current one
+    pub(crate) fn all<'a>(&'a self, current: Vec<&'a VoiceTrack>) -> Vec<Option<Vec<&'a VoiceTrack>>> {
+        let mut current = Some(current);
+        self.slots
+            .iter()
+            .enumerate()
+            .map(|(n, tracks)| {
+                if n == self.current as usize {
+                    current.take()
+                } else {
+                    tracks.as_ref().map(|tracks| tracks.iter().collect())
+                }
+            })
+            .collect()
+    }
+
+    /// Replace all patterns (e.g. with the ones of a project file).
+    /// Returns the tracks of the current one.
+    pub(crate) fn replace(
+        &mut self,
+        tracks: Vec<Option<Vec<VoiceTrack>>>,
+        current: u8,
+        song: heapless::Vec<SongEntry, MAX_SONG_ENTRIES>,
+    ) -> Vec<VoiceTrack> {
+        self.slots = tracks;
+        self.slots.resize_with(NUM_PATTERNS, || None);
+        self.current = current.min(NUM_PATTERNS as u8 - 1);
+        self.queued = None;
+        self.song = song;
+        self.entry = 0;
+        self.played = 0;
+        self.slots[self.current as usize].take().unwrap_or_default()
+    }
+
+    /// Memory taken up by the tracks of the patterns, except the current one
+    fn size(&self) -> usize {
+        self.slots.iter().flatten().flatten().map(VoiceTrack::size).sum()
+    }
+
+    /// Switch to pattern `pattern` on the next bar
+    pub(crate) fn queue(&mut self, pattern: u8) {
+        self.queued = Some(pattern.min(NUM_PATTERNS as u8 - 1));
+    }
+
+    /// Switch to entry `entry` of the song on the next bar
+    pub(crate) fn queue_entry(&mut self, entry: usize) {
+        if let Some(e) = self.song.get(entry) {
+            self.entry = entry;
+            self.played = 0;
+            self.queued = Some(e.pattern);
+        }
+    }
+
+    /// The next song entry in song mode, the next pattern otherwise
+    pub(crate) fn queue_next(&mut self) {
+        if self.song_mode && !self.song.is_empty() {
+            self.queue_entry((self.entry + 1) % self.song.len());
+        } else {
+            self.queue((self.current + 1) % NUM_PATTERNS as u8);
+        }
+    }
+
+    /// Go back to the start of the song, returning its first pattern
+    pub(crate) fn restart_song(&mut self) -> Option<u8> {
+        if !self.song_mode {
+            return None;
+        }
+        self.queued = None;
+        self.entry = 0;
+        self.played = 0;
+        self.song.first().map(|e| e.pattern)
+    }
+
+    /// The transport reached step `beat` of the current pattern (of length `len`),
+    /// counted from when it started. Returns the pattern to switch to, if any.
+    pub(crate) fn step(&mut self, beat: u32, len: usize) -> Option<u8> {
+        if self.song_mode && beat > 0 && beat % len as u32 == 0 {
+            self.played = self.played.saturating_add(1);
+            let done = match self.song.get(self.entry) {
+                Some(e) => self.played >= e.repeats,
+                None => false,
+            };
+            if done && !self.song.is_empty() {
+                self.queue_entry((self.entry + 1) % self.song.len());
+            }
+        }
+
+        if beat % STEPS_PER_BAR == 0 {
+            self.queued.take()
+        } else {
+            None
+        }
+    }
+
+    /// Put `tracks` (the current pattern) back in its slot, and take out pattern `pattern`.
+    /// Empty patterns get tracks as long as the current ones.
+    pub(crate) fn swap(&mut self, pattern: u8, tracks: Vec<VoiceTrack>) -> Vec<VoiceTrack> {
+        if pattern == self.current {
+            return tracks;
+        }
+        let empty = || tracks.iter().map(|track| VoiceTrack::new(track.len())).collect();
+        let next = self.slots[pattern as usize].take().unwrap_or_else(empty);
+        self.slots[self.current as usize] = Some(tracks);
+        self.current = pattern;
+        next
+    }
+}
+
+impl<'t, B: BlockDevice, TS: TimeSource, D: DrawTarget<Color = Rgb565>, TI: TaskInterface>
+    SequencerProgram<'t, B, TS, D, TI>
+where
+    <D as DrawTarget>::Error: Debug,
+{
+    /// The transport reached a new step: switch patterns, if one is due.
+    /// The new pattern starts from its first step.
+    pub(crate) fn pattern_step(&mut self) {
+        let beat = match self.state {
+            State::Playing(pos) | State::Recording(pos) => pos.beat,
+            _ => return,
+        };
+        // the pattern has been played through once its longest track has, in master steps
+        let len = (0..NUM_TRACKS)
+            .map(|n| {
+                let ratio = self.tracks.settings[n].ratio;
+                (self.track(n).len() * ratio.div as usize).div_ceil(ratio.mult as usize)
+            })
+            .max()
+            .unwrap_or(1)
+            .max(1);
+        if let Some(pattern) = self.patterns.step(beat, len) {
+            self.switch_pattern(pattern);
+            if let State::Playing(pos) | State::Recording(pos) = &mut self.state {
+                pos.beat = 0;
+            }
+        }
+    }
+
+    /// Without the transport running there is no bar to wait for, switch right away
+    pub(crate) fn switch_if_stopped(&mut self) {
+        if matches!(self.state, State::Stopped | State::Loading) {
+            if let Some(pattern) = self.patterns.queued.take() {
+                self.switch_pattern(pattern);
+            }
+        }
+    }
+
+    /// Memory taken up by the tracks of all patterns
+    pub(crate) fn tracks_size(&self) -> usize {
+        self.patterns.size() + (0..NUM_TRACKS).map(|n| self.track(n).size()).sum::<usize>()
+    }
+
+    /// Whether tracks taking up another `size` bytes still fit in `MAX_TRACKS_SIZE`
+    pub(crate) fn tracks_fit(&self, size: usize) -> bool {
+        self.tracks_size() + size <= MAX_TRACKS_SIZE
+    }
+
+    /// Make `pattern` the current one, right away.
+    /// Empty patterns are only created while their tracks fit in memory.
+    pub(crate) fn switch_pattern(&mut self, pattern: u8) {
+        let slot = pattern as usize;
+        if pattern != self.patterns.current && self.patterns.slots[slot].is_none() {
+            let size = (0..NUM_TRACKS)
+                .map(|n| VoiceTrack::size_for(self.track(n).len()))
+                .sum();
+            if !self.tracks_fit(size) {
+                error("Not enough memory for another pattern");
+                return;
+            }
+        }
+        self.swap_recorder_track(|program, track| {
+            let tracks = program.tracks.take_all(track);
+            let tracks = program.patterns.swap(pattern, tracks);
+            program.tracks.replace_all(tracks)
+        });
+    }
+}
@@ -0,0 +1,209 @@
+use core::fmt::Debug;
+
+use embedded_graphics::{draw_target::DrawTarget, pixelcolor::Rgb565};
+use embedded_sdmmc::{BlockDevice, TimeSource};
+use voice_lib::{NoteFlag, NotePair, TrigCondition, MAX_MOD_VALUE, MAX_RATCHETS};
+
+use super::{
+    transport::Position,
+    ui::NUM_VERTICAL_NOTES,
+    SequencerProgram,
+};
+use crate::{
+    stdlib::{ui::UIInputEvent, TaskInterface},
+    util::DiscreetUnwrap,
+};
+
+const MIN_PITCH: u8 = 0;
+const MAX_PITCH: u8 = 127;
+/// Change of the modulation value per encoder detent
+const MOD_VALUE_STEP: i32 = 64;
+
+/// Trig conditions the encoder steps through, in condition mode
+const TRIG_CONDITIONS: [TrigCondition; 19] = [
+    TrigCondition::Always,
+    TrigCondition::Probability(10),
+    TrigCondition::Probability(25),
+    TrigCondition::Probability(50),
+    TrigCondition::Probability(75),
+    TrigCondition::Probability(90),
+    TrigCondition::Every(1, 2),
+    TrigCondition::Every(2, 2),
+    TrigCondition::Every(1, 3),
+    TrigCondition::Every(2, 3),
+    TrigCondition::Every(3, 3),
+    TrigCondition::Every(1, 4),
+    TrigCondition::Every(2, 4),
+    TrigCondition::Every(3, 4),
+    TrigCondition::Every(4, 4),
+    TrigCondition::Every(1, 8),
+    TrigCondition::Every(1, 16),
+    TrigCondition::FirstLoop,
+    TrigCondition::NotPrevious,
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum StepEditMode {
+    /// Encoder moves the cursor
+    Step,
+    /// Encoder changes the pitch of the note under the cursor
+    Pitch,
+    /// Encoder changes the trig condition of the step under the cursor
+    Condition,
+    /// Encoder changes the number of ratchets of the step under the cursor
+    Ratchets,
+    /// Encoder changes the modulation value of the step under the cursor
+    /// (turning it below 0 removes the value)
+    Modulation,
+}
+
+pub(crate) struct StepEdit {
+    pub(crate) step: usize,
+    pub(crate) mode: StepEditMode,
+    // pitch used when a rest is turned into a note
+    pitch: u8,
+}
+
+impl StepEdit {
+    pub(crate) fn new(step: usize) -> Self {
+        Self {
+            step,
+            mode: StepEditMode::Step,
+            pitch: 72, // C5
+        }
+    }
+
+    /// Pitch new notes are given (MIDI note number)
+    pub(crate) fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Position the score view should be centered on
+    pub(crate) fn position(&self) -> Position {
+        Position::at_beat(self.step as u32)
+    }
+}
+
+impl<'t, B: BlockDevice, TS: TimeSource, D: DrawTarget<Color = Rgb565>, TI: TaskInterface>
+    SequencerProgram<'t, B, TS, D, TI>
+where
+    <D as DrawTarget>::Error: Debug,
+{
+    /// Write `n` to the cursor step (the step-edit one, if open), and move the cursor on
+    pub(crate) fn step_record(&mut self, n: NotePair) {
+        let len = self.recorder.voice_state.len();
+        let step = match &self.step_edit {
+            Some(edit) => edit.step,
+            None => self.recorder.cursor % len,
+        };
+        self.recorder.step_pressed(step, n);
+
+        self.recorder.cursor = (step + 1) % len;
+        if let Some(edit) = self.step_edit.as_mut() {
+            edit.step = self.recorder.cursor;
+        }
+    }
+
+    /// Process input while in step-edit mode. Returns `false` if the mode should be exited.
+    pub(crate) fn process_step_edit_input(&mut self, msg: &UIInputEvent) -> bool {
+        let track_len = self.recorder.voice_state.len();
+        let edit = match self.step_edit.as_mut() {
+            Some(edit) => edit,
+            None => return false,
+        };
+
+        match msg {
+            UIInputEvent::Switch1(true) => {
+                edit.mode = match edit.mode {
+                    StepEditMode::Step => StepEditMode::Pitch,
+                    StepEditMode::Pitch => StepEditMode::Condition,
+                    StepEditMode::Condition => StepEditMode::Ratchets,
+                    StepEditMode::Ratchets => StepEditMode::Modulation,
+                    StepEditMode::Modulation => StepEditMode::Step,
+                };
+            }
+            UIInputEvent::Switch2(false) => {
+                return false;
+            }
+            UIInputEvent::EncoderTurn(v) if edit.mode == StepEditMode::Step => {
+                edit.step = (edit.step as i32 + *v as i32).rem_euclid(track_len as i32) as usize;
+                if let Some((Some(np), _)) = self.recorder.voice_state.get_note(edit.step) {
+                    edit.pitch = (&np).try_into().duwrp();
+                }
+            }
+            UIInputEvent::EncoderTurn(v) if edit.mode == StepEditMode::Condition => {
+                let current = TRIG_CONDITIONS
+                    .iter()
+                    .position(|c| *c == self.recorder.voice_state.get_condition(edit.step))
+                    .unwrap_or(0);
+                let n = (current as i32 + *v as i32).clamp(0, TRIG_CONDITIONS.len() as i32 - 1);
+                self.recorder.edit_step(edit.step, |track, step| {
+                    track.set_condition(step, TRIG_CONDITIONS[n as usize])
+                });
+            }
+            UIInputEvent::EncoderTurn(v) if edit.mode == StepEditMode::Ratchets => {
+                let ratchets = (self.recorder.voice_state.get_ratchets(edit.step) as i8 + *v)
+                    .clamp(1, MAX_RATCHETS as i8);
+                self.recorder.edit_step(edit.step, |track, step| {
+                    track.set_ratchets(step, ratchets as u8)
+                });
+            }
+            UIInputEvent::EncoderTurn(v) if edit.mode == StepEditMode::Modulation => {
+                let value = match self.recorder.voice_state.get_modulation(edit.step) {
+                    Some(value) => value as i32 + *v as i32 * MOD_VALUE_STEP,
+                    None if *v > 0 => 0,
+                    None => -1,
+                };
+                let value = (value >= 0).then(|| value.min(MAX_MOD_VALUE as i32) as u16);
+                self.recorder
+                    .edit_step(edit.step, |track, step| track.set_modulation(step, value));
+            }
+            UIInputEvent::EncoderTurn(v) => {
+                let step = edit.step;
+                edit.pitch = (edit.pitch as i16 + *v as i16)
+                    .clamp(MIN_PITCH as i16, MAX_PITCH as i16) as u8;
+                let pitch = edit.pitch;
+
+                match self.recorder.voice_state.get_note(step) {
+                    Some((_, flag @ (NoteFlag::Note | NoteFlag::Legato))) => {
+                        self.set_step(step, Some(pitch.into()), flag);
+                    }
+                    _ => {}
+                }
+                self.follow_pitch(pitch);
+            }
+            UIInputEvent::EncoderSwitch(true) => {
+                let (step, pitch) = (edit.step, edit.pitch);
+
+                // note -> tie -> rest -> note
+                let flag = match self.recorder.voice_state.get_note(step) {
+                    Some((_, NoteFlag::Note)) => NoteFlag::Legato,
+                    Some((_, NoteFlag::Legato)) => NoteFlag::None,
+                    _ => NoteFlag::Note,
+                };
+                self.set_step(
+                    step,
+                    (flag != NoteFlag::None).then(|| NotePair::from(pitch)),
+                    flag,
+                );
+                self.follow_pitch(pitch);
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn set_step(&mut self, step: usize, note: Option<NotePair>, flag: NoteFlag) {
+        self.recorder.set_note(step, (note, flag));
+    }
+
+    /// Scroll the piano roll so that `pitch` is visible
+    fn follow_pitch(&mut self, pitch: u8) {
+        let visible = NUM_VERTICAL_NOTES as u8;
+        if pitch < self.current_note {
+            self.current_note = pitch;
+        } else if pitch >= self.current_note + visible {
+            self.current_note = pitch + 1 - visible;
+        }
+    }
+}
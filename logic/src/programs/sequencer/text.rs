@@ -0,0 +1,118 @@
+//! Plain-text sequence files, which can be diffed (e.g. when kept in git):
+//!
+//! ```text
+//! bpm 120.5
+//! length 16
+//! C5 N
+//! C5 L
+//! --
+//! ...
+//! ```
+//!
+//! Blank lines and lines starting with `#` are ignored.
+
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use ufmt::{uWrite, uwrite};
+use voice_lib::VoiceTrack;
+
+use super::{tracks::MAX_TRACK_LENGTH, transport::Bpm};
+use crate::stdlib::StdlibError;
+
+struct TextWriter(Vec<u8>);
+
+impl uWrite for TextWriter {
+    type Error = Infallible;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Infallible> {
+        self.0.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+}
+
+pub(crate) fn write(track: &VoiceTrack, bpm: Bpm) -> Vec<u8> {
+    let mut w = TextWriter(Vec::new());
+    // can't fail
+    uwrite!(w, "bpm {}\nlength {}\n", bpm, track.len()).ok();
+    track.write_text(&mut w).ok();
+    w.0
+}
+
+pub(crate) fn read(data: &[u8]) -> Result<(VoiceTrack, Bpm), StdlibError> {
+    let text = core::str::from_utf8(data).map_err(|_| StdlibError::Deserialization)?;
+    let mut lines = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'));
+
+    let mut header = |key: &str| {
+        lines
+            .next()
+            .and_then(|l| l.strip_prefix(key))
+            .map(str::trim_start)
+            .ok_or(StdlibError::Deserialization)
+    };
+    let bpm: Bpm = header("bpm")?
+        .parse()
+        .map_err(|_| StdlibError::Deserialization)?;
+    let len: usize = header("length")?
+        .parse()
+        .map_err(|_| StdlibError::Deserialization)?;
+
+    // every step takes at least 3 bytes (`--\n`), so don't allocate more than the file can fill
+    if len == 0 || len > MAX_TRACK_LENGTH || len > data.len() / 3 {
+        return Err(StdlibError::Deserialization);
+    }
+
+    let mut track = VoiceTrack::new(len);
+    let mut n = 0;
+    for line in lines {
+        let step = VoiceTrack::parse_step(line).map_err(|_| StdlibError::Deserialization)?;
+        if n >= len {
+            return Err(StdlibError::Deserialization);
+        }
+        step.apply(&mut track, n)
+            .map_err(|_| StdlibError::Deserialization)?;
+        n += 1;
+    }
+
+    if n != len {
+        return Err(StdlibError::Deserialization);
+    }
+    Ok((track, bpm))
+}
+
+#[cfg(test)]
+mod tests {
+    use voice_lib::{Note, NoteFlag, NotePair, VoiceTrack};
+
+    use super::{read, write, Bpm};
+
+    #[test]
+    fn test_round_trip() {
+        // lengths which don't fill the last byte of flags, too
+        for len in [1, 5, 6, 7, 16] {
+            let mut track = VoiceTrack::new(len);
+            track.set_note(0, (Some(NotePair(Note::C, 4)), NoteFlag::Note)).unwrap();
+            track.set_note(len - 1, (Some(NotePair(Note::E, 3)), NoteFlag::Legato)).unwrap();
+            track.set_gate(len - 1, Some(50));
+
+            let (read, bpm) = read(&write(&track, Bpm::from_tenths(1205))).unwrap();
+            assert!(bpm == Bpm::from_tenths(1205));
+            assert!(read.len() == len);
+            for n in 0..len {
+                assert!(read.get_note(n) == track.get_note(n));
+                assert!(read.get_gate(n) == track.get_gate(n));
+            }
+        }
+    }
+
+    #[test]
+    fn test_length() {
+        let text = "bpm 120\nlength 65\n";
+        let steps = "--\n".repeat(65);
+        assert!(read(alloc::format!("{}{}", text, steps).as_bytes()).is_err());
+        // fewer steps than the length
+        assert!(read(b"bpm 120\nlength 3\n--\n--\n").is_err());
+    }
+}
@@ -0,0 +1,452 @@
+use core::{fmt::Debug, mem};
+
+use alloc::vec::Vec;
+use embedded_graphics::{draw_target::DrawTarget, pixelcolor::Rgb565};
+use embedded_sdmmc::{BlockDevice, TimeSource};
+use serde::{Deserialize, Serialize};
+use voice_lib::{ClockRatio, Key, NoteFlag, NotePair, VoiceTrack};
+
+use super::{
+    clock::ClockMode,
+    playback::{ratchet_velocity, GateMode},
+    recorder::{ScaleLock, DEFAULT_SIZE},
+    transport::Position,
+    SequencerProgram, State,
+};
+use crate::stdlib::{CVChannelId, GateChannelId, GlideSettings, Output, TaskInterface};
+
+pub(crate) const NUM_TRACKS: usize = 4;
+pub(crate) const MAX_TRACK_LENGTH: usize = 64;
+
+/// Which pair of outputs a track plays on
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum TrackOutput {
+    Off,
+    /// Gate0/CV0
+    Voice0,
+    /// Gate1/CV1
+    Voice1,
+}
+
+impl TrackOutput {
+    /// Index of the gate/CV pair, and of their clock/glide settings
+    fn index(&self) -> Option<usize> {
+        match self {
+            TrackOutput::Off => None,
+            TrackOutput::Voice0 => Some(0),
+            TrackOutput::Voice1 => Some(1),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct TrackSettings {
+    pub(crate) output: TrackOutput,
+    /// MIDI channel (1-16) the track records from, 0 for any
+    pub(crate) channel: u8,
+    /// Semitones, applied on playback
+    pub(crate) transpose: i8,
+    pub(crate) mute: bool,
+    pub(crate) solo: bool,
+    /// Steps of the track per master step
+    #[serde(default)]
+    pub(crate) ratio: ClockRatio,
+    #[serde(default)]
+    pub(crate) gate_mode: GateMode,
+    /// How much quieter (%) each sub-trigger of a ratcheted step gets
+    #[serde(default)]
+    pub(crate) ratchet_decay: u8,
+    #[serde(default)]
+    pub(crate) scale_lock: ScaleLock,
+}
+
+impl TrackSettings {
+    fn new(output: TrackOutput) -> Self {
+        Self {
+            output,
+            channel: 0,
+            transpose: 0,
+            mute: false,
+            solo: false,
+            ratio: ClockRatio::default(),
+            gate_mode: GateMode::default(),
+            ratchet_decay: 0,
+            scale_lock: ScaleLock::default(),
+        }
+    }
+}
+
+impl Default for TrackSettings {
+    fn default() -> Self {
+        Self::new(TrackOutput::Off)
+    }
+}
+
+/// The outputs a track plays on, minus the ones the clock and the modulation lane are using
+pub(crate) struct Voice {
+    pub(crate) gate: Option<GateChannelId>,
+    pub(crate) cv: Option<CVChannelId>,
+    pub(crate) glide: GlideSettings,
+}
+
+/// The tracks of the current pattern.
+/// The selected one is the recorder's track, its slot holds an empty track meanwhile.
+pub(crate) struct Tracks {
+    tracks: Vec<VoiceTrack>,
+    pub(crate) settings: [TrackSettings; NUM_TRACKS],
+    pub(crate) selected: usize,
+    /// The score shows all tracks at once, instead of the selected one
+    pub(crate) overview: bool,
+    /// Master step all tracks started over from
+    origin: u32,
+    /// Start over on the next master step
+    reset: bool,
+}
+
+impl Default for Tracks {
+    fn default() -> Self {
+        let mut settings = [TrackSettings::default(); NUM_TRACKS];
+        settings[0].output = TrackOutput::Voice0;
+        settings[1].output = TrackOutput::Voice1;
+
+        Self {
+            tracks: (0..NUM_TRACKS)
+                .map(|n| VoiceTrack::new(if n == 0 { 0 } else { DEFAULT_SIZE }))
+                .collect(),
+            settings,
+            selected: 0,
+            overview: false,
+            origin: 0,
+            reset: false,
+        }
+    }
+}
+
+impl Tracks {
+    /// Track `n`, `selected` being the one in the recorder
+    pub(crate) fn get<'a>(&'a self, n: usize, selected: &'a VoiceTrack) -> &'a VoiceTrack {
+        if n == self.selected {
+            selected
+        } else {
+            &self.tracks[n]
+        }
+    }
+
+    /// Track `n`, unless it's the selected one
+    pub(crate) fn get_mut(&mut self, n: usize) -> Option<&mut VoiceTrack> {
+        if n == self.selected {
+            None
+        } else {
+            self.tracks.get_mut(n)
+        }
+    }
+
+    /// All tracks, `selected` being the one in the recorder
+    pub(crate) fn all<'a>(&'a self, selected: &'a VoiceTrack) -> Vec<&'a VoiceTrack> {
+        (0..NUM_TRACKS).map(|n| self.get(n, selected)).collect()
+    }
+
+    /// Put `track` (the selected one) back in its slot, and take out track `n`
+    pub(crate) fn swap(&mut self, n: usize, track: VoiceTrack) -> VoiceTrack {
+        self.tracks[self.selected] = track;
+        self.selected = n;
+        mem::replace(&mut self.tracks[n], VoiceTrack::new(0))
+    }
+
+    /// Take out all the tracks, `selected` being the one in the recorder
+    pub(crate) fn take_all(&mut self, selected: VoiceTrack) -> Vec<VoiceTrack> {
+        self.tracks[self.selected] = selected;
+        mem::take(&mut self.tracks)
+    }
+
+    /// Replace all the tracks, returning the selected one (which goes to the recorder)
+    pub(crate) fn replace_all(&mut self, mut tracks: Vec<VoiceTrack>) -> VoiceTrack {
+        tracks.resize_with(NUM_TRACKS, || VoiceTrack::new(DEFAULT_SIZE));
+        self.tracks = tracks;
+        mem::replace(&mut self.tracks[self.selected], VoiceTrack::new(0))
+    }
+
+    /// Whether track `n` is heard: soloed tracks silence all the others
+    pub(crate) fn audible(&self, n: usize) -> bool {
+        if self.settings.iter().any(|s| s.solo) {
+            self.settings[n].solo
+        } else {
+            !self.settings[n].mute
+        }
+    }
+
+    /// All tracks start over from their first step, on the next master step
+    pub(crate) fn reset(&mut self) {
+        self.reset = true;
+    }
+
+    /// All tracks start over from their first step, at the start of the song
+    pub(crate) fn rewind(&mut self) {
+        self.origin = 0;
+        self.reset = false;
+    }
+
+    /// The master transport reached step `beat`
+    pub(crate) fn master_step(&mut self, beat: u32) {
+        if mem::take(&mut self.reset) {
+            self.origin = beat;
+        } else if beat < self.origin {
+            // back at the origin of the song
+            self.origin = 0;
+        }
+    }
+
+    /// Where track `n` is, when the master transport is at `master`.
+    /// Each track has a length of its own, and runs at its own rate.
+    pub(crate) fn position(&self, n: usize, master: Position) -> Position {
+        let origin = if master.beat < self.origin { 0 } else { self.origin };
+        master.since(origin).scaled(self.settings[n].ratio)
+    }
+
+    /// Length of a step of track `n`, in ms
+    pub(crate) fn step_ms(&self, n: usize, beat_ms: u32) -> u32 {
+        let ratio = self.settings[n].ratio;
+        beat_ms * ratio.div as u32 / ratio.mult as u32
+    }
+
+    /// Key of the selected track, which generators and transforms work in
+    pub(crate) fn key(&self) -> Key {
+        self.settings[self.selected].scale_lock.key
+    }
+
+    /// Whether the selected track takes notes from MIDI channel `channel` (0-15)
+    pub(crate) fn listens(&self, channel: u8) -> bool {
+        match self.settings[self.selected].channel {
+            0 => true,
+            c => c == channel + 1,
+        }
+    }
+}
+
+impl<'t, B: BlockDevice, TS: TimeSource, D: DrawTarget<Color = Rgb565>, TI: TaskInterface>
+    SequencerProgram<'t, B, TS, D, TI>
+where
+    <D as DrawTarget>::Error: Debug,
+{
+    /// Track `n` of the current pattern
+    pub(crate) fn track(&self, n: usize) -> &VoiceTrack {
+        self.tracks.get(n, &self.recorder.voice_state)
+    }
+
+    /// Where track `n` is played, if anywhere
+    pub(crate) fn voice(&self, n: usize) -> Option<Voice> {
+        let index = self.tracks.settings[n].output.index()?;
+        let (gate, cv) = if index == 0 {
+            (GateChannelId::Gate0, CVChannelId::CV0)
+        } else {
+            (GateChannelId::Gate1, CVChannelId::CV1)
+        };
+
+        // the clock and the metronome click take precedence
+        let clicks = self.config.metronome.output.channel() == Some(gate);
+        Some(Voice {
+            gate: (self.config.clock[index].mode == ClockMode::Off && !clicks).then_some(gate),
+            cv: (self.config.modulation.output.channel() != Some(cv)).then_some(cv),
+            glide: self.config.glide[index],
+        })
+    }
+
+    /// Where track `n` is, if the transport is running
+    pub(crate) fn track_position(&self, n: usize) -> Option<Position> {
+        match self.state {
+            State::Playing(pos) | State::Recording(pos) => Some(self.tracks.position(n, pos)),
+            _ => None,
+        }
+    }
+
+    /// Start all tracks over together: right away if the transport is stopped
+    /// (it starts from the origin anyway), on the next master step otherwise
+    pub(crate) fn reset_tracks(&mut self) {
+        match self.state {
+            State::Loading | State::Stopped => self.tracks.rewind(),
+            _ => self.tracks.reset(),
+        }
+    }
+
+    /// Make track `n` the one being edited
+    pub(crate) fn select_track(&mut self, n: usize) {
+        if n != self.tracks.selected {
+            self.swap_recorder_track(|program, track| program.tracks.swap(n, track));
+        }
+    }
+
+    /// Give the recorder another track, through `swap` (which gets the current one).
+    /// The undo history only covers the track it was recorded on, so it starts over.
+    pub(crate) fn swap_recorder_track(&mut self, swap: impl FnOnce(&mut Self, VoiceTrack) -> VoiceTrack) {
+        let recording = matches!(self.state, State::Recording(_));
+        if recording {
+            self.recorder.end_pass();
+        }
+
+        let track = mem::replace(&mut self.recorder.voice_state, VoiceTrack::new(0));
+        let track = swap(self, track);
+        self.recorder.replace_track(track);
+
+        if recording {
+            self.recorder.start_pass();
+        }
+        if let Some(edit) = self.step_edit.as_mut() {
+            edit.step %= self.recorder.voice_state.len();
+        }
+    }
+
+    /// The arpeggiator, then keys being held, play on the outputs of the selected track
+    /// (which is silent meanwhile). Returns whether they did.
+    pub(crate) fn play_live<T: for<'u> TryFrom<&'u NotePair, Error = E>, E>(
+        &mut self,
+        output: &mut impl Output<T, E>,
+    ) -> Result<bool, E> {
+        let n = self.tracks.selected;
+        let voice = self.voice(n);
+        let TrackSettings { transpose, scale_lock, .. } = self.tracks.settings[n];
+
+        if self.arp.is_active() {
+            if let (Some(np), Some(voice)) = (self.arp.pending.take(), voice) {
+                if let Some(cv) = voice.cv {
+                    output.set_glide(cv, voice.glide.for_step(NoteFlag::Note));
+                    let np = scale_lock.on_playback(np.transposed(transpose));
+                    output.set_cv(cv, (&np).try_into()?);
+                }
+                if let Some(gate) = voice.gate {
+                    output.pulse_gate(gate, self.config.arp.gate_ms(self.bpm));
+                }
+            }
+            return Ok(true);
+        }
+
+        let held = self.recorder.last_note().copied();
+        if let (Some(np), Some(voice)) = (held, voice) {
+            let flag = if self.recorder.num_keys_held() > 1 {
+                NoteFlag::Legato
+            } else {
+                NoteFlag::Note
+            };
+            if let Some(gate) = voice.gate {
+                output.set_gate(gate, true);
+            }
+            if let Some(cv) = voice.cv {
+                output.set_glide(cv, voice.glide.for_step(flag));
+                let np = scale_lock.on_playback(np.transposed(transpose));
+                output.set_cv(cv, (&np).try_into()?);
+            }
+            self.playback[n].live = true;
+        }
+
+        // keys were let go of, or the track they were played on isn't selected anymore
+        for m in 0..NUM_TRACKS {
+            if self.playback[m].live && (held.is_none() || m != n) {
+                if let Some(gate) = self.voice(m).and_then(|v| v.gate) {
+                    output.set_gate(gate, false);
+                }
+                self.playback[m].live = false;
+            }
+        }
+        Ok(held.is_some())
+    }
+
+    /// Play the step of track `n` which is due (if any)
+    pub(crate) fn play_track<T: for<'u> TryFrom<&'u NotePair, Error = E>, E>(
+        &mut self,
+        n: usize,
+        output: &mut impl Output<T, E>,
+    ) -> Result<(), E> {
+        let voice = match self.voice(n) {
+            Some(voice) => voice,
+            None => return Ok(()),
+        };
+
+        let pos = match self.track_position(n) {
+            Some(pos) => pos,
+            None => {
+                if let (Some(_), Some(gate)) = (self.playback[n].last_step.take(), voice.gate) {
+                    output.set_gate(gate, false);
+                }
+                return Ok(());
+            }
+        };
+        let audible = self.tracks.audible(n);
+        let settings = self.tracks.settings[n];
+        let step_ms = self.tracks.step_ms(n, self.bpm.beat_ms());
+        let playback = &mut self.playback[n];
+
+        let beat = match playback.due_step(pos, &self.config.groove) {
+            Some(beat) => beat,
+            // gate-off for the last step has already been scheduled,
+            // but it might have more sub-triggers
+            None => {
+                if let (Some((beat, ratchet)), Some(gate), true) =
+                    (playback.due_ratchet(pos), voice.gate, audible)
+                {
+                    let selected = self
+                        .generator
+                        .as_ref()
+                        .and_then(|page| page.live_track())
+                        .unwrap_or(&self.recorder.voice_state);
+                    let track = self.tracks.get(n, selected);
+                    output.pulse_gate(
+                        gate,
+                        settings.gate_mode.gate_length(
+                            track,
+                            beat as usize % track.len(),
+                            step_ms,
+                            ratchet_velocity(
+                                self.config.groove.velocity(beat),
+                                settings.ratchet_decay,
+                                ratchet,
+                            ),
+                            ratchet,
+                        ),
+                    );
+                }
+                return Ok(());
+            }
+        };
+
+        // a generator which plays live replaces the selected track
+        if n == self.tracks.selected {
+            if let Some(page) = self.generator.as_mut() {
+                page.advance(beat, &settings.scale_lock.key);
+            }
+        }
+        let selected = self
+            .generator
+            .as_ref()
+            .and_then(|page| page.live_track())
+            .unwrap_or(&self.recorder.voice_state);
+        let track = self.tracks.get(n, selected);
+        let step = beat as usize % track.len();
+        let playback = &mut self.playback[n];
+
+        let note = match track.get_note(step) {
+            Some((Some(np), flag)) if playback.fires(track, beat, flag) => Some((np, flag)),
+            _ => None,
+        };
+        if let (Some((np, flag)), true) = (note, audible) {
+            if let Some(cv) = voice.cv {
+                output.set_glide(cv, voice.glide.for_step(flag));
+                let np = np.transposed(settings.transpose);
+                let np = settings.scale_lock.on_playback(np);
+                output.set_cv(cv, (&np).try_into()?);
+            }
+            if let Some(gate) = voice.gate {
+                output.pulse_gate(
+                    gate,
+                    settings.gate_mode.gate_length(
+                        track,
+                        step,
+                        step_ms,
+                        self.config.groove.velocity(beat),
+                        0,
+                    ),
+                );
+            }
+            playback.start_ratchets(track.get_ratchets(step));
+        }
+        Ok(())
+    }
+}
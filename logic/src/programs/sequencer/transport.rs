@@ -0,0 +1,156 @@
+use core::str::FromStr;
+
+use heapless::Deque;
+use serde::{Deserialize, Serialize};
+use ufmt::{uDisplay, uWrite, uwrite, Formatter};
+use voice_lib::ClockRatio;
+
+// 1 BPM = 10 tenths of a BPM, 60_000 ms per minute
+const TENTHS_MS_PER_BEAT: u32 = 600_000;
+
+const NUM_TAPS: usize = 4;
+const TAP_TIMEOUT_MS: u32 = 2_000;
+
+/// Tempo, in tenths of a BPM (i.e. `Bpm(1205)` is 120.5 BPM)
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub(crate) struct Bpm(u16);
+
+impl Bpm {
+    pub(crate) const MIN: Bpm = Bpm(200);
+    pub(crate) const MAX: Bpm = Bpm(3000);
+
+    pub(crate) fn from_tenths(tenths: u32) -> Self {
+        Bpm((tenths.clamp(Self::MIN.0 as u32, Self::MAX.0 as u32)) as u16)
+    }
+
+    pub(crate) fn tenths(&self) -> u32 {
+        self.0 as u32
+    }
+
+    /// Length of a beat, in ms
+    pub(crate) fn beat_ms(&self) -> u32 {
+        TENTHS_MS_PER_BEAT / self.0 as u32
+    }
+
+    pub(crate) fn change(&mut self, delta_tenths: i32) {
+        *self = Self::from_tenths((self.0 as i32 + delta_tenths).max(0) as u32);
+    }
+}
+
+impl uDisplay for Bpm {
+    fn fmt<W>(&self, fmt: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        uwrite!(fmt, "{}.{}", self.0 / 10, self.0 % 10)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct InvalidBpm;
+
+/// Parses the `uDisplay` form (`"120.5"`), or whole BPMs (`"120"`)
+impl FromStr for Bpm {
+    type Err = InvalidBpm;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (whole, tenths) = s.split_once('.').unwrap_or((s, "0"));
+        let whole: u32 = whole.parse().map_err(|_| InvalidBpm)?;
+        let tenths: u32 = match tenths.as_bytes() {
+            [d @ b'0'..=b'9'] => (d - b'0') as u32,
+            _ => return Err(InvalidBpm),
+        };
+        Ok(Self::from_tenths(whole.saturating_mul(10).saturating_add(tenths)))
+    }
+}
+
+/// Position within the sequence: the current beat plus how far we are into it.
+/// The fraction is kept as the exact remainder of `ms * tenths of BPM`, so that
+/// no rounding errors accumulate over time (or when the tempo changes).
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub(crate) struct Position {
+    pub(crate) beat: u32,
+    frac: u32,
+}
+
+impl Position {
+    pub(crate) fn at_beat(beat: u32) -> Self {
+        Self { beat, frac: 0 }
+    }
+
+    /// Move forward by `elapsed_ms` at the given tempo. Returns whether a new beat was reached.
+    pub(crate) fn advance(&mut self, elapsed_ms: u32, bpm: Bpm) -> bool {
+        let total = self.frac as u64 + elapsed_ms as u64 * bpm.tenths() as u64;
+        let beats = (total / TENTHS_MS_PER_BEAT as u64) as u32;
+        self.frac = (total % TENTHS_MS_PER_BEAT as u64) as u32;
+        self.beat += beats;
+        beats > 0
+    }
+
+    /// The same moment, counted from beat `origin`
+    pub(crate) fn since(&self, origin: u32) -> Self {
+        Self {
+            beat: self.beat - origin,
+            frac: self.frac,
+        }
+    }
+
+    /// Where a track running at `ratio` against this position is
+    pub(crate) fn scaled(&self, ratio: ClockRatio) -> Self {
+        let (beat, frac) = ratio.scale(self.beat, self.frac, TENTHS_MS_PER_BEAT);
+        Self { beat, frac }
+    }
+
+    /// Time since the origin, in thousandths of a beat
+    pub(crate) fn thousandths(&self) -> u32 {
+        self.beat * 1000 + self.phase(1000)
+    }
+
+    /// How far we are into the current beat, scaled to `0..scale`
+    pub(crate) fn phase(&self, scale: u32) -> u32 {
+        (self.frac as u64 * scale as u64 / TENTHS_MS_PER_BEAT as u64) as u32
+    }
+}
+
+pub(crate) struct TapTempo {
+    last_tap: Option<u32>,
+    intervals: Deque<u32, NUM_TAPS>,
+}
+
+impl TapTempo {
+    pub(crate) fn new() -> Self {
+        Self {
+            last_tap: None,
+            intervals: Deque::new(),
+        }
+    }
+
+    /// Register a tap at time `now` (ms). Returns the new tempo, once there
+    /// are enough taps to calculate one.
+    pub(crate) fn tap(&mut self, now: u32) -> Option<Bpm> {
+        let last_tap = self.last_tap.replace(now);
+
+        match last_tap {
+            Some(t) if now - t <= TAP_TIMEOUT_MS => {
+                if self.intervals.is_full() {
+                    self.intervals.pop_front();
+                }
+                self.intervals.push_back(now - t).ok();
+            }
+            _ => {
+                // first tap or timed out, start over
+                self.intervals.clear();
+                return None;
+            }
+        }
+
+        let total: u32 = self.intervals.iter().sum();
+        if total == 0 {
+            None
+        } else {
+            Some(Bpm::from_tenths(
+                TENTHS_MS_PER_BEAT * self.intervals.len() as u32 / total,
+            ))
+        }
+    }
+}
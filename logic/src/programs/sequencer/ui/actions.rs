@@ -3,17 +3,22 @@ use core::fmt::Debug;
 use embedded_graphics::{
     draw_target::DrawTarget,
     image::Image,
+    mono_font::MonoTextStyle,
     pixelcolor::Rgb565,
     prelude::*,
-    primitives::{PrimitiveStyle, Rectangle},
+    primitives::{Line, PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
 };
 use embedded_sdmmc::{BlockDevice, TimeSource};
+use heapless::String;
+use profont::PROFONT_10_POINT;
+use ufmt::uwrite;
 
-use crate::{programs::{sequencer::State, SequencerProgram}, util::DiscreetUnwrap, stdlib::{TaskInterface, TaskType}};
+use crate::{programs::{sequencer::{State, TempoEdit}, SequencerProgram}, util::DiscreetUnwrap, stdlib::{TaskInterface, TaskType}};
 
 use super::icons;
 
-pub(crate) const NUM_UI_ACTIONS: usize = 5;
+pub(crate) const NUM_UI_ACTIONS: usize = 10;
 
 #[derive(Copy, Clone)]
 #[repr(u8)]
@@ -23,6 +28,11 @@ pub(crate) enum UIAction {
     Record = 2,
     Beginning = 3,
     Seek = 4,
+    Menu = 5,
+    Tempo = 6,
+    Edit = 7,
+    Generate = 8,
+    Track = 9,
 }
 
 impl UIAction {
@@ -33,6 +43,21 @@ impl UIAction {
             UIAction::Record => Point::new(50, 0),
             UIAction::Beginning => Point::new(80, 0),
             UIAction::Seek => Point::new(105, 0),
+            UIAction::Menu => Point::new(133, 0),
+            UIAction::Tempo => Point::new(0, -17),
+            UIAction::Edit => Point::new(75, -17),
+            UIAction::Generate => Point::new(110, -17),
+            UIAction::Track => Point::new(144, -17),
+        }
+    }
+
+    fn button_size(&self) -> Size {
+        match self {
+            UIAction::Menu => Size::new(24, 16),
+            UIAction::Tempo => Size::new(70, 15),
+            UIAction::Edit | UIAction::Generate => Size::new(32, 15),
+            UIAction::Track => Size::new(14, 15),
+            _ => Size::new(26, 16),
         }
     }
 }
@@ -45,6 +70,11 @@ impl From<u8> for UIAction {
             2 => UIAction::Record,
             3 => UIAction::Beginning,
             4 => UIAction::Seek,
+            5 => UIAction::Menu,
+            6 => UIAction::Tempo,
+            7 => UIAction::Edit,
+            8 => UIAction::Generate,
+            9 => UIAction::Track,
             _ => unreachable!(),
         }
     }
@@ -58,7 +88,7 @@ impl<'t, B: BlockDevice, TS: TimeSource, D: DrawTarget<Color = Rgb565>, TI: Task
         D: DrawTarget<Color = Rgb565>,
     {
         Image::new(
-            if let State::Playing(_, _) = self.state {
+            if let State::Playing(_) = self.state {
                 icons::PAUSE()
             } else {
                 icons::PLAY()
@@ -78,7 +108,10 @@ impl<'t, B: BlockDevice, TS: TimeSource, D: DrawTarget<Color = Rgb565>, TI: Task
         .draw(screen)
         .duwrp();
         Image::new(
-            if let State::Recording(_, _) = self.state {
+            // flashing along with the count-in
+            if matches!(self.state, State::Recording(_))
+                || matches!(self.beat_flash(), Some((left, true)) if left > 0)
+            {
                 icons::RECORD_ON()
             } else {
                 icons::RECORD()
@@ -94,9 +127,91 @@ impl<'t, B: BlockDevice, TS: TimeSource, D: DrawTarget<Color = Rgb565>, TI: Task
             .draw(screen)
             .duwrp();
 
-        Rectangle::new(pos + self.selected_action.button_pos(), Size::new(26, 16))
-            .into_styled(PrimitiveStyle::with_stroke(Rgb565::WHITE, 1))
-            .draw(screen)
-            .duwrp();
+        // "hamburger" menu button
+        let menu_pos = pos + UIAction::Menu.button_pos();
+        for y in [4, 8, 12] {
+            Line::new(menu_pos + Point::new(6, y), menu_pos + Point::new(17, y))
+                .into_styled(PrimitiveStyle::with_stroke(Rgb565::WHITE, 2))
+                .draw(screen)
+                .duwrp();
+        }
+
+        // tempo field
+        let mut text = String::<16>::new();
+        uwrite!(text, "{} BPM", self.bpm).duwrp();
+        Text::with_baseline(
+            &text,
+            pos + UIAction::Tempo.button_pos() + Point::new(3, 2),
+            MonoTextStyle::new(
+                &PROFONT_10_POINT,
+                match self.tempo_edit {
+                    TempoEdit::Off => Rgb565::WHITE,
+                    TempoEdit::Coarse => Rgb565::YELLOW,
+                    TempoEdit::Fine => Rgb565::CSS_CORAL,
+                },
+            ),
+            Baseline::Top,
+        )
+        .draw(screen)
+        .duwrp();
+
+        // step edit field
+        Text::with_baseline(
+            "EDIT",
+            pos + UIAction::Edit.button_pos() + Point::new(4, 2),
+            MonoTextStyle::new(
+                &PROFONT_10_POINT,
+                match self.step_edit {
+                    Some(_) => Rgb565::YELLOW,
+                    None => Rgb565::WHITE,
+                },
+            ),
+            Baseline::Top,
+        )
+        .draw(screen)
+        .duwrp();
+
+        // generator page field
+        Text::with_baseline(
+            "GEN",
+            pos + UIAction::Generate.button_pos() + Point::new(7, 2),
+            MonoTextStyle::new(
+                &PROFONT_10_POINT,
+                match self.generator {
+                    Some(_) => Rgb565::YELLOW,
+                    None => Rgb565::WHITE,
+                },
+            ),
+            Baseline::Top,
+        )
+        .draw(screen)
+        .duwrp();
+
+        // selected track field
+        let mut text = String::<4>::new();
+        uwrite!(text, "{}", self.tracks.selected + 1).duwrp();
+        Text::with_baseline(
+            &text,
+            pos + UIAction::Track.button_pos() + Point::new(4, 2),
+            MonoTextStyle::new(
+                &PROFONT_10_POINT,
+                if self.tracks.overview {
+                    Rgb565::YELLOW
+                } else {
+                    Rgb565::WHITE
+                },
+            ),
+            Baseline::Top,
+        )
+        .draw(screen)
+        .duwrp();
+
+        Rectangle::new(
+            pos + self.selected_action.button_pos(),
+            self.selected_action.button_size(),
+        )
+        .into_styled(PrimitiveStyle::with_stroke(Rgb565::WHITE, 1))
+        .draw(screen)
+        .duwrp();
     }
 }
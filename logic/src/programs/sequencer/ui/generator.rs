@@ -0,0 +1,71 @@
+use core::fmt::Debug;
+
+use embedded_graphics::{
+    draw_target::DrawTarget,
+    mono_font::MonoTextStyle,
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+use embedded_sdmmc::{BlockDevice, TimeSource};
+use heapless::String;
+use profont::PROFONT_10_POINT;
+use ufmt::uwrite;
+
+use crate::{
+    programs::SequencerProgram,
+    screen::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    stdlib::TaskInterface,
+    util::DiscreetUnwrap,
+};
+
+const COLUMNS: usize = 3;
+const COLUMN_WIDTH: i32 = 53;
+const ROW_HEIGHT: i32 = 13;
+
+impl<'t, B: BlockDevice, TS: TimeSource, D: DrawTarget<Color = Rgb565>, TI: TaskInterface>
+    SequencerProgram<'t, B, TS, D, TI>
+where
+    <D as DrawTarget>::Error: Debug,
+{
+    /// Parameters of the generator page, drawn over the transport buttons
+    pub(crate) fn draw_generator_params(&self, pos: Point, screen: &mut D) {
+        let page = match &self.generator {
+            Some(page) => page,
+            None => return,
+        };
+
+        Rectangle::new(
+            Point::new(0, pos.y),
+            Size::new(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32 - pos.y as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(Rgb565::CSS_DARK_SLATE_BLUE))
+        .draw(screen)
+        .duwrp();
+
+        for (n, param) in page.params.iter().enumerate() {
+            let mut text = String::<20>::new();
+            uwrite!(text, "{} {}", param.label, page.format_param(n).as_str()).duwrp();
+
+            Text::with_baseline(
+                &text,
+                pos + Point::new(
+                    (n % COLUMNS) as i32 * COLUMN_WIDTH,
+                    (n / COLUMNS) as i32 * ROW_HEIGHT,
+                ),
+                MonoTextStyle::new(
+                    &PROFONT_10_POINT,
+                    if n == page.selected {
+                        Rgb565::YELLOW
+                    } else {
+                        Rgb565::WHITE
+                    },
+                ),
+                Baseline::Top,
+            )
+            .draw(screen)
+            .duwrp();
+        }
+    }
+}
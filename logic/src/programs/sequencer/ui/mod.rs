@@ -1,9 +1,10 @@
 pub(crate) mod actions;
+mod generator;
 pub(crate) mod overlays;
 pub(crate) mod icons;
 mod roll;
 mod score;
 
 const NOTE_HEIGHT: i32 = 4;
-const NUM_VERTICAL_NOTES: i32 = 20;
+pub(crate) const NUM_VERTICAL_NOTES: i32 = 20;
 const NUM_HORIZONTAL_BEATS: u32 = 22;
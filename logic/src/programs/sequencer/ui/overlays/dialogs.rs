@@ -13,7 +13,7 @@ use heapless::String;
 use profont::PROFONT_14_POINT;
 
 use crate::{
-    programs::{Program, SequencerProgram},
+    programs::{sequencer::data::FileFormat, SequencerProgram},
     screen::{SCREEN_HEIGHT, SCREEN_WIDTH},
     stdlib::{
         ui::{
@@ -24,92 +24,6 @@ use crate::{
     util::DiscreetUnwrap,
 };
 
-#[derive(Debug, PartialEq)]
-enum FileLoadDialogState {
-    Initializing,
-    Loading,
-    Loaded
-}
-
-pub(crate) struct FileLoadDialog<T: DrawTarget<Color = Rgb565>> {
-    sg: SelectGroup<T>,
-    file_name: String<8>,
-    state: FileLoadDialogState,
-}
-
-impl<T: DrawTarget<Color = Rgb565>> Default for FileLoadDialog<T> {
-    fn default() -> Self {
-        Self {
-            sg: SelectGroup::new(),
-            file_name: String::new(),
-
-            state: FileLoadDialogState::Initializing
-        }
-    }
-}
-
-impl<
-        't,
-        D: DrawTarget<Color = Rgb565>,
-        P: Program<'t, B, D, TS, TI>,
-        B: BlockDevice + 't,
-        TS: TimeSource + 't,
-        TI: TaskInterface + 't
-    > Overlay<'t, D, P, B, TS, TI> for FileLoadDialog<D>
-{
-    fn process_ui_input(&mut self, _input: &UIInputEvent) -> OverlayResult<'t, D, P, B, TS, TI>
-    where
-        D: 't,
-    {
-        OverlayResult::Nop
-    }
-
-    fn draw(&self, target: &mut D) -> Result<(), <D as DrawTarget>::Error> {
-        let window_style = PrimitiveStyleBuilder::new()
-            .fill_color(Rgb565::CSS_DARK_GRAY)
-            .build();
-        let text_style_title = MonoTextStyle::new(&PROFONT_14_POINT, Rgb565::YELLOW);
-
-        let rect = Rectangle::new(
-            Point::new(10, 10),
-            Size::new(SCREEN_WIDTH as u32 - 20, SCREEN_HEIGHT as u32 - 20),
-        );
-
-        // Dialog frame
-        rect.into_styled(window_style).draw(target)?;
-
-        Text::with_alignment(
-            "FOOO",
-            Point::new(SCREEN_WIDTH as i32 / 2, 23),
-            text_style_title,
-            embedded_graphics::text::Alignment::Center,
-        )
-        .draw(target)?;
-    
-        Ok(())
-    }
-
-    fn run<'u>(
-        &'u mut self,
-    ) -> Result<
-        Option<Box<dyn FnOnce(&mut P) -> Result<Vec<TaskType>, StdlibError> + 'u>>,
-        StdlibError,
-    > {
-        if self.state == FileLoadDialogState::Initializing {
-            self.state = FileLoadDialogState::Loading;
-
-            Ok(Some(Box::new(
-                |_| {
-                    let task = crate::stdlib::TaskType::DirList("data".into());
-                    Ok(alloc::vec![task])
-                },
-            )))
-        } else {
-            Ok(None)
-        }
-    }
-}
-
 #[derive(Clone, Copy, PartialEq)]
 struct OKButton;
 
@@ -152,14 +66,37 @@ impl ButtonId for CancelButton {
     }
 }
 
-pub(crate) struct FileSaveDialog<T: DrawTarget<Color = Rgb565>> {
+/// What to do with the file name entered in a `FileNameDialog`
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum FileAction {
+    Load,
+    Save,
+    Export(FileFormat),
+    Import(FileFormat),
+}
+
+impl FileAction {
+    fn title(&self) -> &'static str {
+        match self {
+            FileAction::Load => "Load File",
+            FileAction::Save => "Save File",
+            FileAction::Export(FileFormat::Midi) => "Export MIDI",
+            FileAction::Export(FileFormat::Text) => "Export Text",
+            FileAction::Import(FileFormat::Midi) => "Import MIDI",
+            FileAction::Import(FileFormat::Text) => "Import Text",
+        }
+    }
+}
+
+pub(crate) struct FileNameDialog<T: DrawTarget<Color = Rgb565>> {
     file_name: String<8>,
-    save: bool,
+    action: FileAction,
+    confirmed: bool,
     sg: SelectGroup<T>,
 }
 
-impl<T: DrawTarget<Color = Rgb565>> Default for FileSaveDialog<T> {
-    fn default() -> Self {
+impl<T: DrawTarget<Color = Rgb565>> FileNameDialog<T> {
+    pub(crate) fn new(action: FileAction) -> Self {
         let mut sg = SelectGroup::new();
         sg.add(Input::new("song01", Point::new(15, 40)));
         sg.add(Button::<OKButton>::new(OKButton, "OK", Point::new(15, 65)));
@@ -172,13 +109,14 @@ impl<T: DrawTarget<Color = Rgb565>> Default for FileSaveDialog<T> {
         Self {
             sg,
             file_name: "song01".into(),
-            save: false,
+            action,
+            confirmed: false,
         }
     }
 }
 
 impl<'t, T: DrawTarget<Color = Rgb565> + 't, B: BlockDevice + 't, TS: TimeSource + 't, TI: TaskInterface>
-    Overlay<'t, T, SequencerProgram<'t, B, TS, T, TI>, B, TS, TI> for FileSaveDialog<T>
+    Overlay<'t, T, SequencerProgram<'t, B, TS, T, TI>, B, TS, TI> for FileNameDialog<T>
 where
     T::Error: Debug,
 {
@@ -195,7 +133,7 @@ where
                     OverlayResult::Close
                 } else {
                     // OK
-                    self.save = true;
+                    self.confirmed = true;
                     OverlayResult::Close
                 }
             }
@@ -224,7 +162,7 @@ where
 
         // Title
         Text::with_alignment(
-            "Save File",
+            self.action.title(),
             Point::new(SCREEN_WIDTH as i32 / 2, 23),
             text_style_title,
             embedded_graphics::text::Alignment::Center,
@@ -247,11 +185,17 @@ where
         >>,
         StdlibError,
     > {
-        if self.save {
-            self.save = false;
+        if self.confirmed {
+            self.confirmed = false;
             Ok(Some(Box::new(
                 |program| {
-                    let task = program.save(self.file_name.clone())?;
+                    let file_name = self.file_name.clone();
+                    let task = match self.action {
+                        FileAction::Load => program.load(file_name),
+                        FileAction::Save => program.save(file_name)?,
+                        FileAction::Export(format) => program.export(file_name, format)?,
+                        FileAction::Import(format) => program.import(file_name, format),
+                    };
                     Ok(alloc::vec![task])
                 },
             )))
@@ -0,0 +1,126 @@
+use core::fmt::Debug;
+
+use alloc::{vec, vec::Vec};
+use embedded_graphics::{draw_target::DrawTarget, pixelcolor::Rgb565};
+use embedded_sdmmc::{BlockDevice, TimeSource};
+use voice_lib::{InvalidNotePair, Note, NotePair};
+
+use crate::{
+    log::warning,
+    programs::SequencerProgram,
+    stdlib::{
+        ui::{Param, ParamDialog},
+        StdlibError, TaskInterface, TaskType,
+    },
+};
+
+use super::NOTE_NAMES;
+
+fn no_params<P>(_: &P, _: &mut [Param]) {}
+
+fn check_range(res: Result<(), InvalidNotePair>) -> Result<Vec<TaskType>, StdlibError> {
+    if res.is_err() {
+        warning("Notes would go out of range, track left as it is");
+    }
+    Ok(Vec::new())
+}
+
+impl<'t, B: BlockDevice, TS: TimeSource, D: DrawTarget<Color = Rgb565>, TI: TaskInterface>
+    SequencerProgram<'t, B, TS, D, TI>
+where
+    <D as DrawTarget>::Error: Debug,
+{
+    /// Confirmation before clearing the whole track (it can still be undone)
+    pub(crate) fn clear_dialog() -> ParamDialog<Self> {
+        ParamDialog::new("Clear track?", Vec::new(), no_params, Self::clear_track)
+    }
+
+    fn clear_track(&mut self, _params: &[Param]) -> Result<Vec<TaskType>, StdlibError> {
+        let len = self.recorder.voice_state.len();
+        self.recorder.clear_range(0..len);
+        Ok(Vec::new())
+    }
+
+    pub(crate) fn transpose_dialog() -> ParamDialog<Self> {
+        ParamDialog::new(
+            "Transpose",
+            vec![Param::new("Semitones", -24, 24)],
+            |_, params| params[0].set(0),
+            |program, params| {
+                let semitones = params[0].value as i8;
+                check_range(program.recorder.transform(|t| t.transpose(semitones)))
+            },
+        )
+    }
+
+    pub(crate) fn transpose_in_key_dialog() -> ParamDialog<Self> {
+        ParamDialog::new(
+            "In key",
+            vec![Param::new("Degrees", -14, 14)],
+            |_, params| params[0].set(0),
+            |program, params| {
+                let key = program.tracks.key();
+                let degrees = params[0].value as i8;
+                check_range(
+                    program
+                        .recorder
+                        .transform(|t| t.transpose_in_key(&key, degrees)),
+                )
+            },
+        )
+    }
+
+    pub(crate) fn octave_dialog() -> ParamDialog<Self> {
+        ParamDialog::new(
+            "Octave",
+            vec![Param::new("Octaves", -4, 4)],
+            |_, params| params[0].set(0),
+            |program, params| {
+                let octaves = params[0].value as i8;
+                check_range(program.recorder.transform(|t| t.shift_octave(octaves)))
+            },
+        )
+    }
+
+    pub(crate) fn invert_dialog() -> ParamDialog<Self> {
+        ParamDialog::new(
+            "Invert",
+            vec![
+                Param::choice("Pivot", NOTE_NAMES),
+                Param::new("Octave", -1, 9),
+            ],
+            |_, params| params[1].set(4),
+            |program, params| {
+                let pivot = NotePair(Note::from_semitone(params[0].value as u8), params[1].value as i8);
+                check_range(program.recorder.transform(|t| t.invert(&pivot)))
+            },
+        )
+    }
+
+    pub(crate) fn rotate_dialog() -> ParamDialog<Self> {
+        ParamDialog::new(
+            "Rotate",
+            vec![Param::new("Steps", -64, 64)],
+            |_, params| params[0].set(0),
+            |program, params| {
+                let steps = params[0].value as isize;
+                program.recorder.transform(|t| t.rotate(steps));
+                Ok(Vec::new())
+            },
+        )
+    }
+
+    pub(crate) fn retrograde_dialog() -> ParamDialog<Self> {
+        ParamDialog::new("Retrograde?", Vec::new(), no_params, |program, _| {
+            program.recorder.transform(|t| t.retrograde());
+            Ok(Vec::new())
+        })
+    }
+
+    pub(crate) fn reverse_dialog() -> ParamDialog<Self> {
+        ParamDialog::new("Reverse?", Vec::new(), no_params, |program, _| {
+            program.recorder.transform(|t| t.reverse());
+            Ok(Vec::new())
+        })
+    }
+}
@@ -0,0 +1,205 @@
+use core::fmt::Debug;
+
+use alloc::boxed::Box;
+use embedded_graphics::{draw_target::DrawTarget, pixelcolor::Rgb565, prelude::*, Drawable};
+use embedded_sdmmc::{BlockDevice, TimeSource};
+
+use crate::{
+    impl_overlay,
+    programs::SequencerProgram,
+    stdlib::{ui::{MenuDef, MenuOptions, UIInputEvent}, TaskType, TaskInterface},
+    util::DiscreetUnwrap,
+};
+
+use super::{menus::FileMenu, transform_menu::TransformMenu};
+
+pub(crate) struct MainMenu {
+    selection: MainMenuOption,
+}
+
+impl Default for MainMenu {
+    fn default() -> Self {
+        Self {
+            selection: MainMenuOption::File,
+        }
+    }
+}
+
+pub(crate) struct MainMenuOptionError;
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub(crate) enum MainMenuOption {
+    File = 0,
+    Clear = 1,
+    Transform = 2,
+    Quantize = 3,
+    Scale = 4,
+    Glide = 5,
+    Gate = 6,
+    Clock = 7,
+    Groove = 8,
+    Arp = 9,
+    Modulation = 10,
+    Song = 11,
+    Tracks = 12,
+    Record = 13,
+    Cancel = 14,
+}
+
+impl TryFrom<i8> for MainMenuOption {
+    type Error = MainMenuOptionError;
+
+    fn try_from(val: i8) -> Result<Self, Self::Error> {
+        Ok(match val {
+            0 => MainMenuOption::File,
+            1 => MainMenuOption::Clear,
+            2 => MainMenuOption::Transform,
+            3 => MainMenuOption::Quantize,
+            4 => MainMenuOption::Scale,
+            5 => MainMenuOption::Glide,
+            6 => MainMenuOption::Gate,
+            7 => MainMenuOption::Clock,
+            8 => MainMenuOption::Groove,
+            9 => MainMenuOption::Arp,
+            10 => MainMenuOption::Modulation,
+            11 => MainMenuOption::Song,
+            12 => MainMenuOption::Tracks,
+            13 => MainMenuOption::Record,
+            14 => MainMenuOption::Cancel,
+            _ => return Err(MainMenuOptionError),
+        })
+    }
+}
+
+impl MenuOptions for MainMenu {}
+
+impl_overlay!(MainMenu, SequencerProgram);
+
+impl<'t, D: DrawTarget<Color = Rgb565> + 't, B: BlockDevice + 't, TS: TimeSource + 't, TI: TaskInterface + 't>
+    MenuDef<'t, D, SequencerProgram<'t, B, TS, D, TI>, B, TS, TI> for MainMenu
+where
+    D::Error: Debug,
+{
+    type OptionType = MainMenuOption;
+
+    fn options(&self) -> &'t [Self::OptionType]
+    where
+        Self: Sized,
+    {
+        &[
+            MainMenuOption::File,
+            MainMenuOption::Clear,
+            MainMenuOption::Transform,
+            MainMenuOption::Quantize,
+            MainMenuOption::Scale,
+            MainMenuOption::Glide,
+            MainMenuOption::Gate,
+            MainMenuOption::Clock,
+            MainMenuOption::Groove,
+            MainMenuOption::Arp,
+            MainMenuOption::Modulation,
+            MainMenuOption::Song,
+            MainMenuOption::Tracks,
+            MainMenuOption::Record,
+            MainMenuOption::Cancel,
+        ]
+    }
+    fn label(&self, option: &MainMenuOption) -> &'static str {
+        match option {
+            MainMenuOption::File => "File",
+            MainMenuOption::Clear => "Clear",
+            MainMenuOption::Transform => "Transform",
+            MainMenuOption::Quantize => "Quantize",
+            MainMenuOption::Scale => "Scale",
+            MainMenuOption::Glide => "Glide",
+            MainMenuOption::Gate => "Gate",
+            MainMenuOption::Clock => "Clock",
+            MainMenuOption::Groove => "Groove",
+            MainMenuOption::Arp => "Arp",
+            MainMenuOption::Modulation => "Mod lane",
+            MainMenuOption::Song => "Song",
+            MainMenuOption::Tracks => "Tracks",
+            MainMenuOption::Record => "Record",
+            MainMenuOption::Cancel => "Cancel",
+        }
+    }
+
+    fn selected(&self, option: &MainMenuOption) -> bool {
+        self.selection == *option
+    }
+
+    fn run_choice(
+        option: &MainMenuOption,
+    ) -> OverlayResult<'t, D, SequencerProgram<'t, B, TS, D, TI>, B, TS, TI>
+    where
+        D: 't,
+    {
+        match option {
+            MainMenuOption::File => OverlayResult::Push(Box::new(FileMenu::default())),
+            MainMenuOption::Clear => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::clear_dialog()))
+            }
+            MainMenuOption::Transform => OverlayResult::Push(Box::new(TransformMenu::default())),
+            MainMenuOption::Quantize => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::quantize_dialog()))
+            }
+            MainMenuOption::Scale => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::scale_dialog()))
+            }
+            MainMenuOption::Glide => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::glide_dialog()))
+            }
+            MainMenuOption::Gate => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::gate_dialog()))
+            }
+            MainMenuOption::Clock => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::clock_dialog()))
+            }
+            MainMenuOption::Groove => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::groove_dialog()))
+            }
+            MainMenuOption::Arp => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::arp_dialog()))
+            }
+            MainMenuOption::Modulation => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::modulation_dialog()))
+            }
+            MainMenuOption::Song => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::song_dialog()))
+            }
+            MainMenuOption::Tracks => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::tracks_dialog()))
+            }
+            MainMenuOption::Record => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::record_dialog()))
+            }
+            MainMenuOption::Cancel => OverlayResult::Close,
+        }
+    }
+
+    fn process_ui_input(
+        &mut self,
+        input: &UIInputEvent,
+    ) -> OverlayResult<'t, D, SequencerProgram<'t, B, TS, D, TI>, B, TS, TI>
+    where
+        D: 't,
+    {
+        match input {
+            UIInputEvent::EncoderTurn(v) => {
+                self.selection = (self.selection as i8 + *v)
+                    .rem_euclid(
+                        <Self as MenuDef<'t, D, SequencerProgram<'t, B, TS, D, TI>, B, TS, TI>>::options(
+                            self,
+                        )
+                        .len() as i8,
+                    )
+                    .try_into()
+                    .duwrp();
+                OverlayResult::Nop
+            }
+            UIInputEvent::EncoderSwitch(true) => Self::run_choice(&self.selection),
+            _ => OverlayResult::Nop,
+        }
+    }
+}
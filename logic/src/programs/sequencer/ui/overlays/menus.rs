@@ -6,12 +6,12 @@ use embedded_sdmmc::{BlockDevice, TimeSource};
 
 use crate::{
     impl_overlay, log,
-    programs::{SequencerProgram},
+    programs::{sequencer::data::FileFormat, SequencerProgram},
     stdlib::{ui::{MenuDef, MenuOptions, UIInputEvent}, TaskType, TaskInterface},
     util::DiscreetUnwrap,
 };
 
-use super::dialogs::{FileLoadDialog, FileSaveDialog};
+use super::dialogs::{FileAction, FileNameDialog};
 
 pub(crate) struct FileMenu {
     selection: FileMenuOption,
@@ -32,7 +32,11 @@ pub(crate) struct FileMenuOptionError;
 pub(crate) enum FileMenuOption {
     Load = 0,
     Save = 1,
-    Cancel = 2,
+    ExportMidi = 2,
+    ImportMidi = 3,
+    ExportText = 4,
+    ImportText = 5,
+    Cancel = 6,
 }
 
 impl TryFrom<i8> for FileMenuOption {
@@ -42,7 +46,11 @@ impl TryFrom<i8> for FileMenuOption {
         Ok(match val {
             0 => FileMenuOption::Load,
             1 => FileMenuOption::Save,
-            2 => FileMenuOption::Cancel,
+            2 => FileMenuOption::ExportMidi,
+            3 => FileMenuOption::ImportMidi,
+            4 => FileMenuOption::ExportText,
+            5 => FileMenuOption::ImportText,
+            6 => FileMenuOption::Cancel,
             _ => return Err(FileMenuOptionError),
         })
     }
@@ -66,6 +74,10 @@ where
         &[
             FileMenuOption::Load,
             FileMenuOption::Save,
+            FileMenuOption::ExportMidi,
+            FileMenuOption::ImportMidi,
+            FileMenuOption::ExportText,
+            FileMenuOption::ImportText,
             FileMenuOption::Cancel,
         ]
     }
@@ -73,6 +85,10 @@ where
         match option {
             FileMenuOption::Load => "Load",
             FileMenuOption::Save => "Save",
+            FileMenuOption::ExportMidi => "Export MIDI",
+            FileMenuOption::ImportMidi => "Import MIDI",
+            FileMenuOption::ExportText => "Export Text",
+            FileMenuOption::ImportText => "Import Text",
             FileMenuOption::Cancel => "Cancel",
         }
     }
@@ -90,11 +106,23 @@ where
         match option {
             FileMenuOption::Load => {
                 log::info("CHOSE 'LOAD'");
-                OverlayResult::Push(Box::new(FileLoadDialog::<D>::default()))
+                OverlayResult::Push(Box::new(FileNameDialog::new(FileAction::Load)))
             }
             FileMenuOption::Save => {
                 log::info("CHOSE 'SAVE'");
-                OverlayResult::Push(Box::new(FileSaveDialog::default()))
+                OverlayResult::Push(Box::new(FileNameDialog::new(FileAction::Save)))
+            }
+            FileMenuOption::ExportMidi => {
+                OverlayResult::Push(Box::new(FileNameDialog::new(FileAction::Export(FileFormat::Midi))))
+            }
+            FileMenuOption::ImportMidi => {
+                OverlayResult::Push(Box::new(FileNameDialog::new(FileAction::Import(FileFormat::Midi))))
+            }
+            FileMenuOption::ExportText => {
+                OverlayResult::Push(Box::new(FileNameDialog::new(FileAction::Export(FileFormat::Text))))
+            }
+            FileMenuOption::ImportText => {
+                OverlayResult::Push(Box::new(FileNameDialog::new(FileAction::Import(FileFormat::Text))))
             }
             FileMenuOption::Cancel => {
                 log::info("CHOSE 'CANCEL'");
@@ -1,3 +1,10 @@
 mod dialogs;
+mod edit;
+mod main_menu;
 mod menus;
+mod settings;
+mod transform_menu;
 
+pub(crate) use main_menu::MainMenu;
+
+const NOTE_NAMES: &[&str] = &["C", "Db", "D", "Eb", "E", "F", "Gb", "G", "Ab", "A", "Bb", "B"];
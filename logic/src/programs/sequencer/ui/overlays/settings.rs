@@ -0,0 +1,575 @@
+use core::fmt::Debug;
+
+use alloc::{vec, vec::Vec};
+use embedded_graphics::{draw_target::DrawTarget, pixelcolor::Rgb565};
+use embedded_sdmmc::{BlockDevice, TimeSource};
+
+use crate::{
+    log::error,
+    programs::{
+        sequencer::{
+            clock::{ClockMode, CLOCK_RATES, CLOCK_RATE_LABELS},
+            modulation::{ModInterpolation, ModOutput},
+            playback::GateMode,
+            metronome::{ClickOutput, MAX_COUNT_IN_BARS},
+            recorder::{Punch, RecordMode},
+            song::{SongEntry, MAX_SONG_ENTRIES, NUM_PATTERNS},
+            tracks::{TrackOutput, MAX_TRACK_LENGTH, NUM_TRACKS},
+        },
+        SequencerProgram,
+    },
+    stdlib::{
+        ui::{Param, ParamDialog},
+        GlideMode, StdlibError, TaskInterface, TaskType,
+    },
+};
+use voice_lib::{ArpMode, ClockRatio, Note, Scale, VoiceTrack, MAX_ARP_OCTAVES, MAX_CLOCK_RATIO};
+
+use super::NOTE_NAMES;
+
+const GLIDE_MODES: &[&str] = &["Time", "Rate"];
+const GATE_MODES: &[&str] = &["Length", "Fixed", "Trigger"];
+const SCALES: &[&str] = &["Major", "Minor", "Dorian", "Penta", "Chroma", "User"];
+const CLOCK_MODES: &[&str] = &["Off", "Clock", "Reset"];
+const ARP_MODES: &[&str] = &["Up", "Down", "Up/Down", "Random", "Played"];
+const MOD_OUTPUTS: &[&str] = &["Off", "CV1", "CV0"];
+const MOD_INTERPOLATIONS: &[&str] = &["Step", "Hold", "Slew"];
+const RECORD_MODES: &[&str] = &["Overdub", "Replace", "Step"];
+const CLICK_OUTPUTS: &[&str] = &["Off", "Gate0", "Gate1"];
+// "-" ends the song
+const SONG_PATTERNS: &[&str] = &[
+    "-", "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13", "14", "15", "16",
+];
+const SONG_ENTRIES: &[&str] = &["-", "1", "2", "3", "4", "5", "6", "7", "8"];
+const SONG_ENTRY_LABELS: [(&str, &str); MAX_SONG_ENTRIES] = [
+    ("1 Pattern", "1 Repeats"),
+    ("2 Pattern", "2 Repeats"),
+    ("3 Pattern", "3 Repeats"),
+    ("4 Pattern", "4 Repeats"),
+    ("5 Pattern", "5 Repeats"),
+    ("6 Pattern", "6 Repeats"),
+    ("7 Pattern", "7 Repeats"),
+    ("8 Pattern", "8 Repeats"),
+];
+const MAX_SONG_REPEATS: i32 = 16;
+// params before the song entries
+const SONG_HEADER: usize = 3;
+const MIDI_CHANNELS: &[&str] = &[
+    "Any", "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13", "14", "15", "16",
+];
+const TRACK_OUTPUTS: &[&str] = &["Off", "G0/CV0", "G1/CV1"];
+const TRACK_LABELS: [[&str; TRACK_PARAMS]; NUM_TRACKS] = [
+    ["1 Length", "1 Mult", "1 Div", "1 MIDI ch", "1 Output", "1 Transpose", "1 Mute", "1 Solo"],
+    ["2 Length", "2 Mult", "2 Div", "2 MIDI ch", "2 Output", "2 Transpose", "2 Mute", "2 Solo"],
+    ["3 Length", "3 Mult", "3 Div", "3 MIDI ch", "3 Output", "3 Transpose", "3 Mute", "3 Solo"],
+    ["4 Length", "4 Mult", "4 Div", "4 MIDI ch", "4 Output", "4 Transpose", "4 Mute", "4 Solo"],
+];
+const TRACK_PARAMS: usize = 8;
+
+impl<'t, B: BlockDevice, TS: TimeSource, D: DrawTarget<Color = Rgb565>, TI: TaskInterface>
+    SequencerProgram<'t, B, TS, D, TI>
+where
+    <D as DrawTarget>::Error: Debug,
+{
+    pub(crate) fn glide_dialog() -> ParamDialog<Self> {
+        let mut params = Vec::new();
+        for (glide, mode, time, legato) in [
+            ("CV0 glide", "CV0 mode", "CV0 ms", "CV0 legato"),
+            ("CV1 glide", "CV1 mode", "CV1 ms", "CV1 legato"),
+        ] {
+            params.push(Param::toggle(glide));
+            params.push(Param::choice(mode, GLIDE_MODES));
+            params.push(Param::new(time, 0, 2000).with_step(10));
+            params.push(Param::toggle(legato));
+        }
+        ParamDialog::new("Glide", params, Self::load_glide, Self::store_glide)
+    }
+
+    fn load_glide(&self, params: &mut [Param]) {
+        for (settings, params) in self.config.glide.iter().zip(params.chunks_mut(4)) {
+            let (mode, ms) = match settings.mode {
+                GlideMode::Time(ms) => (0, ms),
+                GlideMode::Rate(ms) => (1, ms),
+            };
+            params[0].set(settings.enabled as i32);
+            params[1].set(mode);
+            params[2].set(ms as i32);
+            params[3].set(settings.legato_only as i32);
+        }
+    }
+
+    fn store_glide(&mut self, params: &[Param]) -> Result<Vec<TaskType>, StdlibError> {
+        for (settings, params) in self.config.glide.iter_mut().zip(params.chunks(4)) {
+            let ms = params[2].value as u16;
+            settings.enabled = params[0].enabled();
+            settings.mode = match params[1].value {
+                0 => GlideMode::Time(ms),
+                _ => GlideMode::Rate(ms),
+            };
+            settings.legato_only = params[3].enabled();
+        }
+        Ok(vec![self.save_config()?])
+    }
+
+    /// Gate settings of the selected track
+    pub(crate) fn gate_dialog() -> ParamDialog<Self> {
+        ParamDialog::new(
+            "Gate",
+            vec![
+                Param::choice("Mode", GATE_MODES),
+                Param::new("Length %", 1, 100),
+                Param::new("Fixed ms", 1, 2000).with_step(5),
+                Param::new("Ratchet decay %", 0, 50).with_step(5),
+            ],
+            Self::load_gate,
+            Self::store_gate,
+        )
+    }
+
+    fn load_gate(&self, params: &mut [Param]) {
+        // keep the defaults for the fields that don't apply to the current mode
+        params[1].set(75);
+        params[2].set(100);
+        let settings = &self.tracks.settings[self.tracks.selected];
+        params[3].set(settings.ratchet_decay as i32);
+        match settings.gate_mode {
+            GateMode::Length(pct) => {
+                params[0].set(0);
+                params[1].set(pct as i32);
+            }
+            GateMode::Fixed(ms) => {
+                params[0].set(1);
+                params[2].set(ms as i32);
+            }
+            GateMode::Trigger => {
+                params[0].set(2);
+            }
+        }
+    }
+
+    fn store_gate(&mut self, params: &[Param]) -> Result<Vec<TaskType>, StdlibError> {
+        let settings = &mut self.tracks.settings[self.tracks.selected];
+        settings.gate_mode = match params[0].value {
+            0 => GateMode::Length(params[1].value as u8),
+            1 => GateMode::Fixed(params[2].value as u16),
+            _ => GateMode::Trigger,
+        };
+        settings.ratchet_decay = params[3].value as u8;
+        Ok(Vec::new())
+    }
+
+    pub(crate) fn clock_dialog() -> ParamDialog<Self> {
+        let mut params = Vec::new();
+        for (mode, rate, width, free_run) in [
+            ("Gate0 clock", "Gate0 rate", "Gate0 width", "Gate0 free"),
+            ("Gate1 clock", "Gate1 rate", "Gate1 width", "Gate1 free"),
+        ] {
+            params.push(Param::choice(mode, CLOCK_MODES));
+            params.push(Param::choice(rate, CLOCK_RATE_LABELS));
+            params.push(Param::new(width, 1, 500));
+            params.push(Param::toggle(free_run));
+        }
+        ParamDialog::new("Clock", params, Self::load_clock, Self::store_clock)
+    }
+
+    fn load_clock(&self, params: &mut [Param]) {
+        for (settings, params) in self.config.clock.iter().zip(params.chunks_mut(4)) {
+            params[0].set(settings.mode as i32);
+            params[1].set(
+                CLOCK_RATES
+                    .iter()
+                    .position(|rate| *rate == settings.rate)
+                    .unwrap_or(3) as i32,
+            );
+            params[2].set(settings.pulse_ms as i32);
+            params[3].set(settings.free_run as i32);
+        }
+    }
+
+    fn store_clock(&mut self, params: &[Param]) -> Result<Vec<TaskType>, StdlibError> {
+        for (settings, params) in self.config.clock.iter_mut().zip(params.chunks(4)) {
+            settings.mode = match params[0].value {
+                0 => ClockMode::Off,
+                1 => ClockMode::Clock,
+                _ => ClockMode::Reset,
+            };
+            settings.rate = CLOCK_RATES[params[1].value as usize];
+            settings.pulse_ms = params[2].value as u16;
+            settings.free_run = params[3].enabled();
+        }
+        Ok(vec![self.save_config()?])
+    }
+
+    pub(crate) fn groove_dialog() -> ParamDialog<Self> {
+        ParamDialog::new(
+            "Groove",
+            vec![
+                Param::new("Swing %", 50, 75),
+                // 0 = no template, bounded by the templates in the config once loaded
+                Param::new("Template", 0, 0),
+            ],
+            Self::load_groove,
+            Self::store_groove,
+        )
+    }
+
+    fn load_groove(&self, params: &mut [Param]) {
+        let groove = &self.config.groove;
+        params[0].set(groove.swing as i32);
+        params[1].set_max(groove.templates.len() as i32);
+        params[1].set(groove.template.map_or(0, |n| n as i32 + 1));
+    }
+
+    fn store_groove(&mut self, params: &[Param]) -> Result<Vec<TaskType>, StdlibError> {
+        let groove = &mut self.config.groove;
+        groove.swing = params[0].value as u8;
+        groove.template = match params[1].value as usize {
+            0 => None,
+            n if n <= groove.templates.len() => Some(n - 1),
+            _ => None,
+        };
+        Ok(vec![self.save_config()?])
+    }
+
+    pub(crate) fn quantize_dialog() -> ParamDialog<Self> {
+        ParamDialog::new(
+            "Quantize",
+            vec![
+                Param::new("Strength %", 0, 100).with_step(5),
+                Param::new("Window %", 0, 50).with_step(5),
+                // re-process the last recording pass with the new settings
+                Param::toggle("Apply to take"),
+            ],
+            Self::load_quantize,
+            Self::store_quantize,
+        )
+    }
+
+    fn load_quantize(&self, params: &mut [Param]) {
+        params[0].set(self.recorder.quantize.strength as i32);
+        params[1].set(self.recorder.quantize.window as i32);
+    }
+
+    fn store_quantize(&mut self, params: &[Param]) -> Result<Vec<TaskType>, StdlibError> {
+        self.recorder.quantize.strength = params[0].value as u8;
+        self.recorder.quantize.window = params[1].value as u8;
+        if params[2].enabled() {
+            self.recorder.requantize();
+        }
+        Ok(Vec::new())
+    }
+
+    pub(crate) fn record_dialog() -> ParamDialog<Self> {
+        ParamDialog::new(
+            "Record",
+            vec![
+                Param::choice("Mode", RECORD_MODES),
+                Param::new("Count-in bars", 0, MAX_COUNT_IN_BARS as i32),
+                Param::choice("Click output", CLICK_OUTPUTS),
+                Param::toggle("Click on rec"),
+                // recording only takes effect from the punch-in step to the punch-out one
+                Param::toggle("Punch"),
+                Param::new("Punch in", 1, MAX_TRACK_LENGTH as i32),
+                Param::new("Punch out", 1, MAX_TRACK_LENGTH as i32),
+            ],
+            Self::load_record,
+            Self::store_record,
+        )
+    }
+
+    fn load_record(&self, params: &mut [Param]) {
+        let metronome = &self.config.metronome;
+        params[0].set(self.recorder.mode as i32);
+        params[1].set(metronome.count_in as i32);
+        params[2].set(metronome.output as i32);
+        params[3].set(metronome.while_recording as i32);
+        let len = self.recorder.voice_state.len();
+        let punch = self.recorder.punch.unwrap_or(Punch {
+            start: 0,
+            end: len.saturating_sub(1),
+        });
+        params[4].set(self.recorder.punch.is_some() as i32);
+        // punch points are steps of the selected track
+        params[5].set_max(len as i32);
+        params[6].set_max(len as i32);
+        params[5].set(punch.start as i32 + 1);
+        params[6].set(punch.end as i32 + 1);
+    }
+
+    fn store_record(&mut self, params: &[Param]) -> Result<Vec<TaskType>, StdlibError> {
+        self.recorder.mode = match params[0].value {
+            0 => RecordMode::Overdub,
+            1 => RecordMode::Replace,
+            _ => RecordMode::Step,
+        };
+        self.recorder.punch = params[4].enabled().then(|| Punch {
+            start: params[5].value as usize - 1,
+            end: params[6].value as usize - 1,
+        });
+
+        let metronome = &mut self.config.metronome;
+        metronome.count_in = params[1].value as u8;
+        metronome.output = match params[2].value {
+            0 => ClickOutput::Off,
+            1 => ClickOutput::Gate0,
+            _ => ClickOutput::Gate1,
+        };
+        metronome.while_recording = params[3].enabled();
+        Ok(vec![self.save_config()?])
+    }
+
+    /// Scale lock of the selected track
+    pub(crate) fn scale_dialog() -> ParamDialog<Self> {
+        ParamDialog::new(
+            "Scale",
+            vec![
+                Param::choice("Root", NOTE_NAMES),
+                Param::choice("Scale", SCALES),
+                // one bit per semitone above the root
+                Param::new("User mask", 0, 0xfff),
+                Param::toggle("Lock rec"),
+                Param::toggle("Lock play"),
+            ],
+            Self::load_scale,
+            Self::store_scale,
+        )
+    }
+
+    fn load_scale(&self, params: &mut [Param]) {
+        let lock = &self.tracks.settings[self.tracks.selected].scale_lock;
+        params[0].set(lock.key.root as i32);
+        params[1].set(match lock.key.scale {
+            Scale::Major => 0,
+            Scale::Minor => 1,
+            Scale::Dorian => 2,
+            Scale::Pentatonic => 3,
+            Scale::Chromatic => 4,
+            Scale::User(_) => 5,
+        });
+        params[2].set(lock.key.scale.mask() as i32);
+        params[3].set(lock.record as i32);
+        params[4].set(lock.playback as i32);
+    }
+
+    fn store_scale(&mut self, params: &[Param]) -> Result<Vec<TaskType>, StdlibError> {
+        let lock = &mut self.tracks.settings[self.tracks.selected].scale_lock;
+        lock.key.root = Note::from_semitone(params[0].value as u8);
+        lock.key.scale = match params[1].value {
+            0 => Scale::Major,
+            1 => Scale::Minor,
+            2 => Scale::Dorian,
+            3 => Scale::Pentatonic,
+            4 => Scale::Chromatic,
+            _ => Scale::User(params[2].value as u16),
+        };
+        lock.record = params[3].enabled();
+        lock.playback = params[4].enabled();
+        Ok(Vec::new())
+    }
+
+    pub(crate) fn arp_dialog() -> ParamDialog<Self> {
+        ParamDialog::new(
+            "Arpeggiator",
+            vec![
+                Param::toggle("Arp"),
+                Param::choice("Mode", ARP_MODES),
+                Param::new("Octaves", 1, MAX_ARP_OCTAVES as i32),
+                Param::choice("Rate", CLOCK_RATE_LABELS),
+                Param::new("Gate %", 10, 100).with_step(10),
+                Param::toggle("Latch"),
+            ],
+            Self::load_arp,
+            Self::store_arp,
+        )
+    }
+
+    fn load_arp(&self, params: &mut [Param]) {
+        let arp = &self.config.arp;
+        params[0].set(arp.enabled as i32);
+        params[1].set(arp.mode as i32);
+        params[2].set(arp.octaves as i32);
+        params[3].set(
+            CLOCK_RATES
+                .iter()
+                .position(|rate| *rate == arp.rate)
+                .unwrap_or(2) as i32,
+        );
+        params[4].set(arp.gate as i32);
+        params[5].set(arp.latch as i32);
+    }
+
+    fn store_arp(&mut self, params: &[Param]) -> Result<Vec<TaskType>, StdlibError> {
+        let arp = &mut self.config.arp;
+        arp.enabled = params[0].enabled();
+        arp.mode = match params[1].value {
+            0 => ArpMode::Up,
+            1 => ArpMode::Down,
+            2 => ArpMode::UpDown,
+            3 => ArpMode::Random,
+            _ => ArpMode::AsPlayed,
+        };
+        arp.octaves = params[2].value as u8;
+        arp.rate = CLOCK_RATES[params[3].value as usize];
+        arp.gate = params[4].value as u8;
+        arp.latch = params[5].enabled();
+
+        // notes which are only latched (or held while disabling) would keep on playing
+        if !arp.enabled || !arp.latch {
+            self.arp.clear();
+        }
+        Ok(vec![self.save_config()?])
+    }
+
+    pub(crate) fn modulation_dialog() -> ParamDialog<Self> {
+        ParamDialog::new(
+            "Mod lane",
+            vec![
+                Param::choice("Output", MOD_OUTPUTS),
+                Param::choice("Between steps", MOD_INTERPOLATIONS),
+                Param::new("Record CC", 0, 127),
+            ],
+            Self::load_modulation,
+            Self::store_modulation,
+        )
+    }
+
+    fn load_modulation(&self, params: &mut [Param]) {
+        let lane = &self.config.modulation;
+        params[0].set(lane.output as i32);
+        params[1].set(lane.interpolation as i32);
+        params[2].set(lane.cc as i32);
+    }
+
+    fn store_modulation(&mut self, params: &[Param]) -> Result<Vec<TaskType>, StdlibError> {
+        let lane = &mut self.config.modulation;
+        lane.output = match params[0].value {
+            0 => ModOutput::Off,
+            1 => ModOutput::CV1,
+            _ => ModOutput::CV0,
+        };
+        lane.interpolation = match params[1].value {
+            0 => ModInterpolation::Step,
+            1 => ModInterpolation::Hold,
+            _ => ModInterpolation::Slew,
+        };
+        lane.cc = params[2].value as u8;
+        Ok(vec![self.save_config()?])
+    }
+
+    pub(crate) fn song_dialog() -> ParamDialog<Self> {
+        let mut params = vec![
+            Param::new("Pattern", 1, NUM_PATTERNS as i32),
+            Param::toggle("Song mode"),
+            // jump to an entry of the song, on the next bar
+            Param::choice("Go to entry", SONG_ENTRIES),
+        ];
+        for (pattern, repeats) in SONG_ENTRY_LABELS {
+            params.push(Param::choice(pattern, SONG_PATTERNS));
+            params.push(Param::new(repeats, 1, MAX_SONG_REPEATS));
+        }
+        ParamDialog::new("Song", params, Self::load_song, Self::store_song)
+    }
+
+    fn load_song(&self, params: &mut [Param]) {
+        let patterns = &self.patterns;
+        params[0].set(patterns.queued.unwrap_or(patterns.current) as i32 + 1);
+        params[1].set(patterns.song_mode as i32);
+        for (entry, params) in patterns.song.iter().zip(params[SONG_HEADER..].chunks_mut(2)) {
+            params[0].set(entry.pattern as i32 + 1);
+            params[1].set(entry.repeats as i32);
+        }
+    }
+
+    fn store_song(&mut self, params: &[Param]) -> Result<Vec<TaskType>, StdlibError> {
+        let patterns = &mut self.patterns;
+        patterns.song.clear();
+        for params in params[SONG_HEADER..].chunks(2) {
+            if params[0].value == 0 {
+                break;
+            }
+            patterns
+                .song
+                .push(SongEntry {
+                    pattern: params[0].value as u8 - 1,
+                    repeats: params[1].value as u8,
+                })
+                .ok();
+        }
+        patterns.song_mode = params[1].enabled();
+
+        let pattern = params[0].value as u8 - 1;
+        if params[2].value > 0 {
+            patterns.queue_entry(params[2].value as usize - 1);
+        } else if pattern != patterns.current {
+            patterns.queue(pattern);
+        }
+        self.switch_if_stopped();
+        Ok(Vec::new())
+    }
+
+    pub(crate) fn tracks_dialog() -> ParamDialog<Self> {
+        let mut params = vec![Param::toggle("Overview"), Param::toggle("Reset tracks")];
+        for [length, mult, div, channel, output, transpose, mute, solo] in TRACK_LABELS {
+            params.push(Param::new(length, 1, MAX_TRACK_LENGTH as i32));
+            params.push(Param::new(mult, 1, MAX_CLOCK_RATIO as i32));
+            params.push(Param::new(div, 1, MAX_CLOCK_RATIO as i32));
+            params.push(Param::choice(channel, MIDI_CHANNELS));
+            params.push(Param::choice(output, TRACK_OUTPUTS));
+            params.push(Param::new(transpose, -24, 24));
+            params.push(Param::toggle(mute));
+            params.push(Param::toggle(solo));
+        }
+        ParamDialog::new("Tracks", params, Self::load_tracks, Self::store_tracks)
+    }
+
+    fn load_tracks(&self, params: &mut [Param]) {
+        params[0].set(self.tracks.overview as i32);
+        params[1].set(0);
+        for (n, params) in params[2..].chunks_mut(TRACK_PARAMS).enumerate() {
+            let settings = &self.tracks.settings[n];
+            params[0].set(self.track(n).len() as i32);
+            params[1].set(settings.ratio.mult as i32);
+            params[2].set(settings.ratio.div as i32);
+            params[3].set(settings.channel as i32);
+            params[4].set(settings.output as i32);
+            params[5].set(settings.transpose as i32);
+            params[6].set(settings.mute as i32);
+            params[7].set(settings.solo as i32);
+        }
+    }
+
+    fn store_tracks(&mut self, params: &[Param]) -> Result<Vec<TaskType>, StdlibError> {
+        self.tracks.overview = params[0].enabled();
+        for (n, params) in params[2..].chunks(TRACK_PARAMS).enumerate() {
+            let len = params[0].value as usize;
+            let current = self.track(n).len();
+            if len != current {
+                let grow = VoiceTrack::size_for(len).saturating_sub(VoiceTrack::size_for(current));
+                if !self.tracks_fit(grow) {
+                    error("Not enough memory for a longer track");
+                } else {
+                    match self.tracks.get_mut(n) {
+                        Some(track) => track.resize(len),
+                        None => self.recorder.resize(len),
+                    }
+                }
+            }
+
+            let settings = &mut self.tracks.settings[n];
+            settings.ratio = ClockRatio::new(params[1].value as u8, params[2].value as u8);
+            settings.channel = params[3].value as u8;
+            settings.output = match params[4].value {
+                0 => TrackOutput::Off,
+                1 => TrackOutput::Voice0,
+                _ => TrackOutput::Voice1,
+            };
+            settings.transpose = params[5].value as i8;
+            settings.mute = params[6].enabled();
+            settings.solo = params[7].enabled();
+        }
+        if params[1].enabled() {
+            self.reset_tracks();
+        }
+        Ok(Vec::new())
+    }
+}
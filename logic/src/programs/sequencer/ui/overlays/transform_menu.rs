@@ -0,0 +1,158 @@
+use core::fmt::Debug;
+
+use alloc::boxed::Box;
+use embedded_graphics::{draw_target::DrawTarget, pixelcolor::Rgb565, prelude::*, Drawable};
+use embedded_sdmmc::{BlockDevice, TimeSource};
+
+use crate::{
+    impl_overlay,
+    programs::SequencerProgram,
+    stdlib::{ui::{MenuDef, MenuOptions, UIInputEvent}, TaskType, TaskInterface},
+    util::DiscreetUnwrap,
+};
+
+pub(crate) struct TransformMenu {
+    selection: TransformMenuOption,
+}
+
+impl Default for TransformMenu {
+    fn default() -> Self {
+        Self {
+            selection: TransformMenuOption::Transpose,
+        }
+    }
+}
+
+pub(crate) struct TransformMenuOptionError;
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub(crate) enum TransformMenuOption {
+    Transpose = 0,
+    InKey = 1,
+    Octave = 2,
+    Invert = 3,
+    Retrograde = 4,
+    Reverse = 5,
+    Rotate = 6,
+    Cancel = 7,
+}
+
+impl TryFrom<i8> for TransformMenuOption {
+    type Error = TransformMenuOptionError;
+
+    fn try_from(val: i8) -> Result<Self, Self::Error> {
+        Ok(match val {
+            0 => TransformMenuOption::Transpose,
+            1 => TransformMenuOption::InKey,
+            2 => TransformMenuOption::Octave,
+            3 => TransformMenuOption::Invert,
+            4 => TransformMenuOption::Retrograde,
+            5 => TransformMenuOption::Reverse,
+            6 => TransformMenuOption::Rotate,
+            7 => TransformMenuOption::Cancel,
+            _ => return Err(TransformMenuOptionError),
+        })
+    }
+}
+
+impl MenuOptions for TransformMenu {}
+
+impl_overlay!(TransformMenu, SequencerProgram);
+
+impl<'t, D: DrawTarget<Color = Rgb565> + 't, B: BlockDevice + 't, TS: TimeSource + 't, TI: TaskInterface + 't>
+    MenuDef<'t, D, SequencerProgram<'t, B, TS, D, TI>, B, TS, TI> for TransformMenu
+where
+    D::Error: Debug,
+{
+    type OptionType = TransformMenuOption;
+
+    fn options(&self) -> &'t [Self::OptionType]
+    where
+        Self: Sized,
+    {
+        &[
+            TransformMenuOption::Transpose,
+            TransformMenuOption::InKey,
+            TransformMenuOption::Octave,
+            TransformMenuOption::Invert,
+            TransformMenuOption::Retrograde,
+            TransformMenuOption::Reverse,
+            TransformMenuOption::Rotate,
+            TransformMenuOption::Cancel,
+        ]
+    }
+    fn label(&self, option: &TransformMenuOption) -> &'static str {
+        match option {
+            TransformMenuOption::Transpose => "Transpose",
+            TransformMenuOption::InKey => "In key",
+            TransformMenuOption::Octave => "Octave",
+            TransformMenuOption::Invert => "Invert",
+            TransformMenuOption::Retrograde => "Retrograde",
+            TransformMenuOption::Reverse => "Reverse",
+            TransformMenuOption::Rotate => "Rotate",
+            TransformMenuOption::Cancel => "Cancel",
+        }
+    }
+
+    fn selected(&self, option: &TransformMenuOption) -> bool {
+        self.selection == *option
+    }
+
+    fn run_choice(
+        option: &TransformMenuOption,
+    ) -> OverlayResult<'t, D, SequencerProgram<'t, B, TS, D, TI>, B, TS, TI>
+    where
+        D: 't,
+    {
+        match option {
+            TransformMenuOption::Transpose => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::transpose_dialog()))
+            }
+            TransformMenuOption::InKey => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::transpose_in_key_dialog()))
+            }
+            TransformMenuOption::Octave => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::octave_dialog()))
+            }
+            TransformMenuOption::Invert => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::invert_dialog()))
+            }
+            TransformMenuOption::Retrograde => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::retrograde_dialog()))
+            }
+            TransformMenuOption::Reverse => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::reverse_dialog()))
+            }
+            TransformMenuOption::Rotate => {
+                OverlayResult::Push(Box::new(SequencerProgram::<'t, B, TS, D, TI>::rotate_dialog()))
+            }
+            TransformMenuOption::Cancel => OverlayResult::Close,
+        }
+    }
+
+    fn process_ui_input(
+        &mut self,
+        input: &UIInputEvent,
+    ) -> OverlayResult<'t, D, SequencerProgram<'t, B, TS, D, TI>, B, TS, TI>
+    where
+        D: 't,
+    {
+        match input {
+            UIInputEvent::EncoderTurn(v) => {
+                self.selection = (self.selection as i8 + *v)
+                    .rem_euclid(
+                        <Self as MenuDef<'t, D, SequencerProgram<'t, B, TS, D, TI>, B, TS, TI>>::options(
+                            self,
+                        )
+                        .len() as i8,
+                    )
+                    .try_into()
+                    .duwrp();
+                OverlayResult::Nop
+            }
+            UIInputEvent::EncoderSwitch(true) => Self::run_choice(&self.selection),
+            _ => OverlayResult::Nop,
+        }
+    }
+}
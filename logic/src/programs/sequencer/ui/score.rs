@@ -5,14 +5,28 @@ use core::{
 };
 use embedded_graphics::{
     draw_target::DrawTarget,
+    mono_font::MonoTextStyle,
     pixelcolor::Rgb565,
     prelude::*,
-    primitives::{Line, PrimitiveStyleBuilder, Rectangle},
+    primitives::{Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
 };
 use embedded_sdmmc::{BlockDevice, TimeSource};
-use voice_lib::{NoteFlag, NotePair};
+use heapless::String;
+use profont::{PROFONT_10_POINT, PROFONT_14_POINT};
+use ufmt::uwrite;
+use voice_lib::{NoteFlag, TrigCondition, VoiceTrack, MAX_MOD_VALUE, MAX_RATCHETS};
 
-use crate::{programs::SequencerProgram, screen::SCREEN_WIDTH};
+use crate::{
+    programs::{
+        sequencer::{
+            modulation::ModOutput, recorder::RecordMode, step_edit::StepEditMode,
+            tracks::NUM_TRACKS, transport::Position,
+        },
+        SequencerProgram,
+    },
+    screen::SCREEN_WIDTH,
+};
 
 use super::{
     roll::{draw_piano_roll, ROLL_HEIGHT, ROLL_WIDTH},
@@ -21,6 +35,12 @@ use super::{
 
 const SCORE_WIDTH: u32 = SCREEN_WIDTH as u32 - ROLL_WIDTH as u32;
 const PIXELS_PER_BEAT: u32 = SCORE_WIDTH / NUM_HORIZONTAL_BEATS;
+/// The modulation lane goes below the transport buttons
+const MOD_LANE_TOP: i32 = 117;
+const MOD_LANE_HEIGHT: u32 = 11;
+const MOD_LANE_COLOR: Rgb565 = Rgb565::CSS_VIOLET;
+/// Height of each track in the overview
+const OVERVIEW_LANE_HEIGHT: i32 = ROLL_HEIGHT / NUM_TRACKS as i32;
 
 impl<'t, B: BlockDevice, TS: TimeSource, D: DrawTarget<Color = Rgb565>, TI: TaskInterface>
     SequencerProgram<'t, B, TS, D, TI>
@@ -28,37 +48,216 @@ where
     <D as DrawTarget>::Error: Debug,
 {
     pub(crate) fn _render_screen(&self, screen: &mut D) {
-        let (current_time, beat) = self.state.get_time();
-        let start_time =
-            current_time as i32 - (NUM_HORIZONTAL_BEATS as i32 / 2 * 60_000 / (self.bpm as i32));
-        let start_beat = beat.saturating_sub(NUM_HORIZONTAL_BEATS / 2) as usize;
+        // while step-editing (or step-recording), the view follows the cursor instead of the transport
+        let position = match (&self.step_edit, self.recorder.mode) {
+            (Some(edit), _) => edit.position(),
+            (None, RecordMode::Step) => Position::at_beat(self.recorder.cursor as u32),
+            (None, _) => self.tracks.position(self.tracks.selected, self.state.position()),
+        };
+        let (start_x, start_beat) = view_start(position);
         screen.clear(Rgb565::CSS_DARK_SLATE_BLUE).unwrap();
-        draw_piano_roll(0, self.current_note, screen);
-        self.draw_grid(0, start_time, start_beat as u32, screen);
-
-        self.draw_notes(
-            0,
-            self.current_note,
-            start_time,
-            self.recorder
-                .iter_notes_since(start_beat, NUM_HORIZONTAL_BEATS as usize + 1),
-            screen,
-        );
+
+        // the generator page previews its pattern instead of the track
+        let track = match &self.generator {
+            Some(page) => &page.preview,
+            None => &self.recorder.voice_state,
+        };
+        // editing always happens on the selected track
+        if self.tracks.overview && self.step_edit.is_none() && self.generator.is_none() {
+            self.draw_overview(0, start_x, start_beat, screen);
+        } else {
+            draw_piano_roll(0, self.current_note, screen);
+            self.draw_grid(0, start_x, start_beat as u32, screen);
+            self.draw_notes(0, self.current_note, start_x, track, start_beat, screen);
+        }
+        self.draw_punch(0, start_x, start_beat, screen);
+        self.draw_mod_lane(MOD_LANE_TOP, start_x, track, start_beat, screen);
         self.draw_cursor(0, screen);
-        self.draw_buttons(Point::new(10, 100), screen);
+        self.draw_step_edit_cursor(0, screen);
+        self.draw_buttons(Point::new(2, 100), screen);
+        self.draw_generator_params(Point::new(2, 100), screen);
+        self.draw_pattern(Point::new(SCREEN_WIDTH as i32 - 2, 1), screen);
+        self.draw_beat_flash(screen);
+    }
+
+    /// Flashes on every beat of the metronome, showing how many beats are left to count in
+    pub(crate) fn draw_beat_flash(&self, screen: &mut D) {
+        let (left, flash) = match self.beat_flash() {
+            Some(beat) => beat,
+            None => return,
+        };
+        if flash {
+            Rectangle::new(Point::zero(), Size::new(SCREEN_WIDTH as u32, ROLL_HEIGHT as u32 + 1))
+                .into_styled(PrimitiveStyle::with_stroke(Rgb565::RED, 2))
+                .draw(screen)
+                .unwrap();
+        }
+        if left == 0 {
+            return;
+        }
+
+        let mut text = String::<4>::new();
+        uwrite!(text, "{}", left).duwrp();
+        Text::with_text_style(
+            &text,
+            Point::new(ROLL_WIDTH + 1 + SCORE_WIDTH as i32 / 2, ROLL_HEIGHT / 2),
+            MonoTextStyle::new(
+                &PROFONT_14_POINT,
+                if flash { Rgb565::RED } else { Rgb565::WHITE },
+            ),
+            TextStyleBuilder::new()
+                .alignment(Alignment::Center)
+                .baseline(Baseline::Middle)
+                .build(),
+        )
+        .draw(screen)
+        .duwrp();
+    }
+
+    /// A line over the steps recording is limited to, if it is
+    pub(crate) fn draw_punch(&self, top: i32, start_x: i32, start_beat: usize, screen: &mut D) {
+        let punch = match self.recorder.punch {
+            Some(punch) => punch,
+            None => return,
+        };
+        let len = self.recorder.voice_state.len();
+        for beat in start_beat..(start_beat + NUM_HORIZONTAL_BEATS as usize + 1) {
+            if !punch.contains(beat % len) {
+                continue;
+            }
+            let beat_x = (beat as u32 * PIXELS_PER_BEAT) as i32 - start_x;
+            if beat_x < 0 || beat_x + PIXELS_PER_BEAT as i32 >= SCORE_WIDTH as i32 {
+                continue;
+            }
+            Line::new(
+                Point::new(ROLL_WIDTH + 1 + beat_x, top + 1),
+                Point::new(ROLL_WIDTH + 1 + beat_x + PIXELS_PER_BEAT as i32, top + 1),
+            )
+            .into_styled(PrimitiveStyle::with_stroke(Rgb565::RED, 1))
+            .draw(screen)
+            .unwrap();
+        }
+    }
+
+    /// Current pattern (and song entry, in song mode), plus the one queued next.
+    /// `pos` is the top right corner.
+    pub(crate) fn draw_pattern(&self, pos: Point, screen: &mut D) {
+        let patterns = &self.patterns;
+        let mut text = String::<16>::new();
+        if patterns.song_mode {
+            uwrite!(text, "S{} ", patterns.entry + 1).duwrp();
+        }
+        uwrite!(text, "P{}", patterns.current + 1).duwrp();
+        if let Some(queued) = patterns.queued {
+            uwrite!(text, ">{}", queued + 1).duwrp();
+        }
+
+        Text::with_text_style(
+            &text,
+            pos,
+            MonoTextStyle::new(
+                &PROFONT_10_POINT,
+                match patterns.queued {
+                    Some(_) => Rgb565::YELLOW,
+                    None => Rgb565::WHITE,
+                },
+            ),
+            TextStyleBuilder::new()
+                .alignment(Alignment::Right)
+                .baseline(Baseline::Top)
+                .build(),
+        )
+        .draw(screen)
+        .duwrp();
     }
 
-    pub(crate) fn draw_grid(&self, top: i32, start_time: i32, start_beat: u32, screen: &mut D) {
+    /// All the tracks at once, a lane each, showing where the notes are and roughly how high.
+    /// Tracks loop on their own length, and move at their own rate.
+    pub(crate) fn draw_overview(&self, top: i32, start_x: i32, start_beat: usize, screen: &mut D) {
+        Rectangle::new(
+            Point::new(0, top),
+            Size::new(SCREEN_WIDTH as u32, ROLL_HEIGHT as u32 + 1),
+        )
+        .into_styled(
+            PrimitiveStyleBuilder::new()
+                .stroke_color(Rgb565::WHITE)
+                .stroke_width(1)
+                .fill_color(Rgb565::BLACK)
+                .build(),
+        )
+        .draw(screen)
+        .unwrap();
+        self.draw_grid(top, start_x, start_beat as u32, screen);
+
+        for n in 0..NUM_TRACKS {
+            let lane_top = top + n as i32 * OVERVIEW_LANE_HEIGHT;
+            if n > 0 {
+                Line::new(Point::new(1, lane_top), Point::new(SCREEN_WIDTH as i32 - 2, lane_top))
+                    .into_styled(PrimitiveStyle::with_stroke(Rgb565::CSS_GRAY, 1))
+                    .draw(screen)
+                    .unwrap();
+            }
+            // the selected track is marked where the piano roll usually is
+            if n == self.tracks.selected {
+                Rectangle::new(
+                    Point::new(1, lane_top + 1),
+                    Size::new(ROLL_WIDTH as u32 - 1, OVERVIEW_LANE_HEIGHT as u32 - 1),
+                )
+                .into_styled(PrimitiveStyle::with_fill(Rgb565::YELLOW))
+                .draw(screen)
+                .unwrap();
+            }
+
+            // each track scrolls by at its own rate
+            let (start_x, start_beat) = view_start(self.tracks.position(n, self.state.position()));
+            let track = self.track(n);
+            let len = track.len();
+            let pitches = || {
+                track
+                    .since(0, len)
+                    .filter_map(|(_, step)| step.and_then(|(np, _)| np))
+                    .filter_map(|np| u8::try_from(&np).ok())
+            };
+            let low = pitches().min().unwrap_or(0);
+            let range = (pitches().max().unwrap_or(0) - low).max(1) as i32;
+
+            let color = if self.tracks.audible(n) {
+                Rgb565::BLUE
+            } else {
+                Rgb565::CSS_DIM_GRAY
+            };
+            for beat in start_beat..(start_beat + NUM_HORIZONTAL_BEATS as usize + 1) {
+                let note = match track.get_note(beat % len) {
+                    Some((Some(np), _)) => u8::try_from(&np).unwrap_or(low),
+                    _ => continue,
+                };
+                let beat_x = (beat as u32 * PIXELS_PER_BEAT) as i32 - start_x;
+                if beat_x < 0 || beat_x + PIXELS_PER_BEAT as i32 >= SCORE_WIDTH as i32 {
+                    continue;
+                }
+
+                let y = lane_top + OVERVIEW_LANE_HEIGHT
+                    - 4
+                    - (note - low) as i32 * (OVERVIEW_LANE_HEIGHT - 6) / range;
+                Rectangle::new(
+                    Point::new(ROLL_WIDTH + 2 + beat_x, y),
+                    Size::new(PIXELS_PER_BEAT - 1, 2),
+                )
+                .into_styled(PrimitiveStyle::with_fill(color))
+                .draw(screen)
+                .unwrap();
+            }
+        }
+    }
+
+    pub(crate) fn draw_grid(&self, top: i32, start_x: i32, start_beat: u32, screen: &mut D) {
         let mark_style = PrimitiveStyleBuilder::new()
             .stroke_color(Rgb565::CSS_DARK_GRAY)
             .stroke_width(1)
             .build();
 
         for beat in 0..(NUM_HORIZONTAL_BEATS + 1) {
-            let beat_t = (start_beat + beat as u32) * 60_000 / self.bpm as u32;
-            let mut x =
-                (beat_t as i32 - start_time) as i32 * self.bpm as i32 * PIXELS_PER_BEAT as i32
-                    / 60_000;
+            let mut x = ((start_beat + beat as u32) * PIXELS_PER_BEAT) as i32 - start_x;
 
             if x > 0 {
                 x += ROLL_WIDTH as i32 + 1;
@@ -87,14 +286,77 @@ where
             .unwrap();
     }
 
-    pub(crate) fn draw_notes<
-        IN: IntoIterator<Item = (usize, Option<(Option<NotePair>, NoteFlag)>)>,
-    >(
+    /// Frame around the step being edited (and the current pitch, in pitch mode),
+    /// with its trig condition and ratchets next to it
+    pub(crate) fn draw_step_edit_cursor(&self, top: i32, screen: &mut D) {
+        let edit = match &self.step_edit {
+            Some(edit) => edit,
+            None => return,
+        };
+
+        let x = ROLL_WIDTH + 1 + (SCORE_WIDTH as i32 / 2);
+        let color = match edit.mode {
+            StepEditMode::Step => Rgb565::YELLOW,
+            StepEditMode::Pitch => Rgb565::CSS_CORAL,
+            StepEditMode::Condition => Rgb565::CSS_LIGHT_GREEN,
+            StepEditMode::Ratchets => Rgb565::CSS_ORANGE,
+            StepEditMode::Modulation => MOD_LANE_COLOR,
+        };
+
+        Rectangle::new(
+            Point::new(x, top + 1),
+            Size::new(PIXELS_PER_BEAT + 1, ROLL_HEIGHT as u32 - 1),
+        )
+        .into_styled(PrimitiveStyle::with_stroke(color, 1))
+        .draw(screen)
+        .unwrap();
+
+        if edit.mode == StepEditMode::Pitch {
+            let pitch = edit.pitch() as i32 - self.current_note as i32;
+            let y = top + (NUM_VERTICAL_NOTES - 1 - pitch) * NOTE_HEIGHT;
+            Line::new(Point::new(ROLL_WIDTH + 1, y + NOTE_HEIGHT / 2), Point::new(x - 1, y + NOTE_HEIGHT / 2))
+                .into_styled(PrimitiveStyle::with_stroke(color, 1))
+                .draw(screen)
+                .unwrap();
+        }
+
+        let track = &self.recorder.voice_state;
+        let condition = track.get_condition(edit.step);
+        let mut text = String::<16>::new();
+        if condition != TrigCondition::Always || edit.mode == StepEditMode::Condition {
+            uwrite!(text, "{} ", condition).duwrp();
+        }
+        if track.get_ratchets(edit.step) > 1 || edit.mode == StepEditMode::Ratchets {
+            uwrite!(text, "x{} ", track.get_ratchets(edit.step)).duwrp();
+        }
+        // the value is also visible in the lane, only show it while editing it
+        if edit.mode == StepEditMode::Modulation {
+            match track.get_modulation(edit.step) {
+                Some(value) => uwrite!(text, "m{}", value).duwrp(),
+                None => uwrite!(text, "m-").duwrp(),
+            }
+        }
+        if !text.is_empty() {
+            Text::with_baseline(
+                &text,
+                Point::new(x + PIXELS_PER_BEAT as i32 + 3, top + 2),
+                MonoTextStyle::new(&PROFONT_10_POINT, color),
+                Baseline::Top,
+            )
+            .draw(screen)
+            .duwrp();
+        }
+    }
+
+    /// Notes of `track` from `start_beat` on. Ratcheted notes get a bar
+    /// over them, as long as the note with `MAX_RATCHETS` ratchets.
+    pub(crate) fn draw_notes(
         &self,
         top: i32,
         from_note: u8,
-        start_time: i32,
-        slots: IN,
+        start_x: i32,
+        track: &VoiceTrack,
+        start_beat: usize,
         screen: &mut D,
     ) where
         D: DrawTarget<Color = Rgb565>,
@@ -105,22 +367,20 @@ where
         let note_style = PrimitiveStyleBuilder::new()
             .fill_color(Rgb565::BLUE)
             .build();
+        let ratchet_style = PrimitiveStyle::with_stroke(Rgb565::CSS_ORANGE, 1);
 
-        for (beat, (note, flag)) in slots
-            .into_iter()
+        for (beat, (note, flag)) in track
+            .since(start_beat, NUM_HORIZONTAL_BEATS as usize + 1)
             .filter(|(_, s)| s.is_some())
             .map(|(n, v)| (n, v.unwrap()))
         {
-            let beat_t = (beat as u32) * 60_000 / self.bpm as u32;
+            let beat_x = (beat as u32 * PIXELS_PER_BEAT) as i32 - start_x;
 
-            let start_x =
-                max(0, beat_t as i32 - start_time) as u32 * self.bpm as u32 * PIXELS_PER_BEAT
-                    / 60_000;
-            let next_beat_t = beat_t as i32 + 60_000 / self.bpm as i32;
-            let end_x = min(
-                SCORE_WIDTH - 1,
-                (next_beat_t - start_time) as u32 * self.bpm as u32 * PIXELS_PER_BEAT / 60_000,
-            );
+            let note_start_x = max(0, beat_x) as u32;
+            let note_end_x = min(
+                SCORE_WIDTH as i32 - 1,
+                max(0, beat_x + PIXELS_PER_BEAT as i32),
+            ) as u32;
 
             match flag {
                 NoteFlag::None => {
@@ -137,17 +397,75 @@ where
                     let y =
                         top + (NUM_VERTICAL_NOTES - 1 - (note - from_note) as i32) * NOTE_HEIGHT;
 
-                    if end_x > start_x {
+                    if note_end_x > note_start_x {
                         Rectangle::new(
-                            Point::new(ROLL_WIDTH as i32 + 1 + (start_x + 1) as i32, y + 1),
-                            Size::new(end_x - start_x - 1, NOTE_HEIGHT as u32 - 1),
+                            Point::new(ROLL_WIDTH as i32 + 1 + (note_start_x + 1) as i32, y + 1),
+                            Size::new(note_end_x - note_start_x - 1, NOTE_HEIGHT as u32 - 1),
                         )
                         .into_styled(note_style)
                         .draw(screen)
                         .unwrap();
+
+                        let ratchets = track.get_ratchets(beat) as u32;
+                        if ratchets > 1 {
+                            let width = (note_end_x - note_start_x) * ratchets / MAX_RATCHETS as u32;
+                            let x = ROLL_WIDTH as i32 + 1 + note_start_x as i32;
+                            Line::new(Point::new(x, y), Point::new(x + width as i32, y))
+                                .into_styled(ratchet_style)
+                                .draw(screen)
+                                .unwrap();
+                        }
                     }
                 }
             }
         }
     }
+
+    /// One bar per step with a modulation value. Only shown while the lane is in use.
+    pub(crate) fn draw_mod_lane(
+        &self,
+        top: i32,
+        start_x: i32,
+        track: &VoiceTrack,
+        start_beat: usize,
+        screen: &mut D,
+    ) {
+        let editing = matches!(&self.step_edit, Some(edit) if edit.mode == StepEditMode::Modulation);
+        if self.config.modulation.output == ModOutput::Off && !editing {
+            return;
+        }
+
+        let bar_style = PrimitiveStyle::with_fill(MOD_LANE_COLOR);
+        for beat in start_beat..(start_beat + NUM_HORIZONTAL_BEATS as usize + 1) {
+            let value = match track.get_modulation(beat) {
+                Some(value) => value as u32,
+                None => continue,
+            };
+            let beat_x = (beat as u32 * PIXELS_PER_BEAT) as i32 - start_x;
+            if beat_x < 0 || beat_x + PIXELS_PER_BEAT as i32 >= SCORE_WIDTH as i32 {
+                continue;
+            }
+
+            let height = 1 + value * (MOD_LANE_HEIGHT - 1) / MAX_MOD_VALUE as u32;
+            Rectangle::new(
+                Point::new(
+                    ROLL_WIDTH + 2 + beat_x,
+                    top + (MOD_LANE_HEIGHT - height) as i32,
+                ),
+                Size::new(PIXELS_PER_BEAT - 1, height),
+            )
+            .into_styled(bar_style)
+            .draw(screen)
+            .unwrap();
+        }
+    }
+}
+
+/// x offset (in pixels) of the left edge of the view, so that `position` is right
+/// under the cursor, and the first beat in view
+fn view_start(position: Position) -> (i32, usize) {
+    let start_x = (position.beat * PIXELS_PER_BEAT + position.phase(PIXELS_PER_BEAT)) as i32
+        - (NUM_HORIZONTAL_BEATS / 2 * PIXELS_PER_BEAT) as i32;
+    let start_beat = position.beat.saturating_sub(NUM_HORIZONTAL_BEATS / 2) as usize;
+    (start_x, start_beat)
 }
@@ -1,5 +1,8 @@
 use alloc::{format, string::String};
-use ciborium::{de::Error as CBORDeserializerError, ser::Error as CBORSerializerError};
+use ciborium::{
+    de::Error as CBORDeserializerError, ser::Error as CBORSerializerError,
+    value::Error as CBORValueError,
+};
 use core::fmt::{Debug, Display};
 use embedded_sdmmc::{Error as ESDMMCError};
 
@@ -21,6 +24,8 @@ pub enum StdlibError {
     FS(FSError),
     Serialization,
     Deserialization,
+    /// The data doesn't fit in the memory set aside for it (see `MAX_FILE_BYTES`)
+    TooLarge,
     TaskInterface(String)
 }
 
@@ -99,6 +104,12 @@ impl<T> From<CBORSerializerError<T>> for StdlibError {
     }
 }
 
+impl From<CBORValueError> for StdlibError {
+    fn from(_err: CBORValueError) -> Self {
+        StdlibError::Deserialization
+    }
+}
+
 impl Display for FSError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let txt: String;
@@ -1,6 +1,6 @@
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, vec, vec::Vec};
 use ciborium::{ser::into_writer, de::from_reader, value::Value};
-use core::{marker::PhantomData, str, fmt::Debug};
+use core::{any::Any, convert::Infallible, marker::PhantomData, str, fmt::Debug};
 use embedded_sdmmc::{
     BlockDevice, Controller, Directory, File as FATFile, Mode, ShortFileName, TimeSource, Volume,
     VolumeIdx,
@@ -30,16 +30,67 @@ pub struct Closed;
 impl FileState for Closed {}
 
 pub trait FileContent: Debug + Send {
-    fn serialize(&self, buf: &mut [u8]) -> Result<(), ciborium::ser::Error<ciborium_io::OutOfSpace>>;
+    /// Serialize into `buf`, returning the number of bytes written
+    fn serialize(&self, buf: &mut [u8]) -> Result<usize, ciborium::ser::Error<ciborium_io::OutOfSpace>>;
 }
 
 impl<T: Serialize + Debug + Send> FileContent for T {
-    fn serialize(&self, buf: &mut [u8]) -> Result<(), ciborium::ser::Error<ciborium_io::OutOfSpace>> {
-        into_writer(self, buf)?;
+    fn serialize(&self, buf: &mut [u8]) -> Result<usize, ciborium::ser::Error<ciborium_io::OutOfSpace>> {
+        let size = buf.len();
+        let mut rest = buf;
+        into_writer(self, &mut rest)?;
+        Ok(size - rest.len())
+    }
+}
+
+/// Produces a file a part at a time, as it is saved, so that it's never in memory as a whole
+pub trait FileWriter: Debug + Send {
+    /// Write the next part of the file to `buf`, returning its size (0 once the file is complete)
+    fn write_part(&mut self, buf: &mut [u8]) -> Result<usize, StdlibError>;
+}
+
+/// Takes in a file a part at a time, as it is loaded, so that it's never in memory as a whole
+pub trait FileReader: Debug + Send {
+    /// Take in the next part of the file
+    fn read_part(&mut self, data: &[u8]) -> Result<(), StdlibError>;
+
+    /// The whole file was read
+    fn end(&mut self) -> Result<(), StdlibError>;
+
+    /// The reader itself, to get what it read
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send>;
+}
+
+/// Counts the bytes written to it, to size data before it's serialized for real
+struct ByteCounter(usize);
+
+impl ciborium_io::Write for ByteCounter {
+    type Error = Infallible;
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.0 += data.len();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
 }
 
+/// Serialize `data` to CBOR, into a buffer of just the right size.
+/// Data larger than `MAX_FILE_BYTES` is refused, before anything is allocated.
+pub fn to_bytes<T: Serialize + ?Sized>(data: &T) -> Result<Vec<u8>, StdlibError> {
+    let mut counter = ByteCounter(0);
+    into_writer(data, &mut counter)?;
+    if counter.0 > MAX_FILE_BYTES {
+        return Err(StdlibError::TooLarge);
+    }
+
+    let mut bytes = vec![0u8; counter.0];
+    into_writer(data, &mut bytes[..])?;
+    Ok(bytes)
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct File<S: FileState> {
     pub dir: String<8>,
@@ -135,9 +186,12 @@ impl File<OpenWrite> {
         data: &S,
     ) -> Result<(), StdlibError> {
         let mut buffer = [0u8; FILE_BUFFER_SIZE];
-        data.serialize(&mut buffer[..])?;
+        let size = data.serialize(&mut buffer[..]).map_err(|e| match e {
+            ciborium::ser::Error::Io(_) => StdlibError::TooLarge,
+            e => e.into(),
+        })?;
         fs.controller
-            .write(&mut fs.volume, self.handle_mut().unwrap(), &buffer)
+            .write(&mut fs.volume, self.handle_mut().unwrap(), &buffer[..size])
             .await?;
         Ok(())
     }
@@ -153,6 +207,24 @@ impl File<OpenWrite> {
         Ok(())
     }
 
+    /// Write what `writer` produces, in chunks
+    pub async fn dump_parts<D: BlockDevice, TS: TimeSource>(
+        &mut self,
+        fs: &mut FileSystem<D, TS>,
+        writer: &mut dyn FileWriter,
+    ) -> Result<(), StdlibError> {
+        let mut chunk = [0u8; FILE_CHUNK_SIZE];
+        loop {
+            let n = writer.write_part(&mut chunk)?;
+            if n == 0 {
+                return Ok(());
+            }
+            fs.controller
+                .write(&mut fs.volume, self.handle_mut().unwrap(), &chunk[..n])
+                .await?;
+        }
+    }
+
     pub fn close<D: BlockDevice, TS: TimeSource>(
         &mut self,
         fs: &mut FileSystem<D, TS>,
@@ -169,10 +241,52 @@ impl File<OpenRead> {
         fs: &'t mut FileSystem<D, TS>,
     ) -> Result<Value, StdlibError> {
         let mut buffer = [0u8; FILE_BUFFER_SIZE];
-        fs.controller
+        let size = fs
+            .controller
             .read(&fs.volume, self.handle_mut().unwrap(), &mut buffer)
             .await?;
-        Ok(from_reader(&buffer[..])?)
+        Ok(from_reader(&buffer[..size])?)
+    }
+
+    /// Read the whole file, in chunks. Files larger than `MAX_FILE_BYTES` are refused.
+    pub async fn load_bytes<D: BlockDevice, TS: TimeSource>(
+        &mut self,
+        fs: &mut FileSystem<D, TS>,
+    ) -> Result<Vec<u8>, StdlibError> {
+        let mut data = Vec::new();
+        let mut chunk = [0u8; FILE_CHUNK_SIZE];
+        loop {
+            let n = fs
+                .controller
+                .read(&fs.volume, self.handle_mut().unwrap(), &mut chunk)
+                .await?;
+            if n == 0 {
+                return Ok(data);
+            }
+            if data.len() + n > MAX_FILE_BYTES {
+                return Err(StdlibError::TooLarge);
+            }
+            data.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    /// Read the whole file into `reader`, in chunks
+    pub async fn load_parts<D: BlockDevice, TS: TimeSource>(
+        &mut self,
+        fs: &mut FileSystem<D, TS>,
+        reader: &mut dyn FileReader,
+    ) -> Result<(), StdlibError> {
+        let mut chunk = [0u8; FILE_CHUNK_SIZE];
+        loop {
+            let n = fs
+                .controller
+                .read(&fs.volume, self.handle_mut().unwrap(), &mut chunk)
+                .await?;
+            if n == 0 {
+                return reader.end();
+            }
+            reader.read_part(&chunk[..n])?;
+        }
     }
 
     pub fn close<D: BlockDevice, TS: TimeSource>(
@@ -208,6 +322,11 @@ pub struct FileSystem<D: BlockDevice, TS: TimeSource> {
 }
 
 const FILE_BUFFER_SIZE: usize = 4096; // 4KB
+/// Files are read/written a block at a time, besides the CBOR ones
+const FILE_CHUNK_SIZE: usize = 512;
+/// Biggest file `load_bytes` will read into memory, and `to_bytes` will serialize.
+/// It's kept on the heap (16 KB in total), along with everything it is loaded into.
+pub const MAX_FILE_BYTES: usize = 5 * 1024;
 
 impl<D: BlockDevice, TS: TimeSource> FileSystem<D, TS> {
     pub async fn list_files(
@@ -6,7 +6,10 @@ pub mod ui;
 
 pub use errors::{StdlibError, StdlibErrorFileWrapper, FSError};
 pub use files::{
-    Closed, File, FileState, FileSystem, OpenRead, OpenWrite, FileContent
+    Closed, File, FileState, FileSystem, OpenRead, OpenWrite, FileContent, FileReader, FileWriter,
+    to_bytes, MAX_FILE_BYTES,
 };
 pub use tasks::{SignalId, TaskManager, Task, TaskResult, TaskId, TaskReturn, TaskType, TaskInterface};
-pub use output::{Channel, CVChannelId, GateChannelId, GateChannel, CVChannel, Output};
+pub use output::{
+    Channel, CVChannelId, GateChannelId, GateChannel, CVChannel, GlideMode, GlideSettings, Output, Slew,
+};
@@ -1,20 +1,64 @@
 use core::fmt::Debug;
 
-use voice_lib::NotePair;
+use serde::{Deserialize, Serialize};
+use voice_lib::{NoteFlag, NotePair};
 
+#[derive(Clone, Copy, PartialEq)]
 pub enum GateChannelId {
     Gate0,
     Gate1,
 }
 
+#[derive(Clone, Copy, PartialEq)]
 pub enum CVChannelId {
     CV0,
     CV1,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GlideMode {
+    /// Always take the same time (ms) to reach the new pitch
+    Time(u16),
+    /// Move at a constant speed (ms per octave)
+    Rate(u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GlideSettings {
+    pub enabled: bool,
+    pub mode: GlideMode,
+    pub legato_only: bool,
+}
+
+impl Default for GlideSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: GlideMode::Time(50),
+            legato_only: true,
+        }
+    }
+}
+
+impl GlideSettings {
+    /// Glide to apply when moving into a step with the given flag (if any)
+    pub fn for_step(&self, flag: NoteFlag) -> Option<GlideMode> {
+        if !self.enabled || (self.legato_only && flag != NoteFlag::Legato) {
+            None
+        } else {
+            Some(self.mode)
+        }
+    }
+}
+
 pub trait Output<T: for<'t> TryFrom<&'t NotePair, Error = E>, E> {
     fn set_gate(&mut self, id: GateChannelId, value: bool);
+    /// Set the gate high and schedule it to go low again after `length_ms`
+    fn pulse_gate(&mut self, id: GateChannelId, length_ms: u32);
     fn set_cv(&mut self, id: CVChannelId, value: T);
+    /// Set a CV output to a raw 12-bit value (0 to `voice_lib::MAX_MOD_VALUE`), not a pitch
+    fn set_cv_raw(&mut self, id: CVChannelId, value: u16);
+    fn set_glide(&mut self, id: CVChannelId, glide: Option<GlideMode>);
 }
 
 pub trait Channel<T> {
@@ -28,3 +72,59 @@ pub trait CVChannel<T>: Channel<T> {
 
     fn set_from_note(&mut self, val: &NotePair) -> Result<(), Self::Error>;
 }
+
+const SLEW_FRAC_BITS: u32 = 8;
+
+/// Moves a CV value towards its target a bit on every output update,
+/// so that the DAC gets the intermediate values.
+pub struct Slew {
+    units_per_octave: u16,
+    // fixed point, `SLEW_FRAC_BITS` fractional bits
+    current: i32,
+    target: i32,
+    step_per_ms: i32,
+}
+
+impl Slew {
+    pub fn new(units_per_octave: u16) -> Self {
+        Self {
+            units_per_octave,
+            current: 0,
+            target: 0,
+            step_per_ms: 0,
+        }
+    }
+
+    pub fn set(&mut self, target: u16, glide: Option<GlideMode>) {
+        let target = (target as i32) << SLEW_FRAC_BITS;
+
+        if target == self.target {
+            return;
+        }
+        self.target = target;
+
+        let distance = (target - self.current).abs();
+        self.step_per_ms = match glide {
+            None | Some(GlideMode::Time(0)) | Some(GlideMode::Rate(0)) => distance,
+            Some(GlideMode::Time(ms)) => distance / ms as i32,
+            Some(GlideMode::Rate(ms_per_octave)) => {
+                ((self.units_per_octave as i32) << SLEW_FRAC_BITS) / ms_per_octave as i32
+            }
+        }
+        .max(1);
+    }
+
+    pub fn tick(&mut self, elapsed_ms: u32) -> u16 {
+        let step = self.step_per_ms.saturating_mul(elapsed_ms as i32);
+        self.current = if self.current < self.target {
+            (self.current + step).min(self.target)
+        } else {
+            (self.current - step).max(self.target)
+        };
+        self.value()
+    }
+
+    pub fn value(&self) -> u16 {
+        (self.current >> SLEW_FRAC_BITS) as u16
+    }
+}
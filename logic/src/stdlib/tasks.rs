@@ -10,13 +10,22 @@ use crate::{
 };
 use futures::{StreamExt, Stream, Sink, SinkExt};
 
-use super::{FileSystem, File, FileContent, Closed, StdlibError, StdlibErrorFileWrapper};
+use super::{
+    FileSystem, File, FileContent, FileReader, FileWriter, Closed, StdlibError, StdlibErrorFileWrapper,
+};
 
 pub struct SignalId(pub u64);
 
 pub enum TaskType {
     FileSave(String<8>, String<12>, Box<dyn FileContent>),
     FileLoad(String<8>, String<12>),
+    /// Save raw bytes, as they are (for non-CBOR formats)
+    FileSaveBytes(String<8>, String<12>, Vec<u8>),
+    FileLoadBytes(String<8>, String<12>),
+    /// Save what a `FileWriter` produces, without ever keeping the whole file in memory
+    FileSaveStream(String<8>, String<12>, Box<dyn FileWriter>),
+    /// Load a file into a `FileReader`, which comes back in `TaskResult::FileStream`
+    FileLoadStream(String<8>, String<12>, Box<dyn FileReader>),
     DirList(String<8>)
 }
 
@@ -29,6 +38,8 @@ pub type TaskReturn = (TaskId, TaskResult);
 pub enum TaskResult {
     Done,
     FileContent(Value),
+    FileBytes(Vec<u8>),
+    FileStream(Box<dyn FileReader>),
     DirList(Vec<File<Closed>>),
     Error(StdlibError)
 }
@@ -42,7 +53,7 @@ pub struct TaskManager<B: BlockDevice, TS: TimeSource> {
 async fn save_file<B: BlockDevice, TS: TimeSource, S: FileContent + ?Sized>(fs: &mut FileSystem<B, TS>, dir: &str, file_name: &str, data: &S) -> Result<TaskResult, StdlibError> {
     let f = File::new(dir, file_name);
     info("Saving file...");
-    let mut f = f.open_write(fs, false).await.map_err(|StdlibErrorFileWrapper(e, _)| e)?;
+    let mut f = f.open_write(fs, true).await.map_err(|StdlibErrorFileWrapper(e, _)| e)?;
     debug("Dumping bytes...");
     f.dump(fs, &*data).await?;
     f.close(fs).unwrap();
@@ -59,6 +70,42 @@ async fn load_file<B: BlockDevice, TS: TimeSource>(fs: &mut FileSystem<B, TS>, d
     Ok(TaskResult::FileContent(content))
 }
 
+async fn save_bytes<B: BlockDevice, TS: TimeSource>(fs: &mut FileSystem<B, TS>, dir: &str, file_name: &str, data: &[u8]) -> Result<TaskResult, StdlibError> {
+    let f = File::new(dir, file_name);
+    info("Saving file...");
+    let mut f = f.open_write(fs, true).await.map_err(|StdlibErrorFileWrapper(e, _)| e)?;
+    f.dump_bytes(fs, data).await?;
+    f.close(fs).unwrap();
+    Ok(TaskResult::Done)
+}
+
+async fn load_bytes<B: BlockDevice, TS: TimeSource>(fs: &mut FileSystem<B, TS>, dir: &str, file_name: &str) -> Result<TaskResult, StdlibError> {
+    let f = File::new(dir, file_name);
+    info("Loading file...");
+    let mut f = f.open_read(fs).await.map_err(|StdlibErrorFileWrapper(e, _)| e)?;
+    let content = f.load_bytes(fs).await;
+    f.close(fs).unwrap();
+    Ok(TaskResult::FileBytes(content?))
+}
+
+async fn save_stream<B: BlockDevice, TS: TimeSource>(fs: &mut FileSystem<B, TS>, dir: &str, file_name: &str, writer: &mut dyn FileWriter) -> Result<TaskResult, StdlibError> {
+    let f = File::new(dir, file_name);
+    info("Saving file...");
+    let mut f = f.open_write(fs, true).await.map_err(|StdlibErrorFileWrapper(e, _)| e)?;
+    let res = f.dump_parts(fs, writer).await;
+    f.close(fs).unwrap();
+    res.map(|_| TaskResult::Done)
+}
+
+async fn load_stream<B: BlockDevice, TS: TimeSource>(fs: &mut FileSystem<B, TS>, dir: &str, file_name: &str, mut reader: Box<dyn FileReader>) -> Result<TaskResult, StdlibError> {
+    let f = File::new(dir, file_name);
+    info("Loading file...");
+    let mut f = f.open_read(fs).await.map_err(|StdlibErrorFileWrapper(e, _)| e)?;
+    let res = f.load_parts(fs, &mut *reader).await;
+    f.close(fs).unwrap();
+    res.map(|_| TaskResult::FileStream(reader))
+}
+
 impl<'t, B: BlockDevice + 't, TS: TimeSource + 't> TaskManager<B, TS> {
     pub fn new(fs: FileSystem<B, TS>) -> Self {
         Self {
@@ -74,6 +121,10 @@ impl<'t, B: BlockDevice + 't, TS: TimeSource + 't> TaskManager<B, TS> {
                 let result = match task.1 {
                     TaskType::FileSave(dir_name, file_name, data) =>  save_file(&mut self.fs, &dir_name, &file_name, &*data).await,
                     TaskType::FileLoad(dir_name, file_name) => load_file(&mut self.fs, &dir_name, &file_name).await,
+                    TaskType::FileSaveBytes(dir_name, file_name, data) => save_bytes(&mut self.fs, &dir_name, &file_name, &data).await,
+                    TaskType::FileLoadBytes(dir_name, file_name) => load_bytes(&mut self.fs, &dir_name, &file_name).await,
+                    TaskType::FileSaveStream(dir_name, file_name, mut writer) => save_stream(&mut self.fs, &dir_name, &file_name, &mut *writer).await,
+                    TaskType::FileLoadStream(dir_name, file_name, reader) => load_stream(&mut self.fs, &dir_name, &file_name, reader).await,
                     TaskType::DirList(dir_name) => self.fs.list_files(&dir_name).await.map(|res| TaskResult::DirList(res)),
                 };
 
@@ -5,6 +5,9 @@ use crate::{programs::Program, stdlib::{TaskInterface, TaskType}};
 
 use super::{UIInputEvent, overlays::{Overlay, OverlayResult}};
 
+/// How many options fit in the menu window
+pub const MENU_VISIBLE_OPTIONS: usize = 5;
+
 pub trait MenuOptions {}
 
 pub trait MenuDef<
@@ -74,9 +77,17 @@ macro_rules! impl_overlay {
 
                 rect.into_styled(window_style).draw(target)?;
 
+                let options = <Self as MenuDef<'t, D, $p<'t, B, TS, D, TI>, _, _, _>>::options(self);
+                let selected = options
+                    .iter()
+                    .position(|o| <Self as MenuDef<'t, D, $p<'t, B, TS, D, TI>, _, _, _>>::selected(self, o))
+                    .unwrap_or(0);
+                // scroll so that the selected option is always visible
+                let first = selected.saturating_sub($crate::stdlib::ui::MENU_VISIBLE_OPTIONS - 1);
+
                 let mut y = 15i32;
 
-                for option in <Self as MenuDef<'t, D, $p<'t, B, TS, D, TI>, _, _, _>>::options(self) {
+                for option in options.iter().skip(first).take($crate::stdlib::ui::MENU_VISIBLE_OPTIONS) {
                     let text =
                         <Self as MenuDef<'t, D, $p<'t, B, TS, D, TI>, _, _, _>>::label(self, option);
 
@@ -7,6 +7,7 @@ mod dialog;
 mod input;
 mod menu;
 mod overlays;
+mod params;
 
 pub use button::{Button, ButtonId};
 pub use dialog::Dialog;
@@ -14,8 +15,9 @@ use embedded_graphics::{
     draw_target::DrawTarget, pixelcolor::Rgb565
 };
 pub use input::Input;
-pub use menu::{MenuDef, MenuOptions};
+pub use menu::{MenuDef, MenuOptions, MENU_VISIBLE_OPTIONS};
 pub use overlays::{Overlay, OverlayResult, OverlayManager};
+pub use params::{Param, ParamDialog};
 use ufmt::derive::uDebug;
 
 
@@ -80,6 +80,10 @@ where
         }
     }
 
+    pub(crate) fn open(&mut self, overlay: Box<dyn Overlay<'t, D, P, B, TS, TI> + 't>) {
+        self.pending_ops.push(OverlayResult::Push(overlay));
+    }
+
     pub(crate) fn process_input(&mut self, msg: &UIInputEvent) -> Result<bool, StdlibError> {
         let mut overlays = self.stack.take().unwrap();
         let res = match overlays.last_mut() {
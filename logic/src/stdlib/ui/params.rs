@@ -0,0 +1,278 @@
+use alloc::{boxed::Box, vec::Vec};
+use embedded_graphics::{
+    draw_target::DrawTarget,
+    mono_font::MonoTextStyle,
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyleBuilder, Rectangle},
+    text::{Alignment, Text},
+};
+use embedded_sdmmc::{BlockDevice, TimeSource};
+use heapless::String;
+use profont::{PROFONT_10_POINT, PROFONT_14_POINT};
+use ufmt::uwrite;
+
+use crate::{
+    programs::Program,
+    screen::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    stdlib::{StdlibError, TaskInterface, TaskType},
+};
+
+use super::{Overlay, OverlayResult, UIInputEvent};
+
+const ROW_HEIGHT: i32 = 13;
+const NUM_VISIBLE_ROWS: usize = 6;
+
+const ON_OFF: &[&str] = &["Off", "On"];
+
+pub struct Param {
+    pub label: &'static str,
+    pub value: i32,
+    min: i32,
+    max: i32,
+    step: i32,
+    choices: Option<&'static [&'static str]>,
+}
+
+impl Param {
+    pub fn new(label: &'static str, min: i32, max: i32) -> Self {
+        Self {
+            label,
+            value: min,
+            min,
+            max,
+            step: 1,
+            choices: None,
+        }
+    }
+
+    pub fn choice(label: &'static str, choices: &'static [&'static str]) -> Self {
+        Self {
+            choices: Some(choices),
+            ..Self::new(label, 0, choices.len() as i32 - 1)
+        }
+    }
+
+    pub fn toggle(label: &'static str) -> Self {
+        Self::choice(label, ON_OFF)
+    }
+
+    pub fn with_step(mut self, step: i32) -> Self {
+        self.step = step;
+        self
+    }
+
+    /// Change the upper bound, for ranges only known once the dialog opens
+    pub fn set_max(&mut self, max: i32) {
+        self.max = max.max(self.min);
+        self.set(self.value);
+    }
+
+    pub fn set(&mut self, value: i32) {
+        self.value = value.clamp(self.min, self.max);
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.value != 0
+    }
+
+    pub fn change(&mut self, delta: i8) {
+        self.set(self.value + delta as i32 * self.step);
+    }
+
+    pub fn format(&self) -> String<12> {
+        let mut out = String::new();
+        match self.choices {
+            Some(choices) => {
+                out.push_str(choices[self.value as usize]).ok();
+            }
+            None => {
+                uwrite!(out, "{}", self.value).ok();
+            }
+        }
+        out
+    }
+}
+
+#[derive(PartialEq)]
+enum ParamDialogState {
+    Initializing,
+    Editing,
+    Applying,
+    Done,
+}
+
+/// A dialog which edits a list of numeric/choice parameters of a program.
+/// `load` fills in the current values once the dialog opens, and `store`
+/// writes them back when the user confirms (returning any tasks to submit).
+pub struct ParamDialog<P> {
+    title: &'static str,
+    params: Vec<Param>,
+    selected: usize,
+    editing: bool,
+    state: ParamDialogState,
+    load: fn(&P, &mut [Param]),
+    store: fn(&mut P, &[Param]) -> Result<Vec<TaskType>, StdlibError>,
+}
+
+impl<P> ParamDialog<P> {
+    pub fn new(
+        title: &'static str,
+        params: Vec<Param>,
+        load: fn(&P, &mut [Param]),
+        store: fn(&mut P, &[Param]) -> Result<Vec<TaskType>, StdlibError>,
+    ) -> Self {
+        Self {
+            title,
+            params,
+            selected: 0,
+            editing: false,
+            state: ParamDialogState::Initializing,
+            load,
+            store,
+        }
+    }
+
+    fn num_rows(&self) -> usize {
+        // last row is "OK"
+        self.params.len() + 1
+    }
+}
+
+impl<
+        't,
+        D: DrawTarget<Color = Rgb565>,
+        P: Program<'t, B, D, TS, TI>,
+        B: BlockDevice + 't,
+        TS: TimeSource + 't,
+        TI: TaskInterface + 't,
+    > Overlay<'t, D, P, B, TS, TI> for ParamDialog<P>
+{
+    fn process_ui_input(&mut self, input: &UIInputEvent) -> OverlayResult<'t, D, P, B, TS, TI>
+    where
+        D: 't,
+    {
+        match input {
+            UIInputEvent::EncoderTurn(v) => {
+                if self.editing {
+                    self.params[self.selected].change(*v);
+                } else {
+                    self.selected = (self.selected as i16 + *v as i16)
+                        .rem_euclid(self.num_rows() as i16)
+                        as usize;
+                }
+                OverlayResult::Nop
+            }
+            UIInputEvent::EncoderSwitch(true) => {
+                if self.selected == self.params.len() {
+                    self.state = ParamDialogState::Applying;
+                    OverlayResult::Close
+                } else {
+                    self.editing = !self.editing;
+                    OverlayResult::Nop
+                }
+            }
+            _ => OverlayResult::Nop,
+        }
+    }
+
+    fn draw(&self, target: &mut D) -> Result<(), D::Error> {
+        let text_style_title = MonoTextStyle::new(&PROFONT_14_POINT, Rgb565::YELLOW);
+        let text_style = MonoTextStyle::new(&PROFONT_10_POINT, Rgb565::WHITE);
+        let text_style_selected = MonoTextStyle::new(&PROFONT_10_POINT, Rgb565::YELLOW);
+        let text_style_editing = MonoTextStyle::new(&PROFONT_10_POINT, Rgb565::CSS_CORAL);
+
+        let window_style = PrimitiveStyleBuilder::new()
+            .fill_color(Rgb565::CSS_DARK_GRAY)
+            .build();
+        let row_style_selected = PrimitiveStyleBuilder::new()
+            .fill_color(Rgb565::CSS_SLATE_BLUE)
+            .build();
+
+        Rectangle::new(
+            Point::new(10, 10),
+            Size::new(SCREEN_WIDTH as u32 - 20, SCREEN_HEIGHT as u32 - 20),
+        )
+        .into_styled(window_style)
+        .draw(target)?;
+
+        Text::with_alignment(
+            self.title,
+            Point::new(SCREEN_WIDTH as i32 / 2, 23),
+            text_style_title,
+            Alignment::Center,
+        )
+        .draw(target)?;
+
+        // scroll so that the selected row is always visible
+        let first = self.selected.saturating_sub(NUM_VISIBLE_ROWS - 1);
+        let mut y = 30;
+
+        for row in first..(first + NUM_VISIBLE_ROWS).min(self.num_rows()) {
+            let selected = row == self.selected;
+
+            if selected {
+                Rectangle::new(
+                    Point::new(12, y),
+                    Size::new(SCREEN_WIDTH as u32 - 24, ROW_HEIGHT as u32),
+                )
+                .into_styled(row_style_selected)
+                .draw(target)?;
+            }
+
+            let style = if selected { text_style_selected } else { text_style };
+
+            match self.params.get(row) {
+                Some(param) => {
+                    Text::new(param.label, Point::new(15, y + 10), style).draw(target)?;
+                    Text::with_alignment(
+                        &param.format(),
+                        Point::new(SCREEN_WIDTH as i32 - 15, y + 10),
+                        if selected && self.editing {
+                            text_style_editing
+                        } else {
+                            style
+                        },
+                        Alignment::Right,
+                    )
+                    .draw(target)?;
+                }
+                None => {
+                    Text::with_alignment(
+                        "OK",
+                        Point::new(SCREEN_WIDTH as i32 / 2, y + 10),
+                        style,
+                        Alignment::Center,
+                    )
+                    .draw(target)?;
+                }
+            }
+
+            y += ROW_HEIGHT;
+        }
+
+        Ok(())
+    }
+
+    fn run<'u>(
+        &'u mut self,
+    ) -> Result<
+        Option<Box<dyn FnOnce(&mut P) -> Result<Vec<TaskType>, StdlibError> + 'u>>,
+        StdlibError,
+    > {
+        match self.state {
+            ParamDialogState::Initializing => {
+                self.state = ParamDialogState::Editing;
+                Ok(Some(Box::new(|program| {
+                    (self.load)(program, &mut self.params);
+                    Ok(Vec::new())
+                })))
+            }
+            ParamDialogState::Applying => {
+                self.state = ParamDialogState::Done;
+                Ok(Some(Box::new(|program| (self.store)(program, &self.params))))
+            }
+            ParamDialogState::Editing | ParamDialogState::Done => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::{NotePair, Rng};
+
+/// Most notes an arpeggio can have (held notes times octaves)
+const MAX_ARP_NOTES: usize = 32;
+pub const MAX_ARP_OCTAVES: u8 = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArpMode {
+    Up,
+    Down,
+    UpDown,
+    Random,
+    /// In the order the keys were pressed
+    AsPlayed,
+}
+
+/// Turns a set of held notes into a sequence, one note per `next` call
+pub struct Arpeggiator {
+    pub mode: ArpMode,
+    /// How many octaves the held notes are repeated over (1 = just the held notes)
+    pub octaves: u8,
+    index: usize,
+    rng: Rng,
+}
+
+impl Arpeggiator {
+    pub fn new(mode: ArpMode, octaves: u8, seed: u32) -> Self {
+        Self {
+            mode,
+            octaves,
+            index: 0,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Start over from the first note of the arpeggio
+    pub fn reset(&mut self) {
+        self.index = 0;
+    }
+
+    /// All notes of one cycle of the arpeggio. `held` is in the order the keys were pressed.
+    fn notes(&self, held: &[NotePair]) -> Vec<NotePair, MAX_ARP_NOTES> {
+        let mut base: Vec<NotePair, MAX_ARP_NOTES> = held.iter().cloned().collect();
+        if self.mode != ArpMode::AsPlayed {
+            base.sort_unstable_by_key(|np| np.semitones());
+        }
+
+        let mut notes: Vec<NotePair, MAX_ARP_NOTES> = Vec::new();
+        for octave in 0..self.octaves.clamp(1, MAX_ARP_OCTAVES) {
+            for np in base.iter() {
+                let np = NotePair::from_semitones(np.semitones() + 12 * octave as i16);
+                // notes above the MIDI range are left out
+                if u8::try_from(&np).is_ok() {
+                    notes.push(np).ok();
+                }
+            }
+        }
+
+        match self.mode {
+            ArpMode::Down => notes.reverse(),
+            ArpMode::UpDown if notes.len() > 2 => {
+                // don't repeat the highest and lowest notes
+                for n in (1..notes.len() - 1).rev() {
+                    let np = notes[n];
+                    notes.push(np).ok();
+                }
+            }
+            _ => {}
+        }
+        notes
+    }
+
+    /// Next note to play, `None` if no notes are held
+    pub fn next(&mut self, held: &[NotePair]) -> Option<NotePair> {
+        let notes = self.notes(held);
+        if notes.is_empty() {
+            return None;
+        }
+
+        let n = match self.mode {
+            ArpMode::Random => self.rng.below(notes.len() as u32) as usize,
+            _ => {
+                let n = self.index % notes.len();
+                self.index = n + 1;
+                n
+            }
+        };
+        Some(notes[n])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::{ArpMode, Arpeggiator};
+    use crate::{Note, NotePair};
+
+    const C4: NotePair = NotePair(Note::C, 4);
+    const E4: NotePair = NotePair(Note::E, 4);
+    const G4: NotePair = NotePair(Note::G, 4);
+
+    fn run(arp: &mut Arpeggiator, held: &[NotePair], n: usize) -> Vec<NotePair> {
+        (0..n).map(|_| arp.next(held).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_modes() {
+        let held = [E4, C4, G4];
+
+        let mut arp = Arpeggiator::new(ArpMode::Up, 1, 0);
+        assert_eq!(run(&mut arp, &held, 4), [C4, E4, G4, C4]);
+
+        let mut arp = Arpeggiator::new(ArpMode::Down, 1, 0);
+        assert_eq!(run(&mut arp, &held, 4), [G4, E4, C4, G4]);
+
+        let mut arp = Arpeggiator::new(ArpMode::UpDown, 1, 0);
+        assert_eq!(run(&mut arp, &held, 6), [C4, E4, G4, E4, C4, E4]);
+
+        let mut arp = Arpeggiator::new(ArpMode::AsPlayed, 1, 0);
+        assert_eq!(run(&mut arp, &held, 4), [E4, C4, G4, E4]);
+    }
+
+    #[test]
+    fn test_octaves() {
+        let mut arp = Arpeggiator::new(ArpMode::Up, 2, 0);
+        assert_eq!(
+            run(&mut arp, &[C4, G4], 5),
+            [C4, G4, NotePair(Note::C, 5), NotePair(Note::G, 5), C4]
+        );
+
+        // out of range octaves are skipped
+        let top = NotePair(Note::C, 9);
+        let mut arp = Arpeggiator::new(ArpMode::Up, 3, 0);
+        assert_eq!(run(&mut arp, &[top], 2), [top, top]);
+    }
+
+    #[test]
+    fn test_random() {
+        let held = [C4, E4, G4];
+        let mut a = Arpeggiator::new(ArpMode::Random, 1, 5);
+        let mut b = Arpeggiator::new(ArpMode::Random, 1, 5);
+        let notes = run(&mut a, &held, 50);
+        assert_eq!(notes, run(&mut b, &held, 50));
+        assert!(held.iter().all(|np| notes.contains(np)));
+    }
+
+    #[test]
+    fn test_held_notes_change() {
+        let mut arp = Arpeggiator::new(ArpMode::Up, 1, 0);
+        assert_eq!(arp.next(&[]), None);
+        assert_eq!(run(&mut arp, &[C4, E4], 1), [C4]);
+        // a note is added in the middle of the arpeggio
+        assert_eq!(run(&mut arp, &[C4, E4, G4], 2), [E4, G4]);
+        arp.reset();
+        assert_eq!(run(&mut arp, &[C4, E4, G4], 1), [C4]);
+    }
+}
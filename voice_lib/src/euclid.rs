@@ -0,0 +1,109 @@
+use crate::{NoteFlag, NotePair, VoiceTrack};
+
+/// Euclidean rhythm: `pulses` onsets spread as evenly as possible over `steps`
+/// steps, rotated by `rotation` steps.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Euclid {
+    pub steps: u8,
+    pub pulses: u8,
+    pub rotation: u8,
+}
+
+impl Euclid {
+    /// `pulses` is capped at `steps`, `rotation` wraps around
+    pub fn new(steps: u8, pulses: u8, rotation: u8) -> Self {
+        let steps = steps.max(1);
+        Self {
+            steps,
+            pulses: pulses.min(steps),
+            rotation: rotation % steps,
+        }
+    }
+
+    /// Whether there is an onset at `step` (the pattern repeats every `steps` steps)
+    pub fn is_pulse(&self, step: usize) -> bool {
+        let steps = self.steps as usize;
+        let n = (step % steps + steps - self.rotation as usize % steps) % steps;
+        (n * self.pulses as usize) % steps < self.pulses as usize
+    }
+
+    /// Fill the whole of `track` with the (repeated) pattern. Onset `n` gets
+    /// the pitch `pitch(n)`, all other steps are rests.
+    pub fn fill(&self, track: &mut VoiceTrack, mut pitch: impl FnMut(usize) -> NotePair) {
+        let mut onsets = 0;
+        for step in 0..track.len() {
+            let state = if self.is_pulse(step) {
+                onsets += 1;
+                (Some(pitch(onsets - 1)), NoteFlag::Note)
+            } else {
+                (None, NoteFlag::None)
+            };
+            // pitches out of range become rests
+            if track.set_note(step, state).is_err() {
+                track.set_note(step, (None, NoteFlag::None)).unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::Euclid;
+    use crate::{Note, NoteFlag, NotePair, VoiceTrack};
+
+    fn pattern(e: &Euclid) -> Vec<bool> {
+        (0..e.steps as usize).map(|n| e.is_pulse(n)).collect()
+    }
+
+    const X: bool = true;
+    const O: bool = false;
+
+    #[test]
+    fn test_patterns() {
+        assert_eq!(pattern(&Euclid::new(8, 3, 0)), [X, O, O, X, O, O, X, O]);
+        assert_eq!(pattern(&Euclid::new(4, 4, 0)), [X, X, X, X]);
+        assert_eq!(pattern(&Euclid::new(5, 0, 0)), [O, O, O, O, O]);
+        assert_eq!(pattern(&Euclid::new(5, 2, 0)), [X, O, O, X, O]);
+        for (steps, pulses) in [(8, 5), (13, 5), (16, 7), (12, 4)] {
+            let p = pattern(&Euclid::new(steps, pulses, 0));
+            assert_eq!(p.iter().filter(|x| **x).count(), pulses as usize);
+            assert!(p[0]);
+        }
+    }
+
+    #[test]
+    fn test_rotation_and_limits() {
+        assert_eq!(pattern(&Euclid::new(8, 3, 1)), [O, X, O, O, X, O, O, X]);
+        // same as rotating by 1
+        assert_eq!(Euclid::new(8, 3, 9), Euclid::new(8, 3, 1));
+        assert_eq!(Euclid::new(4, 9, 0).pulses, 4);
+        assert_eq!(Euclid::new(0, 1, 0).steps, 1);
+    }
+
+    #[test]
+    fn test_fill() {
+        let mut track = VoiceTrack::new(8);
+        track.set_note(1, (Some(NotePair(Note::A, 3)), NoteFlag::Note)).unwrap();
+
+        let pitches = [NotePair(Note::C, 4), NotePair(Note::E, 4)];
+        Euclid::new(4, 2, 0).fill(&mut track, |n| pitches[n % 2]);
+
+        let steps: Vec<_> = (0..8).map(|n| track.get_note(n).unwrap()).collect();
+        let rest = (None, NoteFlag::None);
+        assert_eq!(
+            steps,
+            [
+                (Some(pitches[0]), NoteFlag::Note),
+                rest,
+                (Some(pitches[1]), NoteFlag::Note),
+                rest,
+                (Some(pitches[0]), NoteFlag::Note),
+                rest,
+                (Some(pitches[1]), NoteFlag::Note),
+                rest,
+            ]
+        );
+    }
+}
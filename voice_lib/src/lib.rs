@@ -4,11 +4,30 @@ extern crate alloc;
 
 use serde::{Deserialize, Serialize};
 
+mod arp;
+mod euclid;
+mod markov;
 mod note;
+mod polymeter;
+mod rng;
+mod scale;
+mod text;
 mod track;
-
-pub use note::{Note, NotePair, InvalidNotePair};
-pub use track::{NoteFlag, VoiceTrack};
+mod transform;
+mod trig;
+mod turing;
+
+pub use arp::{ArpMode, Arpeggiator, MAX_ARP_OCTAVES};
+pub use euclid::Euclid;
+pub use markov::{Markov, MARKOV_TEMPERATURE};
+pub use note::{Accidentals, Note, NotePair, NotePairDisplay, InvalidNotePair};
+pub use polymeter::{ClockRatio, MAX_CLOCK_RATIO};
+pub use rng::Rng;
+pub use scale::{Key, Scale};
+pub use text::{InvalidStep, ParsedStep};
+pub use track::{NoteFlag, VoiceTrack, MAX_MOD_VALUE, MAX_RATCHETS};
+pub use trig::{InvalidTrigCondition, TrigCondition, Trigs};
+pub use turing::{TuringMachine, MAX_TURING_LENGTH, MIN_TURING_LENGTH};
 
 
 #[derive(Serialize, Deserialize)]
@@ -42,14 +61,14 @@ impl From<(Option<NotePair>, NoteFlag)> for NoteState {
 mod tests {
     use heapless::String;
 
-    use super::{Note, NotePair, VoiceState, VoiceTrack};
+    use super::{Note, NoteFlag, NotePair, NoteState, VoiceTrack};
     use ufmt::uwrite;
 
     #[test]
     fn test_note_midi_conversion() {
-        assert!(Into::<NotePair>::into(24) == NotePair(Note::C, 1));
-        assert!(Into::<NotePair>::into(50) == NotePair(Note::D, 3));
-        assert!(Into::<i8>::into(NotePair(Note::D, 3)) == 50);
+        assert!(NotePair::from(24) == NotePair(Note::C, 1));
+        assert!(NotePair::from(50) == NotePair(Note::D, 3));
+        assert!(u8::try_from(&NotePair(Note::D, 3)) == Ok(50));
     }
 
     #[test]
@@ -61,48 +80,40 @@ mod tests {
     }
 
     #[test]
-    fn test_voice_history() {
-        let mut h = VoiceTrack::<10>::new(16);
-        h.start_note(NotePair(Note::D, 2), 10);
-
-        assert!(h.content.last() == Some(&(NotePair(Note::D, 2), 10, None)));
-
-        h.end_note(20);
-
-        assert!(h.content.last() == Some(&(NotePair(Note::D, 2), 10, Some(20))));
-
-        h.start_note(NotePair(Note::D, 2), 40);
-
-        assert!(h.content.last() == Some(&(NotePair(Note::D, 2), 40, None)));
-    }
-
-    #[test]
-    fn test_voice_state() {
-        let mut s = VoiceState::<2, 10>::new();
-        s.set(NotePair(Note::B, 3), 10);
-        assert!(s[0] == Some(NotePair(Note::B, 3)));
-        assert!(s[1] == None);
-        s.clear(NotePair(Note::B, 3), 20);
-        assert!(s[0] == None);
-        assert!(s[1] == None);
-        s.set(NotePair(Note::B, 3), 10);
-        s.set(NotePair(Note::C, 4), 12);
-        assert!(s[0] == Some(NotePair(Note::B, 3)));
-        assert!(s[1] == Some(NotePair(Note::C, 4)));
-        assert!(s.history[0].content.last() == Some(&(NotePair(Note::B, 3), 10, None)));
-        s.set(NotePair(Note::D, 2), 14);
-        assert!(s[0] == Some(NotePair(Note::D, 2)));
+    fn test_voice_track() {
+        let mut t = VoiceTrack::new(6);
+        t.set_note(1, (Some(NotePair(Note::D, 2)), NoteFlag::Note)).unwrap();
+        t.set_note(2, (Some(NotePair(Note::D, 2)), NoteFlag::Legato)).unwrap();
+        // the flags of steps 3 and 4 are in different bytes
+        t.set_note(3, (Some(NotePair(Note::E, 2)), NoteFlag::Note)).unwrap();
+        t.set_note(4, (Some(NotePair(Note::F, 2)), NoteFlag::Note)).unwrap();
+
+        assert!(t.get_note(0) == Some((None, NoteFlag::None)));
+        assert!(t.get_note(1) == Some((Some(NotePair(Note::D, 2)), NoteFlag::Note)));
+        assert!(t.get_note(2) == Some((Some(NotePair(Note::D, 2)), NoteFlag::Legato)));
+        assert!(t.get_note(3) == Some((Some(NotePair(Note::E, 2)), NoteFlag::Note)));
+        assert!(t.get_note(4) == Some((Some(NotePair(Note::F, 2)), NoteFlag::Note)));
+        assert!(t.get_note(6).is_none());
+
+        t.set_note(3, (None, NoteFlag::None)).unwrap();
+        assert!(t.get_note(2) == Some((Some(NotePair(Note::D, 2)), NoteFlag::Legato)));
+        assert!(t.get_note(3) == Some((None, NoteFlag::None)));
+        assert!(t.get_note(4) == Some((Some(NotePair(Note::F, 2)), NoteFlag::Note)));
+
+        // out of the MIDI range
+        assert!(t.set_note(5, (Some(NotePair(Note::A, 9)), NoteFlag::Note)).is_err());
     }
 
     #[test]
-    fn test_voice_state_zombie() {
-        let mut s = VoiceState::<2, 10>::new();
-        s.set(NotePair(Note::D, 2), 10);
-        s.set(NotePair(Note::E, 2), 11);
-        s.set(NotePair(Note::F, 2), 12);
-        s.clear(NotePair(Note::F, 2), 13);
-        s.clear(NotePair(Note::E, 2), 14);
-        // this shouldn't blow up
-        s.clear(NotePair(Note::D, 2), 15);
+    fn test_note_state() {
+        let steps = [
+            (None, NoteFlag::None),
+            (Some(NotePair(Note::B, 3)), NoteFlag::Note),
+            (Some(NotePair(Note::C, 4)), NoteFlag::Legato),
+        ];
+        for step in steps {
+            let state: NoteState = step.into();
+            assert!(<(Option<NotePair>, NoteFlag)>::from(state) == step);
+        }
     }
 }
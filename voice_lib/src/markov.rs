@@ -0,0 +1,306 @@
+use heapless::Vec;
+
+use crate::{NoteFlag, NotePair, Rng, VoiceTrack};
+
+/// Most distinct (pitch, length) events a chain can learn
+const MAX_SYMBOLS: usize = 64;
+/// Most distinct transitions, first and second order together
+const MAX_TRANSITIONS: usize = 256;
+// no symbol (yet)
+const NONE: u8 = u8::MAX;
+// pitch of a rest
+const REST: u8 = u8::MAX;
+
+/// Neutral temperature: follow the learned probabilities
+pub const MARKOV_TEMPERATURE: u8 = 100;
+
+/// A note (with its ties) or a rest, and how many steps it lasts
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Event {
+    pitch: u8,
+    length: u8,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Transition {
+    /// the previous two symbols, the first is `NONE` for first order transitions
+    from: [u8; 2],
+    to: u8,
+    count: u8,
+}
+
+/// First and second order Markov chain of the notes and rests of a track
+/// (as pitch + length, so the rhythm is learned too). The tables have a fixed
+/// size, so that it fits on the heap whatever the track: what doesn't fit
+/// (very long and varied tracks) isn't learned.
+pub struct Markov {
+    symbols: Vec<Event, MAX_SYMBOLS>,
+    transitions: Vec<Transition, MAX_TRANSITIONS>,
+    // the state generating starts from
+    start: [u8; 2],
+}
+
+impl Markov {
+    /// Learn from `track`, which is treated as a loop
+    pub fn new(track: &VoiceTrack) -> Self {
+        let mut chain = Self {
+            symbols: Vec::new(),
+            transitions: Vec::new(),
+            start: [NONE; 2],
+        };
+
+        let events = events(track);
+        let ids: alloc::vec::Vec<u8> = events.iter().map(|e| chain.symbol(*e)).collect();
+        let len = ids.len();
+        if len == 0 {
+            return chain;
+        }
+
+        chain.start = [ids[len - 1], ids[0]];
+        for n in 0..len {
+            let prev2 = ids[(n + 2 * len - 2) % len];
+            let prev = ids[(n + len - 1) % len];
+            let to = ids[n];
+            chain.learn([NONE, prev], to);
+            chain.learn([prev2, prev], to);
+        }
+        chain
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transitions.is_empty()
+    }
+
+    fn symbol(&mut self, event: Event) -> u8 {
+        match self.symbols.iter().position(|e| *e == event) {
+            Some(n) => n as u8,
+            None => match self.symbols.push(event) {
+                Ok(()) => (self.symbols.len() - 1) as u8,
+                Err(_) => NONE,
+            },
+        }
+    }
+
+    fn learn(&mut self, from: [u8; 2], to: u8) {
+        if from[1] == NONE || to == NONE {
+            return;
+        }
+        match self.transitions.iter_mut().find(|t| t.from == from && t.to == to) {
+            Some(t) => t.count = t.count.saturating_add(1),
+            None => {
+                // table full: only the transitions seen so far are known
+                self.transitions.push(Transition { from, to, count: 1 }).ok();
+            }
+        }
+    }
+
+    /// Symbol following `state`, `None` at a dead end
+    fn next(&self, state: [u8; 2], temperature: u8, rng: &mut Rng) -> Option<u8> {
+        let candidates = |from: [u8; 2]| self.transitions.iter().filter(move |t| t.from == from);
+
+        // back to first order, if this pair is unknown (order 1 asks for that anyway)
+        let from = match candidates(state).next() {
+            Some(_) => state,
+            None => [NONE, state[1]],
+        };
+        let n = candidates(from).count() as u32;
+        if n == 0 {
+            return None;
+        }
+        let total: u32 = candidates(from).map(|t| t.count as u32).sum();
+        let max = candidates(from).map(|t| t.count).max().unwrap_or(0);
+        let num_max = candidates(from).filter(|t| t.count == max).count() as u32;
+
+        // in 1/65536ths, blending from always the most likely one (0), over
+        // the learned probabilities (`MARKOV_TEMPERATURE`) to all equally likely (200)
+        let temperature = temperature.min(2 * MARKOV_TEMPERATURE) as u32;
+        let weight = |t: &Transition| -> u32 {
+            let learned = 0x10000 * t.count as u32 / total;
+            if temperature <= 100 {
+                let greedy = if t.count == max { 0x10000 / num_max } else { 0 };
+                (greedy * (100 - temperature) + learned * temperature) / 100
+            } else {
+                let uniform = 0x10000 / n;
+                (learned * (200 - temperature) + uniform * (temperature - 100)) / 100
+            }
+        };
+
+        let sum: u32 = candidates(from).map(weight).sum();
+        let mut pick = rng.below(sum.max(1));
+        for t in candidates(from) {
+            let w = weight(t);
+            if pick < w {
+                return Some(t.to);
+            }
+            pick -= w;
+        }
+        candidates(from).next_back().map(|t| t.to)
+    }
+
+    /// Fill the whole of `track` with new material. `order` is 1 or 2, `temperature`
+    /// goes from 0 (most likely choices only) to 200 (any learned transition).
+    pub fn generate(&self, track: &mut VoiceTrack, order: u8, temperature: u8, seed: u32) {
+        let mut rng = Rng::new(seed);
+        let mut state = self.start;
+        let mut step = 0;
+
+        while step < track.len() {
+            let event = match self.symbols.get(state[1] as usize) {
+                Some(event) => *event,
+                // nothing learned
+                None => Event { pitch: REST, length: u8::MAX },
+            };
+
+            for n in 0..event.length as usize {
+                if step >= track.len() {
+                    break;
+                }
+                let state = match (event.pitch, n) {
+                    (REST, _) => (None, NoteFlag::None),
+                    (pitch, 0) => (Some(NotePair::from(pitch)), NoteFlag::Note),
+                    (pitch, _) => (Some(NotePair::from(pitch)), NoteFlag::Legato),
+                };
+                track.set_note(step, state).unwrap();
+                step += 1;
+            }
+
+            let from = if order >= 2 { state } else { [NONE, state[1]] };
+            state = match self.next(from, temperature, &mut rng) {
+                Some(next) => [state[1], next],
+                // start over
+                None => self.start,
+            };
+        }
+    }
+}
+
+/// The notes and rests of `track`
+fn events(track: &VoiceTrack) -> alloc::vec::Vec<Event> {
+    let mut events: alloc::vec::Vec<Event> = alloc::vec::Vec::new();
+    for step in 0..track.len() {
+        let (note, flag) = track.get_note(step).unwrap();
+        let pitch = match note {
+            Some(np) => u8::try_from(&np).unwrap_or(REST),
+            None => REST,
+        };
+
+        match (events.last_mut(), flag) {
+            // ties and rests (after a rest) make the last event longer
+            (Some(last), NoteFlag::Legato) if last.pitch != REST && last.length < u8::MAX => {
+                last.length += 1
+            }
+            (Some(last), NoteFlag::None) if last.pitch == REST && last.length < u8::MAX => {
+                last.length += 1
+            }
+            _ => events.push(Event { pitch, length: 1 }),
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::{Markov, MARKOV_TEMPERATURE};
+    use crate::{Note, NoteFlag, NotePair, VoiceTrack};
+
+    fn track(steps: &[(Option<NotePair>, NoteFlag)]) -> VoiceTrack {
+        let mut track = VoiceTrack::new(steps.len());
+        for (n, step) in steps.iter().enumerate() {
+            track.set_note(n, *step).unwrap();
+        }
+        track
+    }
+
+    fn steps(track: &VoiceTrack) -> Vec<(Option<NotePair>, NoteFlag)> {
+        (0..track.len()).map(|n| track.get_note(n).unwrap()).collect()
+    }
+
+    const REST: (Option<NotePair>, NoteFlag) = (None, NoteFlag::None);
+
+    fn note(n: Note) -> (Option<NotePair>, NoteFlag) {
+        (Some(NotePair(n, 4)), NoteFlag::Note)
+    }
+
+    fn tie(n: Note) -> (Option<NotePair>, NoteFlag) {
+        (Some(NotePair(n, 4)), NoteFlag::Legato)
+    }
+
+    #[test]
+    fn test_unambiguous_source() {
+        // every event has a single successor, so the source comes out again
+        let source = track(&[note(Note::C), tie(Note::C), note(Note::E), REST]);
+        let chain = Markov::new(&source);
+
+        for temperature in [0, MARKOV_TEMPERATURE, 200] {
+            let mut out = VoiceTrack::new(8);
+            chain.generate(&mut out, 1, temperature, 1);
+            let mut expected = steps(&source);
+            expected.extend(steps(&source));
+            assert_eq!(steps(&out), expected);
+        }
+    }
+
+    #[test]
+    fn test_second_order() {
+        // after A comes B or C at first order, but (C, A) is always followed by B
+        let source = track(&[note(Note::A), note(Note::B), note(Note::A), note(Note::C)]);
+        let chain = Markov::new(&source);
+
+        for seed in 1..10 {
+            let mut out = VoiceTrack::new(16);
+            chain.generate(&mut out, 2, 200, seed);
+            let mut expected = Vec::new();
+            for _ in 0..4 {
+                expected.extend(steps(&source));
+            }
+            assert_eq!(steps(&out), expected);
+        }
+
+        // first order makes variations
+        let variations = (1..20).any(|seed| {
+            let mut out = VoiceTrack::new(16);
+            chain.generate(&mut out, 1, MARKOV_TEMPERATURE, seed);
+            steps(&out)[..4] != steps(&source)[..]
+                || steps(&out)[4..8] != steps(&source)[..]
+        });
+        assert!(variations);
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let source = track(&[
+            note(Note::C),
+            note(Note::D),
+            REST,
+            note(Note::C),
+            note(Note::E),
+            tie(Note::E),
+            note(Note::D),
+            note(Note::C),
+        ]);
+        let chain = Markov::new(&source);
+
+        let mut a = VoiceTrack::new(32);
+        let mut b = VoiceTrack::new(32);
+        chain.generate(&mut a, 1, 150, 1234);
+        chain.generate(&mut b, 1, 150, 1234);
+        assert_eq!(steps(&a), steps(&b));
+
+        // only learned pitches
+        for (note, _) in steps(&a) {
+            if let Some(np) = note {
+                assert!([Note::C, Note::D, Note::E].contains(&np.0));
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_source() {
+        let chain = Markov::new(&VoiceTrack::new(4));
+        let mut out = track(&[note(Note::C); 4]);
+        chain.generate(&mut out, 2, MARKOV_TEMPERATURE, 1);
+        assert_eq!(steps(&out), [REST; 4]);
+    }
+}
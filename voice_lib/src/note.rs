@@ -1,3 +1,5 @@
+use core::str::FromStr;
+
 use serde::{Serialize, Deserialize};
 use ufmt::{uDisplay, uWrite, Formatter, uwrite, derive::uDebug};
 
@@ -23,30 +25,63 @@ impl uDisplay for Note {
     where
         W: uWrite + ?Sized,
     {
-        fmt.write_str(match self {
-            Note::C => "C",
-            Note::Db => "Db",
-            Note::D => "D",
-            Note::Eb => "Eb",
-            Note::E => "E",
-            Note::F => "F",
-            Note::Gb => "Gb",
-            Note::G => "G",
-            Note::Ab => "Ab",
-            Note::A => "A",
-            Note::Bb => "Bb",
-            Note::B => "B",
-        })
+        fmt.write_str(self.name(Accidentals::Flats))
     }
 }
 
+/// How to name the black keys
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Accidentals {
+    #[default]
+    Flats,
+    Sharps,
+}
+
 impl Note {
-    pub fn is_black_key(&self) -> bool {
-        match self {
-            Note::Db | Note::Eb | Note::Gb | Note::Ab | Note::Bb => true,
-            _ => false,
+    pub fn name(&self, accidentals: Accidentals) -> &'static str {
+        match (self, accidentals) {
+            (Note::Db, Accidentals::Sharps) => "C#",
+            (Note::Eb, Accidentals::Sharps) => "D#",
+            (Note::Gb, Accidentals::Sharps) => "F#",
+            (Note::Ab, Accidentals::Sharps) => "G#",
+            (Note::Bb, Accidentals::Sharps) => "A#",
+            (Note::C, _) => "C",
+            (Note::Db, _) => "Db",
+            (Note::D, _) => "D",
+            (Note::Eb, _) => "Eb",
+            (Note::E, _) => "E",
+            (Note::F, _) => "F",
+            (Note::Gb, _) => "Gb",
+            (Note::G, _) => "G",
+            (Note::Ab, _) => "Ab",
+            (Note::A, _) => "A",
+            (Note::Bb, _) => "Bb",
+            (Note::B, _) => "B",
+        }
+    }
+
+    /// Note `n` semitones above C (wraps around every octave)
+    pub fn from_semitone(n: u8) -> Self {
+        match n % 12 {
+            0 => Note::C,
+            1 => Note::Db,
+            2 => Note::D,
+            3 => Note::Eb,
+            4 => Note::E,
+            5 => Note::F,
+            6 => Note::Gb,
+            7 => Note::G,
+            8 => Note::Ab,
+            9 => Note::A,
+            10 => Note::Bb,
+            11 => Note::B,
+            _ => unreachable!(),
         }
     }
+
+    pub fn is_black_key(&self) -> bool {
+        matches!(self, Note::Db | Note::Eb | Note::Gb | Note::Ab | Note::Bb)
+    }
 }
 
 // valid range is the same as MIDI: C-1 - G9
@@ -54,38 +89,93 @@ impl Note {
 pub struct NotePair(pub Note, pub i8);
 
 impl uDisplay for NotePair {
+    fn fmt<W>(&self, fmt: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        uwrite!(fmt, "{}", self.display(Accidentals::Flats))
+    }
+}
+
+/// A `NotePair`, displayed with the chosen accidentals
+pub struct NotePairDisplay(NotePair, Accidentals);
+
+impl uDisplay for NotePairDisplay {
     fn fmt<W>(&self, fmt: &mut Formatter<'_, W>) -> Result<(), W::Error>
     where
         W: uWrite + ?Sized,
     {
         let mut out = heapless::String::<8>::new();
-        uwrite!(out, "{}{}", self.0, self.1).unwrap();
+        uwrite!(out, "{}{}", self.0 .0.name(self.1), self.0 .1).unwrap();
         fmt.write_str(&out)
     }
 }
 
+/// Parses note names like `"C4"`, `"C#4"`, `"Db4"` or `"c-1"`. Letters are
+/// case-insensitive; enharmonics outside of the octave (`"Cb4"`, `"B#3"`) work too.
+impl FromStr for NotePair {
+    type Err = InvalidNotePair;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        let note = match chars.next().map(|c| c.to_ascii_uppercase()) {
+            Some('C') => Note::C,
+            Some('D') => Note::D,
+            Some('E') => Note::E,
+            Some('F') => Note::F,
+            Some('G') => Note::G,
+            Some('A') => Note::A,
+            Some('B') => Note::B,
+            _ => return Err(InvalidNotePair),
+        };
+
+        let rest = chars.as_str();
+        let (accidental, octave) = match rest.as_bytes().first() {
+            Some(b'#') => (1, &rest[1..]),
+            Some(b'b') => (-1, &rest[1..]),
+            _ => (0, rest),
+        };
+        let octave: i8 = octave.parse().map_err(|_| InvalidNotePair)?;
+
+        let np = NotePair::from_semitones(NotePair(note, octave).semitones() + accidental);
+        u8::try_from(&np)?;
+        Ok(np)
+    }
+}
+
+/// MIDI note number to note. Values above 127 aren't valid MIDI notes, they
+/// carry on past G9 (and can't be converted back).
 impl From<u8> for NotePair {
     fn from(val: u8) -> Self {
-        let note = match (val as i8 - 12) % 12 {
-            0 => Note::C,
-            1 => Note::Db,
-            2 => Note::D,
-            3 => Note::Eb,
-            4 => Note::E,
-            5 => Note::F,
-            6 => Note::Gb,
-            7 => Note::G,
-            8 => Note::Ab,
-            9 => Note::A,
-            10 => Note::Bb,
-            11 => Note::B,
-            _ => unreachable!(),
-        };
-        NotePair(note, (val as i8 - 12) / 12)
+        NotePair::from_semitones(val as i16)
     }
 }
 
-#[derive(Debug)]
+impl NotePair {
+    /// e.g. `uwrite!(out, "{}", np.display(Accidentals::Sharps))`
+    pub fn display(&self, accidentals: Accidentals) -> NotePairDisplay {
+        NotePairDisplay(*self, accidentals)
+    }
+
+    /// Semitones since C-1 (may be outside of the MIDI range)
+    pub(crate) fn semitones(&self) -> i16 {
+        (self.1 as i16 + 1) * 12 + self.0 as i16
+    }
+
+    /// Chromatic transposition, kept within the MIDI range
+    pub fn transposed(&self, semitones: i8) -> Self {
+        NotePair::from_semitones((self.semitones() + semitones as i16).clamp(0, 127))
+    }
+
+    pub(crate) fn from_semitones(n: i16) -> Self {
+        NotePair(
+            Note::from_semitone(n.rem_euclid(12) as u8),
+            (n.div_euclid(12) - 1) as i8,
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct InvalidNotePair;
 
 impl TryFrom<&NotePair> for u8 {
@@ -99,3 +189,72 @@ impl TryFrom<&NotePair> for u8 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use heapless::String;
+    use ufmt::uwrite;
+
+    use super::{Accidentals, Note, NotePair};
+
+    #[test]
+    fn test_midi_round_trip() {
+        assert_eq!(NotePair::from(0), NotePair(Note::C, -1));
+        assert_eq!(NotePair::from(11), NotePair(Note::B, -1));
+        assert_eq!(NotePair::from(60), NotePair(Note::C, 4));
+        assert_eq!(NotePair::from(127), NotePair(Note::G, 9));
+        for n in 0..=127u8 {
+            assert_eq!(u8::try_from(&NotePair::from(n)).unwrap(), n);
+        }
+        for n in 128..=255u8 {
+            assert!(u8::try_from(&NotePair::from(n)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_transposed() {
+        assert_eq!(NotePair(Note::C, 4).transposed(7), NotePair(Note::G, 4));
+        assert_eq!(NotePair(Note::C, 4).transposed(-1), NotePair(Note::B, 3));
+        assert_eq!(NotePair(Note::D, -1).transposed(-5), NotePair(Note::C, -1));
+        assert_eq!(NotePair(Note::F, 9).transposed(12), NotePair(Note::G, 9));
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for n in 0..=127u8 {
+            let np = NotePair::from(n);
+            for accidentals in [Accidentals::Flats, Accidentals::Sharps] {
+                let mut out = String::<8>::new();
+                uwrite!(out, "{}", np.display(accidentals)).unwrap();
+                assert_eq!(out.parse::<NotePair>().unwrap(), np);
+                assert_eq!(out.to_ascii_lowercase().parse::<NotePair>().unwrap(), np);
+            }
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let mut out = String::<8>::new();
+        uwrite!(out, "{} {}", NotePair(Note::Db, 4), NotePair(Note::Db, 4).display(Accidentals::Sharps)).unwrap();
+        assert_eq!(out, "Db4 C#4");
+        out.clear();
+        uwrite!(out, "{}", NotePair(Note::Gb, -1).display(Accidentals::Sharps)).unwrap();
+        assert_eq!(out, "F#-1");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("C#4".parse(), Ok(NotePair(Note::Db, 4)));
+        assert_eq!("Db4".parse(), Ok(NotePair(Note::Db, 4)));
+        assert_eq!("c-1".parse(), Ok(NotePair(Note::C, -1)));
+        assert_eq!("bb3".parse(), Ok(NotePair(Note::Bb, 3)));
+        assert_eq!("B#3".parse(), Ok(NotePair(Note::C, 4)));
+        assert_eq!("Cb4".parse(), Ok(NotePair(Note::B, 3)));
+        assert!("Cb-1".parse::<NotePair>().is_err());
+        assert!("G#9".parse::<NotePair>().is_err());
+        assert!("H4".parse::<NotePair>().is_err());
+        assert!("C##4".parse::<NotePair>().is_err());
+        assert!("C".parse::<NotePair>().is_err());
+        assert!("".parse::<NotePair>().is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+pub const MAX_CLOCK_RATIO: u8 = 8;
+
+/// How fast a track runs against the master transport: `mult` of its steps
+/// for every `div` master steps.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockRatio {
+    pub mult: u8,
+    pub div: u8,
+}
+
+impl Default for ClockRatio {
+    fn default() -> Self {
+        Self { mult: 1, div: 1 }
+    }
+}
+
+impl ClockRatio {
+    pub fn new(mult: u8, div: u8) -> Self {
+        Self {
+            mult: mult.clamp(1, MAX_CLOCK_RATIO),
+            div: div.clamp(1, MAX_CLOCK_RATIO),
+        }
+    }
+
+    /// Position of the track, `beat` master steps plus `frac`/`unit` of one after
+    /// the tracks were reset, as (step, fraction of `unit`). It's calculated from
+    /// the master position every time, so tracks never drift apart.
+    pub fn scale(&self, beat: u32, frac: u32, unit: u32) -> (u32, u32) {
+        let unit = unit as u64;
+        let total = (beat as u64 * unit + frac as u64) * self.mult as u64 / self.div as u64;
+        ((total / unit) as u32, (total % unit) as u32)
+    }
+
+    /// Step of a track of `len` steps, `beat` master steps after the reset
+    pub fn step(&self, beat: u32, len: usize) -> usize {
+        self.scale(beat, 0, 1).0 as usize % len
+    }
+
+    /// Master steps it takes a track of `len` steps to be back at its first step,
+    /// right on a master step
+    pub fn period(&self, len: usize) -> u64 {
+        let steps = len as u64 * self.div as u64;
+        steps / gcd(steps, self.mult as u64)
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClockRatio;
+
+    #[test]
+    fn test_scale() {
+        let straight = ClockRatio::default();
+        assert_eq!(straight.scale(7, 300, 1000), (7, 300));
+
+        let double = ClockRatio::new(2, 1);
+        assert_eq!(double.scale(7, 300, 1000), (14, 600));
+        assert_eq!(double.scale(7, 600, 1000), (15, 200));
+
+        let quarter = ClockRatio::new(1, 4);
+        assert_eq!(quarter.scale(7, 0, 1000), (1, 750));
+
+        // out of range ratios are clamped
+        assert_eq!(ClockRatio::new(0, 20), ClockRatio::new(1, 8));
+    }
+
+    #[test]
+    fn test_polymeter() {
+        let straight = ClockRatio::default();
+        // 5 against 16: both start over together every 80 steps
+        let together = |beat| straight.step(beat, 5) == 0 && straight.step(beat, 16) == 0;
+        assert_eq!((1..1000).find(|&beat| together(beat)), Some(80));
+
+        // and still do after a long run
+        let beat = 80 * 1_000_000;
+        assert!(together(beat));
+        assert_eq!(straight.step(beat + 7, 5), 2);
+        assert_eq!(straight.step(beat + 7, 16), 7);
+        assert!(!together(beat - 1));
+    }
+
+    #[test]
+    fn test_long_run() {
+        // 3 steps every 2 master steps, over millions of steps, is exactly 3:2
+        let ratio = ClockRatio::new(3, 2);
+        let unit = 600_000;
+        assert_eq!(ratio.scale(2_000_000, 0, unit), (3_000_000, 0));
+        assert_eq!(ratio.scale(2_000_001, 0, unit), (3_000_001, unit / 2));
+        assert_eq!(ratio.scale(2_000_001, unit / 2, unit), (3_000_002, unit / 4));
+
+        // a track moving in steps of its own never falls behind the scaled master
+        let mut steps = 0;
+        for beat in 0..100_000 {
+            let (step, _) = ratio.scale(beat, 0, unit);
+            assert!(step >= steps && step - steps <= 2);
+            steps = step;
+        }
+        assert_eq!(steps, 149_998);
+    }
+
+    #[test]
+    fn test_period() {
+        let straight = ClockRatio::default();
+        assert_eq!(straight.period(16), 16);
+
+        // 5 steps at 3:2 are back at the first one, right on a master step,
+        // after 10 master steps (having played through 3 times)
+        let ratio = ClockRatio::new(3, 2);
+        assert_eq!(ratio.period(5), 10);
+        assert_eq!(ratio.step(10, 5), 0);
+        assert_eq!(ratio.scale(10, 0, 1000), (15, 0));
+
+        let slow = ClockRatio::new(1, 3);
+        assert_eq!(slow.period(4), 12);
+
+        // a 5 step track at half speed and a 16 step one at 3:2 realign at the
+        // least common multiple of their periods, however long they ran
+        let half = ClockRatio::new(1, 2);
+        let (a, b) = (half.period(5), ratio.period(16));
+        assert_eq!((a, b), (10, 32));
+        // both starting their first step, not just somewhere in it
+        let starts = |r: ClockRatio, len: u32, beat| {
+            let (step, frac) = r.scale(beat, 0, 1000);
+            frac == 0 && step % len == 0
+        };
+        let together = |beat| starts(half, 5, beat) && starts(ratio, 16, beat);
+        assert_eq!((1..1000).find(|&beat| together(beat)), Some(160));
+        for n in [7u32, 1_000, 20_000] {
+            let beat = 160 * n;
+            assert_eq!(half.scale(beat, 0, 1000), (80 * n, 0));
+            assert!(together(beat));
+            assert!(!together(beat - 32));
+        }
+    }
+}
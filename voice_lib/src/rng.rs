@@ -0,0 +1,64 @@
+/// Small seedable pseudo-random number generator (xorshift32), so that
+/// random patterns can be reproduced from their seed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rng(u32);
+
+impl Rng {
+    pub fn new(seed: u32) -> Self {
+        // xorshift gets stuck on 0
+        Rng(if seed == 0 { 0x9e37_79b9 } else { seed })
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+
+    /// Random number in `0..n` (`n` must not be 0)
+    pub fn below(&mut self, n: u32) -> u32 {
+        ((self.next_u32() as u64 * n as u64) >> 32) as u32
+    }
+
+    /// `true` with a probability of `percent`%
+    pub fn chance(&mut self, percent: u8) -> bool {
+        self.below(100) < percent as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rng;
+
+    #[test]
+    fn test_deterministic() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u32(), b.next_u32());
+        }
+        assert_ne!(Rng::new(1).next_u32(), Rng::new(2).next_u32());
+        // 0 is a valid seed too
+        assert_ne!(Rng::new(0).next_u32(), 0);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut rng = Rng::new(7);
+        let mut seen = [false; 10];
+        for _ in 0..1000 {
+            let n = rng.below(10);
+            assert!(n < 10);
+            seen[n as usize] = true;
+        }
+        assert!(seen.iter().all(|s| *s));
+
+        assert!((0..100).all(|_| !rng.chance(0)));
+        assert!((0..100).all(|_| rng.chance(100)));
+        let hits = (0..1000).filter(|_| rng.chance(25)).count();
+        assert!((150..350).contains(&hits));
+    }
+}
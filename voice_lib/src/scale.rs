@@ -0,0 +1,228 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Note, NotePair};
+
+/// A set of pitch classes, relative to the root of a key
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scale {
+    Major,
+    Minor,
+    Dorian,
+    Pentatonic,
+    Chromatic,
+    /// bit `n` set = `n` semitones above the root is part of the scale
+    User(u16),
+}
+
+impl Scale {
+    pub fn mask(&self) -> u16 {
+        match self {
+            Scale::Major => 0b1010_1011_0101,
+            Scale::Minor => 0b0101_1010_1101,
+            Scale::Dorian => 0b0110_1010_1101,
+            Scale::Pentatonic => 0b0010_1001_0101,
+            Scale::Chromatic => 0b1111_1111_1111,
+            Scale::User(mask) => mask & 0xfff,
+        }
+    }
+
+    pub fn contains(&self, semitones: u8) -> bool {
+        self.mask() & (1 << (semitones % 12)) != 0
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Key {
+    pub root: Note,
+    pub scale: Scale,
+}
+
+impl Default for Key {
+    fn default() -> Self {
+        Self {
+            root: Note::C,
+            scale: Scale::Chromatic,
+        }
+    }
+}
+
+impl Key {
+    pub fn new(root: Note, scale: Scale) -> Self {
+        Self { root, scale }
+    }
+
+    fn in_scale(&self, semitones: i16) -> bool {
+        self.scale
+            .contains((semitones - self.root as i16).rem_euclid(12) as u8)
+    }
+
+    pub fn contains(&self, np: &NotePair) -> bool {
+        self.in_scale(np.semitones())
+    }
+
+    /// Nearest note which is part of the key (the lower one, if two are as near)
+    pub fn quantize(&self, np: &NotePair) -> NotePair {
+        let n = np.semitones();
+
+        if self.scale.mask() == 0 {
+            return *np;
+        }
+
+        (0..12)
+            .flat_map(|d| [n - d, n + d])
+            .find(|n| self.in_scale(*n))
+            .map(NotePair::from_semitones)
+            .unwrap_or(*np)
+    }
+
+    /// Move `np` by `degrees` steps of the scale, after quantizing it
+    pub fn transpose(&self, np: &NotePair, degrees: i8) -> NotePair {
+        if self.scale.mask() == 0 {
+            return *np;
+        }
+
+        let mut n = self.quantize(np).semitones();
+        let step = degrees.signum() as i16;
+
+        for _ in 0..degrees.unsigned_abs() {
+            n += step;
+            while !self.in_scale(n) {
+                n += step;
+            }
+        }
+        NotePair::from_semitones(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Key, Scale};
+    use crate::{Note, NotePair};
+
+    const ALL_NOTES: [Note; 12] = [
+        Note::C,
+        Note::Db,
+        Note::D,
+        Note::Eb,
+        Note::E,
+        Note::F,
+        Note::Gb,
+        Note::G,
+        Note::Ab,
+        Note::A,
+        Note::Bb,
+        Note::B,
+    ];
+
+    fn notes_in(key: &Key) -> heapless::Vec<Note, 12> {
+        ALL_NOTES
+            .iter()
+            .filter(|n| key.contains(&NotePair(**n, 4)))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn test_major() {
+        let key = Key::new(Note::C, Scale::Major);
+        assert_eq!(
+            notes_in(&key),
+            [Note::C, Note::D, Note::E, Note::F, Note::G, Note::A, Note::B]
+        );
+        assert_eq!(key.quantize(&NotePair(Note::Db, 4)), NotePair(Note::C, 4));
+        assert_eq!(key.quantize(&NotePair(Note::Bb, 4)), NotePair(Note::A, 4));
+        assert_eq!(key.transpose(&NotePair(Note::C, 4), 2), NotePair(Note::E, 4));
+        assert_eq!(key.transpose(&NotePair(Note::B, 4), 1), NotePair(Note::C, 5));
+        assert_eq!(key.transpose(&NotePair(Note::C, 4), 7), NotePair(Note::C, 5));
+    }
+
+    #[test]
+    fn test_major_other_root() {
+        let key = Key::new(Note::G, Scale::Major);
+        assert_eq!(
+            notes_in(&key),
+            [Note::C, Note::D, Note::E, Note::Gb, Note::G, Note::A, Note::B]
+        );
+        assert_eq!(key.quantize(&NotePair(Note::F, 4)), NotePair(Note::E, 4));
+        assert_eq!(key.transpose(&NotePair(Note::E, 4), 1), NotePair(Note::Gb, 4));
+    }
+
+    #[test]
+    fn test_minor() {
+        let key = Key::new(Note::A, Scale::Minor);
+        assert_eq!(
+            notes_in(&key),
+            [Note::C, Note::D, Note::E, Note::F, Note::G, Note::A, Note::B]
+        );
+        let key = Key::new(Note::C, Scale::Minor);
+        assert_eq!(
+            notes_in(&key),
+            [Note::C, Note::D, Note::Eb, Note::F, Note::G, Note::Ab, Note::Bb]
+        );
+        assert_eq!(key.quantize(&NotePair(Note::E, 4)), NotePair(Note::Eb, 4));
+        assert_eq!(key.transpose(&NotePair(Note::C, 4), 2), NotePair(Note::Eb, 4));
+        assert_eq!(key.transpose(&NotePair(Note::C, 4), -1), NotePair(Note::Bb, 3));
+    }
+
+    #[test]
+    fn test_dorian() {
+        let key = Key::new(Note::D, Scale::Dorian);
+        assert_eq!(
+            notes_in(&key),
+            [Note::C, Note::D, Note::E, Note::F, Note::G, Note::A, Note::B]
+        );
+        let key = Key::new(Note::C, Scale::Dorian);
+        assert_eq!(
+            notes_in(&key),
+            [Note::C, Note::D, Note::Eb, Note::F, Note::G, Note::A, Note::Bb]
+        );
+        assert_eq!(key.quantize(&NotePair(Note::Ab, 4)), NotePair(Note::G, 4));
+        assert_eq!(key.transpose(&NotePair(Note::G, 4), 2), NotePair(Note::Bb, 4));
+    }
+
+    #[test]
+    fn test_pentatonic() {
+        let key = Key::new(Note::C, Scale::Pentatonic);
+        assert_eq!(
+            notes_in(&key),
+            [Note::C, Note::D, Note::E, Note::G, Note::A]
+        );
+        assert_eq!(key.quantize(&NotePair(Note::F, 4)), NotePair(Note::E, 4));
+        assert_eq!(key.quantize(&NotePair(Note::B, 4)), NotePair(Note::C, 5));
+        assert_eq!(key.transpose(&NotePair(Note::A, 4), 1), NotePair(Note::C, 5));
+        assert_eq!(key.transpose(&NotePair(Note::C, 4), -5), NotePair(Note::C, 3));
+    }
+
+    #[test]
+    fn test_chromatic() {
+        let key = Key::new(Note::Eb, Scale::Chromatic);
+        assert_eq!(notes_in(&key), ALL_NOTES);
+        for n in ALL_NOTES {
+            assert_eq!(key.quantize(&NotePair(n, 3)), NotePair(n, 3));
+        }
+        assert_eq!(key.transpose(&NotePair(Note::B, 3), 1), NotePair(Note::C, 4));
+        assert_eq!(key.transpose(&NotePair(Note::C, 4), -12), NotePair(Note::C, 3));
+    }
+
+    #[test]
+    fn test_user() {
+        // root and fifth only
+        let key = Key::new(Note::D, Scale::User(0b0000_1000_0001));
+        assert_eq!(notes_in(&key), [Note::D, Note::A]);
+        assert_eq!(key.quantize(&NotePair(Note::E, 4)), NotePair(Note::D, 4));
+        assert_eq!(key.quantize(&NotePair(Note::G, 4)), NotePair(Note::A, 4));
+        assert_eq!(key.transpose(&NotePair(Note::D, 4), 3), NotePair(Note::A, 5));
+
+        // empty scale leaves everything alone
+        let key = Key::new(Note::C, Scale::User(0));
+        assert_eq!(key.quantize(&NotePair(Note::E, 4)), NotePair(Note::E, 4));
+        assert_eq!(key.transpose(&NotePair(Note::E, 4), 2), NotePair(Note::E, 4));
+    }
+
+    #[test]
+    fn test_out_of_range_octaves() {
+        let key = Key::new(Note::C, Scale::Major);
+        assert_eq!(key.quantize(&NotePair(Note::Db, -1)), NotePair(Note::C, -1));
+        assert_eq!(key.transpose(&NotePair(Note::C, -1), -1), NotePair(Note::B, -2));
+    }
+}
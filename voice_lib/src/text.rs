@@ -0,0 +1,212 @@
+use core::iter::Peekable;
+
+use ufmt::{uWrite, uwrite};
+
+use crate::{NoteFlag, NotePair, TrigCondition, VoiceTrack, MAX_MOD_VALUE, MAX_RATCHETS};
+
+type StepState = (Option<NotePair>, NoteFlag);
+
+#[derive(Debug, PartialEq)]
+pub struct InvalidStep;
+
+/// A step line, as read by `VoiceTrack::parse_step`
+#[derive(Debug, PartialEq)]
+pub struct ParsedStep {
+    pub state: StepState,
+    pub gate: Option<u8>,
+    pub condition: TrigCondition,
+    pub ratchets: u8,
+    pub modulation: Option<u16>,
+}
+
+impl ParsedStep {
+    /// Store the step as step `n` of `track`
+    pub fn apply(&self, track: &mut VoiceTrack, n: usize) -> Result<(), InvalidStep> {
+        track.set_note(n, self.state).map_err(|_| InvalidStep)?;
+        track.set_gate(n, self.gate);
+        track.set_condition(n, self.condition);
+        track.set_ratchets(n, self.ratchets);
+        track.set_modulation(n, self.modulation);
+        Ok(())
+    }
+}
+
+/// Plain-text form of a track: one line per step, e.g. `C5 N` (note),
+/// `C5 L` (legato) or `--` (rest). A gate length override follows the
+/// flag, as % of the step: `C5 N 50`, then the number of ratchets, the
+/// modulation value and the trig condition, if any: `C5 N 50 x3 m2048 1:4`.
+/// Rests can have a modulation value too: `-- m100`.
+impl VoiceTrack {
+    pub fn write_text<W: uWrite + ?Sized>(&self, w: &mut W) -> Result<(), W::Error> {
+        for n in 0..self.len() {
+            match self.get_note(n).unwrap() {
+                (Some(np), flag @ (NoteFlag::Note | NoteFlag::Legato)) => {
+                    let flag = if flag == NoteFlag::Note { "N" } else { "L" };
+                    uwrite!(w, "{} {}", np, flag)?;
+                    if let Some(gate) = self.get_gate(n) {
+                        uwrite!(w, " {}", gate)?;
+                    }
+                    let ratchets = self.get_ratchets(n);
+                    if ratchets > 1 {
+                        uwrite!(w, " x{}", ratchets)?;
+                    }
+                    if let Some(value) = self.get_modulation(n) {
+                        uwrite!(w, " m{}", value)?;
+                    }
+                    let condition = self.get_condition(n);
+                    if condition != TrigCondition::Always {
+                        uwrite!(w, " {}", condition)?;
+                    }
+                }
+                _ => {
+                    w.write_str("--")?;
+                    if let Some(value) = self.get_modulation(n) {
+                        uwrite!(w, " m{}", value)?;
+                    }
+                }
+            }
+            w.write_char('\n')?;
+        }
+        Ok(())
+    }
+
+    /// Parse a step line, as written by `write_text`
+    pub fn parse_step(line: &str) -> Result<ParsedStep, InvalidStep> {
+        let mut tokens = line.split_whitespace().peekable();
+
+        let note = match tokens.next() {
+            Some("--") => {
+                let modulation = parse_modulation(&mut tokens)?;
+                return match tokens.next() {
+                    None => Ok(ParsedStep {
+                        state: (None, NoteFlag::None),
+                        gate: None,
+                        condition: TrigCondition::Always,
+                        ratchets: 1,
+                        modulation,
+                    }),
+                    Some(_) => Err(InvalidStep),
+                };
+            }
+            Some(note) => note.parse::<NotePair>().map_err(|_| InvalidStep)?,
+            None => return Err(InvalidStep),
+        };
+        let flag = match tokens.next() {
+            Some("N") => NoteFlag::Note,
+            Some("L") => NoteFlag::Legato,
+            _ => return Err(InvalidStep),
+        };
+        let gate = match tokens.next_if(|t| t.bytes().all(|b| b.is_ascii_digit())) {
+            Some(gate) => match gate.parse::<u8>() {
+                Ok(gate @ 1..=100) => Some(gate),
+                _ => return Err(InvalidStep),
+            },
+            None => None,
+        };
+        let ratchets = match tokens.next_if(|t| t.starts_with('x')) {
+            Some(ratchets) => match ratchets[1..].parse::<u8>() {
+                Ok(n @ 1..=MAX_RATCHETS) => n,
+                _ => return Err(InvalidStep),
+            },
+            None => 1,
+        };
+        let modulation = parse_modulation(&mut tokens)?;
+        let condition = match tokens.next() {
+            Some(cond) => cond.parse::<TrigCondition>().map_err(|_| InvalidStep)?,
+            None => TrigCondition::Always,
+        };
+        if tokens.next().is_some() {
+            return Err(InvalidStep);
+        }
+
+        Ok(ParsedStep {
+            state: (Some(note), flag),
+            gate,
+            condition,
+            ratchets,
+            modulation,
+        })
+    }
+}
+
+/// Optional `m<value>` token
+fn parse_modulation<'a, I: Iterator<Item = &'a str>>(
+    tokens: &mut Peekable<I>,
+) -> Result<Option<u16>, InvalidStep> {
+    match tokens.next_if(|t| t.starts_with('m')) {
+        Some(value) => match value[1..].parse::<u16>() {
+            Ok(v) if v <= MAX_MOD_VALUE => Ok(Some(v)),
+            _ => Err(InvalidStep),
+        },
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::String;
+
+    use crate::{Note, NoteFlag, NotePair, TrigCondition, VoiceTrack};
+
+    use super::InvalidStep;
+
+    #[test]
+    fn test_note_pair_from_str() {
+        assert_eq!("C5".parse(), Ok(NotePair(Note::C, 5)));
+        assert_eq!("Bb3".parse(), Ok(NotePair(Note::Bb, 3)));
+        assert_eq!("Eb-1".parse(), Ok(NotePair(Note::Eb, -1)));
+        assert_eq!("G9".parse(), Ok(NotePair(Note::G, 9)));
+        assert!("Ab9".parse::<NotePair>().is_err());
+        assert!("H4".parse::<NotePair>().is_err());
+        assert!("C".parse::<NotePair>().is_err());
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut track = VoiceTrack::new(4);
+        track.set_note(0, (Some(NotePair(Note::C, 5)), NoteFlag::Note)).unwrap();
+        track.set_note(1, (Some(NotePair(Note::C, 5)), NoteFlag::Legato)).unwrap();
+        track.set_note(3, (Some(NotePair(Note::Gb, 2)), NoteFlag::Note)).unwrap();
+        track.set_gate(3, Some(50));
+        track.set_condition(3, TrigCondition::Every(1, 4));
+        track.set_ratchets(3, 3);
+        track.set_modulation(3, Some(2048));
+        track.set_modulation(2, Some(0));
+
+        let mut out = String::<64>::new();
+        track.write_text(&mut out).unwrap();
+        assert_eq!(out, "C5 N\nC5 L\n-- m0\nGb2 N 50 x3 m2048 1:4\n");
+
+        let mut parsed = VoiceTrack::new(4);
+        for (n, line) in out.lines().enumerate() {
+            VoiceTrack::parse_step(line).unwrap().apply(&mut parsed, n).unwrap();
+        }
+        for n in 0..4 {
+            assert_eq!(parsed.get_note(n), track.get_note(n));
+            assert_eq!(parsed.get_gate(n), track.get_gate(n));
+            assert_eq!(parsed.get_condition(n), track.get_condition(n));
+            assert_eq!(parsed.get_ratchets(n), track.get_ratchets(n));
+            assert_eq!(parsed.get_modulation(n), track.get_modulation(n));
+        }
+
+        let step = VoiceTrack::parse_step("C5 N 30%").unwrap();
+        assert_eq!(step.gate, None);
+        assert_eq!(step.condition, TrigCondition::Probability(30));
+        assert_eq!(VoiceTrack::parse_step("C5 L x8").unwrap().ratchets, 8);
+    }
+
+    #[test]
+    fn test_invalid_steps() {
+        assert_eq!(VoiceTrack::parse_step(""), Err(InvalidStep));
+        assert_eq!(VoiceTrack::parse_step("C5"), Err(InvalidStep));
+        assert_eq!(VoiceTrack::parse_step("C5 X"), Err(InvalidStep));
+        assert_eq!(VoiceTrack::parse_step("C5 N 101"), Err(InvalidStep));
+        assert_eq!(VoiceTrack::parse_step("-- N"), Err(InvalidStep));
+        assert_eq!(VoiceTrack::parse_step("C5 N 1:4 50"), Err(InvalidStep));
+        assert_eq!(VoiceTrack::parse_step("C5 N 50 9:4"), Err(InvalidStep));
+        assert_eq!(VoiceTrack::parse_step("C5 N x9"), Err(InvalidStep));
+        assert_eq!(VoiceTrack::parse_step("C5 N 1:4 x2"), Err(InvalidStep));
+        assert_eq!(VoiceTrack::parse_step("C5 N m4096"), Err(InvalidStep));
+        assert_eq!(VoiceTrack::parse_step("-- m1 m2"), Err(InvalidStep));
+    }
+}
@@ -1,5 +1,5 @@
-use alloc::vec::Vec;
-use core::fmt;
+use alloc::{vec, vec::Vec};
+use core::{fmt, mem::size_of};
 use serde::{
     de::{Error, SeqAccess, Visitor},
     ser::Error as SerError,
@@ -7,7 +7,14 @@ use serde::{
 };
 use ufmt::derive::uDebug;
 
-use crate::{InvalidNotePair, Note, NotePair, NoteState};
+use crate::{InvalidNotePair, NotePair, NoteState, TrigCondition};
+
+/// Most sub-triggers a step can be split into
+pub const MAX_RATCHETS: u8 = 8;
+/// Modulation values are 12 bits, like the DAC
+pub const MAX_MOD_VALUE: u16 = 0xfff;
+// stored for steps without a modulation value
+const NO_MOD_VALUE: u16 = u16::MAX;
 
 #[derive(Copy, Clone, Debug, uDebug, PartialEq)]
 #[repr(u8)]
@@ -17,9 +24,9 @@ pub enum NoteFlag {
     Legato = 2,
 }
 
-impl Into<NoteFlag> for u8 {
-    fn into(self) -> NoteFlag {
-        match self {
+impl From<u8> for NoteFlag {
+    fn from(val: u8) -> Self {
+        match val {
             0 => NoteFlag::None,
             1 => NoteFlag::Note,
             2 => NoteFlag::Legato,
@@ -32,37 +39,153 @@ impl Into<NoteFlag> for u8 {
 pub struct VoiceTrack {
     notes: Vec<u8>,
     flags: Vec<u8>,
+    // per-step gate length override, as % of the step (0 = track default)
+    gates: Vec<u8>,
+    conditions: Vec<TrigCondition>,
+    // number of evenly spaced triggers within the step
+    ratchets: Vec<u8>,
+    // modulation lane, `NO_MOD_VALUE` if the step has no value
+    modulation: Vec<u16>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Step {
+    #[serde(rename = "s")]
+    state: NoteState,
+    #[serde(rename = "g", default, skip_serializing_if = "is_zero")]
+    gate: u8,
+    #[serde(rename = "c", default, skip_serializing_if = "is_always")]
+    condition: TrigCondition,
+    #[serde(rename = "r", default = "one", skip_serializing_if = "is_one")]
+    ratchets: u8,
+    #[serde(rename = "m", default, skip_serializing_if = "Option::is_none")]
+    modulation: Option<u16>,
+}
+
+/// A saved step. Tracks saved before steps had attributes only stored the note
+/// state, and tracks used to be written nested in another sequence.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedStep {
+    Step(Step),
+    State(NoteState),
+    Nested(Vec<SavedStep>),
+}
+
+impl SavedStep {
+    fn step(self) -> Option<Step> {
+        match self {
+            SavedStep::Step(step) => Some(step),
+            SavedStep::State(state) => Some(state.into()),
+            SavedStep::Nested(_) => None,
+        }
+    }
+}
+
+impl From<NoteState> for Step {
+    fn from(state: NoteState) -> Self {
+        Step {
+            state,
+            gate: 0,
+            condition: TrigCondition::Always,
+            ratchets: 1,
+            modulation: None,
+        }
+    }
+}
+
+fn one() -> u8 {
+    1
+}
+
+fn is_one(v: &u8) -> bool {
+    *v == 1
+}
+
+fn is_zero(v: &u8) -> bool {
+    *v == 0
+}
+
+fn is_always(c: &TrigCondition) -> bool {
+    *c == TrigCondition::Always
 }
 
 impl VoiceTrack {
     pub fn new(size: usize) -> Self {
         Self {
-            notes: Vec::from_iter(core::iter::repeat(0).take(size)),
-            flags: Vec::from_iter(core::iter::repeat(0).take(size / 4)),
+            notes: vec![0; size],
+            flags: vec![0; size.div_ceil(4)],
+            gates: vec![0; size],
+            conditions: vec![TrigCondition::Always; size],
+            ratchets: vec![1; size],
+            modulation: vec![NO_MOD_VALUE; size],
         }
     }
 
+    /// Grow (with rests) or shrink the track to `new_size` steps
     pub fn resize(&mut self, new_size: usize) {
-        let delta = new_size - self.len();
-        for _ in 0..delta {
-            self.notes.push(0);
+        // the flags of the steps which go away may share a byte with the ones which stay
+        for beat in new_size..self.len() {
+            self.set_note(beat, (None, NoteFlag::None)).ok();
         }
 
-        for _ in 0..(delta / 4) {
-            self.flags.push(0);
+        self.notes.resize(new_size, 0);
+        self.gates.resize(new_size, 0);
+        self.conditions.resize(new_size, TrigCondition::Always);
+        self.ratchets.resize(new_size, 1);
+        self.modulation.resize(new_size, NO_MOD_VALUE);
+        self.flags.resize(new_size.div_ceil(4), 0);
+
+        if new_size < self.notes.capacity() {
+            self.shrink_to_fit();
         }
     }
 
+    fn shrink_to_fit(&mut self) {
+        self.notes.shrink_to_fit();
+        self.gates.shrink_to_fit();
+        self.conditions.shrink_to_fit();
+        self.ratchets.shrink_to_fit();
+        self.modulation.shrink_to_fit();
+        self.flags.shrink_to_fit();
+    }
+
+    /// Memory a new track of `len` steps takes up
+    pub fn size_for(len: usize) -> usize {
+        size_of::<Self>()
+            + len * (3 + size_of::<TrigCondition>() + size_of::<u16>())
+            + len.div_ceil(4)
+    }
+
+    /// Memory the track takes up, most of it on the heap
+    pub fn size(&self) -> usize {
+        size_of::<Self>()
+            + self.notes.capacity()
+            + self.flags.capacity()
+            + self.gates.capacity()
+            + self.conditions.capacity() * size_of::<TrigCondition>()
+            + self.ratchets.capacity()
+            + self.modulation.capacity() * size_of::<u16>()
+    }
+
     pub fn len(&self) -> usize {
         self.notes.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
     pub fn set_note(
         &mut self,
         beat: usize,
         (note, flag): (Option<NotePair>, NoteFlag),
     ) -> Result<(), InvalidNotePair> {
-        self.notes[beat] = (&note.unwrap_or(NotePair(Note::C, -127))).try_into()?;
+        // rests don't have a pitch, the stored value doesn't matter
+        self.notes[beat] = match note {
+            Some(np) => (&np).try_into()?,
+            None => 0,
+        };
         let idx = beat / 4;
         let sub_idx = beat % 4;
         let bit_mask = 0xc0 >> (sub_idx * 2);
@@ -70,6 +193,47 @@ impl VoiceTrack {
         Ok(())
     }
 
+    pub fn set_gate(&mut self, beat: usize, gate: Option<u8>) {
+        self.gates[beat] = gate.unwrap_or(0).min(100);
+    }
+
+    pub fn get_gate(&self, t: usize) -> Option<u8> {
+        match self.gates.get(t) {
+            None | Some(0) => None,
+            Some(g) => Some(*g),
+        }
+    }
+
+    pub fn set_condition(&mut self, beat: usize, condition: TrigCondition) {
+        self.conditions[beat] = condition;
+    }
+
+    pub fn get_condition(&self, t: usize) -> TrigCondition {
+        self.conditions.get(t).copied().unwrap_or_default()
+    }
+
+    pub fn set_ratchets(&mut self, beat: usize, ratchets: u8) {
+        self.ratchets[beat] = ratchets.clamp(1, MAX_RATCHETS);
+    }
+
+    pub fn get_ratchets(&self, t: usize) -> u8 {
+        self.ratchets.get(t).copied().unwrap_or(1)
+    }
+
+    pub fn set_modulation(&mut self, beat: usize, value: Option<u16>) {
+        self.modulation[beat] = match value {
+            Some(v) => v.min(MAX_MOD_VALUE),
+            None => NO_MOD_VALUE,
+        };
+    }
+
+    pub fn get_modulation(&self, t: usize) -> Option<u16> {
+        match self.modulation.get(t) {
+            None | Some(&NO_MOD_VALUE) => None,
+            Some(v) => Some(*v),
+        }
+    }
+
     pub fn get_note(&self, t: usize) -> Option<(Option<NotePair>, NoteFlag)> {
         if t >= self.len() {
             None
@@ -101,14 +265,20 @@ impl Serialize for VoiceTrack {
     where
         S: serde::Serializer,
     {
-        serializer.collect_seq(
-            self.since(0, self.len() - 1)
-                .map(|(_, elem)| -> Result<NoteState, S::Error> {
-                    let (np, nf) = elem.ok_or(S::Error::custom("Value should not be empty"))?;
-                    Ok((np, nf).into())
-                }
-            ).collect::<Result<Vec<_>, S::Error>>()
-        )
+        let steps = self
+            .since(0, self.len())
+            .map(|(n, elem)| -> Result<Step, S::Error> {
+                let (np, nf) = elem.ok_or(S::Error::custom("Value should not be empty"))?;
+                Ok(Step {
+                    state: (np, nf).into(),
+                    gate: self.gates[n],
+                    condition: self.conditions[n],
+                    ratchets: self.ratchets[n],
+                    modulation: self.get_modulation(n),
+                })
+            })
+            .collect::<Result<Vec<_>, S::Error>>()?;
+        serializer.collect_seq(steps)
     }
 }
 
@@ -118,7 +288,7 @@ impl<'de> Visitor<'de> for VoiceTrackVisitor {
     type Value = VoiceTrack;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("a sequence of note state values")
+        formatter.write_str("a sequence of steps")
     }
 
     fn visit_seq<V>(self, mut seq: V) -> Result<Self::Value, V::Error>
@@ -128,15 +298,35 @@ impl<'de> Visitor<'de> for VoiceTrackVisitor {
         let mut size = 16;
         let mut vt = VoiceTrack::new(size);
         let mut n = 0;
-        while let Some(e) = seq.next_element::<NoteState>()? {
-            vt.set_note(n, e.into())
+        let mut push = |vt: &mut VoiceTrack, step: Step| -> Result<(), V::Error> {
+            let Step { state, gate, condition, ratchets, modulation } = step;
+            vt.set_note(n, state.into())
                 .map_err(|_| V::Error::custom("Value is not a valid note"))?;
+            vt.set_gate(n, Some(gate));
+            vt.set_condition(n, condition);
+            vt.set_ratchets(n, ratchets);
+            vt.set_modulation(n, modulation);
             n += 1;
 
-            if n > size {
+            if n >= size {
                 vt.resize(size * 2);
                 size *= 2;
             }
+            Ok(())
+        };
+        while let Some(saved) = seq.next_element::<SavedStep>()? {
+            let steps = match saved {
+                SavedStep::Nested(steps) => steps,
+                saved => vec![saved],
+            };
+            for step in steps {
+                let step = step.step().ok_or(V::Error::custom("Steps are nested too deep"))?;
+                push(&mut vt, step)?;
+            }
+        }
+        // keep the length the track was saved with
+        if n > 0 {
+            vt.resize(n);
         }
         Ok(vt)
     }
@@ -150,3 +340,107 @@ impl<'de> Deserialize<'de> for VoiceTrack {
         deserializer.deserialize_seq(VoiceTrackVisitor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::{NoteFlag, VoiceTrack};
+    use crate::{Note, NotePair, NoteState, TrigCondition};
+
+    fn round_trip<T: serde::Serialize>(value: &T) -> VoiceTrack {
+        let mut buf = Vec::new();
+        ciborium::ser::into_writer(value, &mut buf).unwrap();
+        ciborium::de::from_reader(buf.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn test_resize() {
+        let c = Some(NotePair(Note::C, 4));
+        let mut track = VoiceTrack::new(5);
+        track.set_note(4, (c, NoteFlag::Note)).unwrap();
+        track.set_note(3, (c, NoteFlag::Legato)).unwrap();
+        assert_eq!(track.get_note(4), Some((c, NoteFlag::Note)));
+
+        track.resize(4);
+        assert_eq!(track.len(), 4);
+        assert_eq!(track.get_note(4), None);
+
+        // steps which come back are empty
+        track.resize(7);
+        assert_eq!(track.len(), 7);
+        assert_eq!(track.get_note(3), Some((c, NoteFlag::Legato)));
+        assert_eq!(track.get_note(4), Some((None, NoteFlag::None)));
+        assert_eq!(track.get_note(6), Some((None, NoteFlag::None)));
+    }
+
+    #[test]
+    fn test_size() {
+        let mut track = VoiceTrack::new(16);
+        let size = track.size();
+        assert_eq!(size, VoiceTrack::size_for(16));
+
+        // memory is given back when the track shrinks
+        track.resize(64);
+        assert!(track.size() >= VoiceTrack::size_for(64));
+        track.resize(16);
+        assert_eq!(track.size(), size);
+    }
+
+    #[test]
+    fn test_serde() {
+        let c = Some(NotePair(Note::C, 4));
+        let mut track = VoiceTrack::new(20);
+        track.set_note(2, (c, NoteFlag::Note)).unwrap();
+        track.set_note(3, (c, NoteFlag::Legato)).unwrap();
+        track.set_gate(2, Some(75));
+        track.set_condition(3, TrigCondition::Every(1, 2));
+        track.set_ratchets(3, 4);
+        track.set_modulation(19, Some(0x800));
+
+        let loaded = round_trip(&track);
+        assert_eq!(loaded.len(), 20);
+        for n in 0..20 {
+            assert_eq!(loaded.get_note(n), track.get_note(n));
+            assert_eq!(loaded.get_gate(n), track.get_gate(n));
+            assert_eq!(loaded.get_condition(n), track.get_condition(n));
+            assert_eq!(loaded.get_ratchets(n), track.get_ratchets(n));
+            assert_eq!(loaded.get_modulation(n), track.get_modulation(n));
+        }
+    }
+
+    #[test]
+    fn test_load_note_states() {
+        // tracks saved before steps had attributes
+        let c = NotePair(Note::C, 4);
+        let saved = [NoteState::On(c), NoteState::Legato(c), NoteState::Off];
+        let loaded = round_trip(&saved);
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded.get_note(0), Some((Some(c), NoteFlag::Note)));
+        assert_eq!(loaded.get_note(1), Some((Some(c), NoteFlag::Legato)));
+        assert_eq!(loaded.get_note(2), Some((None, NoteFlag::None)));
+        assert_eq!(loaded.get_gate(0), None);
+        assert_eq!(loaded.get_condition(1), TrigCondition::Always);
+        assert_eq!(loaded.get_ratchets(1), 1);
+        assert_eq!(loaded.get_modulation(0), None);
+
+        // and nested in another sequence, as they were written
+        let loaded = round_trip(&[saved]);
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded.get_note(1), Some((Some(c), NoteFlag::Legato)));
+        assert_eq!(loaded.get_note(2), Some((None, NoteFlag::None)));
+    }
+
+    #[test]
+    fn test_load_nested_steps() {
+        let c = Some(NotePair(Note::C, 4));
+        let mut track = VoiceTrack::new(3);
+        track.set_note(1, (c, NoteFlag::Note)).unwrap();
+        track.set_ratchets(1, 3);
+
+        let loaded = round_trip(&[&track]);
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded.get_note(1), Some((c, NoteFlag::Note)));
+        assert_eq!(loaded.get_ratchets(1), 3);
+    }
+}
@@ -0,0 +1,249 @@
+use alloc::vec::Vec;
+
+use crate::{InvalidNotePair, Key, NoteFlag, NotePair, TrigCondition, VoiceTrack};
+
+type StepState = (Option<NotePair>, NoteFlag);
+/// Whatever else belongs to a step and moves along with it
+#[derive(Clone, Copy)]
+struct StepAttrs {
+    gate: Option<u8>,
+    condition: TrigCondition,
+    ratchets: u8,
+    modulation: Option<u16>,
+}
+
+/// Transformations of a whole track. Pitch transformations fail (and leave the
+/// track untouched) if any note would end up outside of the MIDI range.
+impl VoiceTrack {
+    fn steps(&self) -> Vec<(StepState, StepAttrs)> {
+        (0..self.len())
+            .map(|n| {
+                let attrs = StepAttrs {
+                    gate: self.get_gate(n),
+                    condition: self.get_condition(n),
+                    ratchets: self.get_ratchets(n),
+                    modulation: self.get_modulation(n),
+                };
+                (self.get_note(n).unwrap(), attrs)
+            })
+            .collect()
+    }
+
+    fn set_steps(&mut self, steps: Vec<(StepState, StepAttrs)>) -> Result<(), InvalidNotePair> {
+        for (n, (state, attrs)) in steps.into_iter().enumerate() {
+            self.set_note(n, state)?;
+            self.set_gate(n, attrs.gate);
+            self.set_condition(n, attrs.condition);
+            self.set_ratchets(n, attrs.ratchets);
+            self.set_modulation(n, attrs.modulation);
+        }
+        Ok(())
+    }
+
+    /// Apply `f` to the pitch of every note
+    pub fn map_pitches<F: Fn(&NotePair) -> NotePair>(&mut self, f: F) -> Result<(), InvalidNotePair> {
+        let mut steps = self.steps();
+        for ((note, _), _) in steps.iter_mut() {
+            if let Some(np) = note {
+                let new = f(np);
+                u8::try_from(&new)?;
+                *np = new;
+            }
+        }
+        self.set_steps(steps)
+    }
+
+    /// Chromatic transposition
+    pub fn transpose(&mut self, semitones: i8) -> Result<(), InvalidNotePair> {
+        self.map_pitches(|np| NotePair::from_semitones(np.semitones() + semitones as i16))
+    }
+
+    /// Transposition by degrees of the scale of `key` (notes are quantized to it first)
+    pub fn transpose_in_key(&mut self, key: &Key, degrees: i8) -> Result<(), InvalidNotePair> {
+        self.map_pitches(|np| key.transpose(np, degrees))
+    }
+
+    pub fn shift_octave(&mut self, octaves: i8) -> Result<(), InvalidNotePair> {
+        self.map_pitches(|np| NotePair(np.0, np.1 + octaves))
+    }
+
+    /// Mirror all pitches around `pivot`
+    pub fn invert(&mut self, pivot: &NotePair) -> Result<(), InvalidNotePair> {
+        let pivot = pivot.semitones();
+        self.map_pitches(|np| NotePair::from_semitones(2 * pivot - np.semitones()))
+    }
+
+    /// Play the pitches in reverse order, keeping the rhythm as it is
+    pub fn retrograde(&mut self) {
+        let mut steps = self.steps();
+        let pitches: Vec<NotePair> = steps.iter().filter_map(|((np, _), _)| *np).collect();
+        let mut pitches = pitches.into_iter().rev();
+        for ((note, _), _) in steps.iter_mut() {
+            if note.is_some() {
+                *note = pitches.next();
+            }
+        }
+        self.set_steps(steps).unwrap();
+    }
+
+    /// Move every step `n` steps forward (backwards, if negative), wrapping around
+    pub fn rotate(&mut self, n: isize) {
+        let mut steps = self.steps();
+        let n = n.rem_euclid(steps.len() as isize) as usize;
+        steps.rotate_right(n);
+        self.set_steps(steps).unwrap();
+    }
+
+    /// Play the track backwards. Tied notes stay tied: each note
+    /// still starts with `NoteFlag::Note`, followed by its `NoteFlag::Legato` steps.
+    pub fn reverse(&mut self) {
+        let steps = self.steps();
+
+        // split into notes/rests, each a `Note` (or rest) plus the following legato steps
+        let mut groups: Vec<&[(StepState, StepAttrs)]> = Vec::new();
+        let mut start = 0;
+        for n in 1..=steps.len() {
+            if n == steps.len() || (steps[n].0).1 != NoteFlag::Legato {
+                groups.push(&steps[start..n]);
+                start = n;
+            }
+        }
+
+        let mut reversed = Vec::with_capacity(steps.len());
+        for group in groups.iter().rev() {
+            for (n, ((note, _), _)) in group.iter().rev().enumerate() {
+                let flag = (group[n].0).1;
+                reversed.push(((*note, flag), group[n].1));
+            }
+        }
+        self.set_steps(reversed).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::{Key, Note, NoteFlag, NotePair, Scale, VoiceTrack};
+
+    fn track(steps: &[(Option<NotePair>, NoteFlag)]) -> VoiceTrack {
+        let mut track = VoiceTrack::new(steps.len());
+        for (n, step) in steps.iter().enumerate() {
+            track.set_note(n, *step).unwrap();
+        }
+        track
+    }
+
+    fn steps(track: &VoiceTrack) -> Vec<(Option<NotePair>, NoteFlag)> {
+        (0..track.len()).map(|n| track.get_note(n).unwrap()).collect()
+    }
+
+    const REST: (Option<NotePair>, NoteFlag) = (None, NoteFlag::None);
+
+    fn note(n: Note, o: i8) -> (Option<NotePair>, NoteFlag) {
+        (Some(NotePair(n, o)), NoteFlag::Note)
+    }
+
+    fn tie(n: Note, o: i8) -> (Option<NotePair>, NoteFlag) {
+        (Some(NotePair(n, o)), NoteFlag::Legato)
+    }
+
+    #[test]
+    fn test_transpose() {
+        let mut t = track(&[note(Note::C, 4), REST, note(Note::B, 4), tie(Note::B, 4)]);
+        t.transpose(1).unwrap();
+        assert_eq!(
+            steps(&t),
+            [note(Note::Db, 4), REST, note(Note::C, 5), tie(Note::C, 5)]
+        );
+        t.shift_octave(-1).unwrap();
+        assert_eq!(
+            steps(&t),
+            [note(Note::Db, 3), REST, note(Note::C, 4), tie(Note::C, 4)]
+        );
+
+        // out of range, nothing changes
+        assert!(t.shift_octave(8).is_err());
+        assert_eq!(
+            steps(&t),
+            [note(Note::Db, 3), REST, note(Note::C, 4), tie(Note::C, 4)]
+        );
+    }
+
+    #[test]
+    fn test_transpose_in_key() {
+        let mut t = track(&[note(Note::C, 4), note(Note::E, 4), note(Note::B, 4), REST]);
+        t.transpose_in_key(&Key::new(Note::C, Scale::Major), 2).unwrap();
+        assert_eq!(
+            steps(&t),
+            [note(Note::E, 4), note(Note::G, 4), note(Note::D, 5), REST]
+        );
+    }
+
+    #[test]
+    fn test_invert() {
+        let mut t = track(&[note(Note::C, 4), note(Note::E, 4), note(Note::G, 4), REST]);
+        t.invert(&NotePair(Note::E, 4)).unwrap();
+        assert_eq!(
+            steps(&t),
+            [note(Note::Ab, 4), note(Note::E, 4), note(Note::Db, 4), REST]
+        );
+    }
+
+    #[test]
+    fn test_retrograde() {
+        let mut t = track(&[note(Note::C, 4), tie(Note::C, 4), REST, note(Note::G, 4)]);
+        t.retrograde();
+        assert_eq!(
+            steps(&t),
+            [note(Note::G, 4), tie(Note::C, 4), REST, note(Note::C, 4)]
+        );
+    }
+
+    #[test]
+    fn test_rotate() {
+        let mut t = track(&[note(Note::C, 4), REST, REST, note(Note::G, 4)]);
+        t.set_ratchets(3, 4);
+        t.rotate(1);
+        assert_eq!(
+            steps(&t),
+            [note(Note::G, 4), note(Note::C, 4), REST, REST]
+        );
+        // the ratchets move along with their step
+        assert_eq!(t.get_ratchets(0), 4);
+        assert_eq!(t.get_ratchets(3), 1);
+        t.rotate(-2);
+        assert_eq!(
+            steps(&t),
+            [REST, REST, note(Note::G, 4), note(Note::C, 4)]
+        );
+    }
+
+    #[test]
+    fn test_reverse() {
+        let mut t = track(&[
+            note(Note::C, 4),
+            tie(Note::C, 4),
+            tie(Note::D, 4),
+            REST,
+            note(Note::G, 4),
+            note(Note::A, 4),
+            tie(Note::A, 4),
+            REST,
+        ]);
+        t.reverse();
+        assert_eq!(
+            steps(&t),
+            [
+                REST,
+                note(Note::A, 4),
+                tie(Note::A, 4),
+                note(Note::G, 4),
+                REST,
+                note(Note::D, 4),
+                tie(Note::C, 4),
+                tie(Note::C, 4),
+            ]
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use core::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use ufmt::{uDisplay, uWrite, uwrite, Formatter};
+
+use crate::Rng;
+
+/// When a step fires
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrigCondition {
+    #[default]
+    Always,
+    /// With a probability of n %
+    Probability(u8),
+    /// On loop `n` of every `m` loops (both starting at 1), e.g. 1:2, 3:4
+    Every(u8, u8),
+    /// Only the first time through the track
+    FirstLoop,
+    /// Only if the previous conditional step did not fire
+    NotPrevious,
+}
+
+impl uDisplay for TrigCondition {
+    fn fmt<W>(&self, fmt: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        match self {
+            TrigCondition::Always => fmt.write_str("-"),
+            TrigCondition::Probability(p) => uwrite!(fmt, "{}%", p),
+            TrigCondition::Every(n, m) => uwrite!(fmt, "{}:{}", n, m),
+            TrigCondition::FirstLoop => fmt.write_str("1ST"),
+            TrigCondition::NotPrevious => fmt.write_str("!PRE"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InvalidTrigCondition;
+
+/// Parses the `uDisplay` form
+impl FromStr for TrigCondition {
+    type Err = InvalidTrigCondition;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = |s: &str| s.parse::<u8>().map_err(|_| InvalidTrigCondition);
+
+        let cond = match s {
+            "-" => TrigCondition::Always,
+            "1ST" => TrigCondition::FirstLoop,
+            "!PRE" => TrigCondition::NotPrevious,
+            _ => match (s.strip_suffix('%'), s.split_once(':')) {
+                (Some(p), _) => TrigCondition::Probability(number(p)?),
+                (None, Some((n, m))) => TrigCondition::Every(number(n)?, number(m)?),
+                _ => return Err(InvalidTrigCondition),
+            },
+        };
+
+        match cond {
+            TrigCondition::Probability(p) if p > 100 => Err(InvalidTrigCondition),
+            TrigCondition::Every(n, m) if n == 0 || n > m => Err(InvalidTrigCondition),
+            _ => Ok(cond),
+        }
+    }
+}
+
+/// Evaluates trig conditions while a track plays
+#[derive(Clone, Debug)]
+pub struct Trigs {
+    seed: u32,
+    rng: Rng,
+    // outcome of the last conditional step
+    previous: bool,
+}
+
+impl Trigs {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            seed,
+            rng: Rng::new(seed),
+            previous: false,
+        }
+    }
+
+    /// Start over, e.g. when the transport restarts (random steps repeat the same way)
+    pub fn reset(&mut self) {
+        *self = Self::new(self.seed);
+    }
+
+    /// Whether a step with condition `cond` fires during loop `iteration` (the first being 0)
+    pub fn fires(&mut self, cond: TrigCondition, iteration: u32) -> bool {
+        let fires = match cond {
+            TrigCondition::Always => return true,
+            TrigCondition::NotPrevious => return !self.previous,
+            TrigCondition::Probability(p) => self.rng.chance(p),
+            TrigCondition::Every(n, m) => iteration % m.max(1) as u32 + 1 == n as u32,
+            TrigCondition::FirstLoop => iteration == 0,
+        };
+        self.previous = fires;
+        fires
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use heapless::String;
+    use ufmt::uwrite;
+
+    use super::{TrigCondition, Trigs};
+
+    fn loops(trigs: &mut Trigs, cond: TrigCondition, n: u32) -> Vec<bool> {
+        (0..n).map(|i| trigs.fires(cond, i)).collect()
+    }
+
+    #[test]
+    fn test_every() {
+        let mut trigs = Trigs::new(1);
+        assert_eq!(loops(&mut trigs, TrigCondition::Every(1, 2), 4), [true, false, true, false]);
+        assert_eq!(
+            loops(&mut trigs, TrigCondition::Every(3, 4), 8),
+            [false, false, true, false, false, false, true, false]
+        );
+        assert_eq!(loops(&mut trigs, TrigCondition::FirstLoop, 3), [true, false, false]);
+        assert_eq!(loops(&mut trigs, TrigCondition::Always, 2), [true, true]);
+    }
+
+    #[test]
+    fn test_not_previous() {
+        let mut trigs = Trigs::new(1);
+        assert!(!trigs.fires(TrigCondition::FirstLoop, 1));
+        assert!(trigs.fires(TrigCondition::NotPrevious, 1));
+        assert!(trigs.fires(TrigCondition::FirstLoop, 0));
+        assert!(!trigs.fires(TrigCondition::NotPrevious, 0));
+        // unconditional steps don't count
+        trigs.fires(TrigCondition::Always, 0);
+        assert!(!trigs.fires(TrigCondition::NotPrevious, 0));
+    }
+
+    #[test]
+    fn test_probability() {
+        let mut a = Trigs::new(1234);
+        let runs = loops(&mut a, TrigCondition::Probability(30), 200);
+
+        // same seed, same outcome, also after a reset
+        let mut b = Trigs::new(1234);
+        assert_eq!(runs, loops(&mut b, TrigCondition::Probability(30), 200));
+        a.reset();
+        assert_eq!(runs, loops(&mut a, TrigCondition::Probability(30), 200));
+
+        let hits = runs.iter().filter(|f| **f).count();
+        assert!((40..80).contains(&hits));
+        assert!(loops(&mut a, TrigCondition::Probability(0), 50).iter().all(|f| !f));
+        assert!(loops(&mut a, TrigCondition::Probability(100), 50).iter().all(|f| *f));
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for cond in [
+            TrigCondition::Always,
+            TrigCondition::Probability(25),
+            TrigCondition::Every(3, 4),
+            TrigCondition::FirstLoop,
+            TrigCondition::NotPrevious,
+        ] {
+            let mut out = String::<8>::new();
+            uwrite!(out, "{}", cond).unwrap();
+            assert_eq!(out.parse::<TrigCondition>(), Ok(cond));
+        }
+        assert!("101%".parse::<TrigCondition>().is_err());
+        assert!("5:4".parse::<TrigCondition>().is_err());
+        assert!("0:4".parse::<TrigCondition>().is_err());
+        assert!("x".parse::<TrigCondition>().is_err());
+    }
+}
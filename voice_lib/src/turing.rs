@@ -0,0 +1,167 @@
+use crate::{Key, NoteFlag, NotePair, Rng, VoiceTrack};
+
+pub const MIN_TURING_LENGTH: u8 = 2;
+pub const MAX_TURING_LENGTH: u8 = 16;
+
+/// Random looping sequence, like the "Turing machine" module: a shift
+/// register whose last bit is fed back to the start, flipped with a
+/// probability of `flip` %. At 0 % the loop of `length` steps is locked,
+/// at 50 % it is completely random.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TuringMachine {
+    register: u16,
+    length: u8,
+    pub flip: u8,
+    rng: Rng,
+}
+
+impl TuringMachine {
+    /// The register starts out with a random (seeded) loop of `length` steps
+    pub fn new(seed: u32, length: u8, flip: u8) -> Self {
+        let length = length.clamp(MIN_TURING_LENGTH, MAX_TURING_LENGTH);
+        let mut rng = Rng::new(seed);
+        let bits = rng.next_u32() as u16;
+
+        // repeat the loop over the whole register, so that it's periodic from the start
+        let register = (0..16).fold(0, |reg, n| reg | ((bits >> (n % length)) & 1) << n);
+        Self {
+            register,
+            length,
+            flip,
+            rng,
+        }
+    }
+
+    pub fn length(&self) -> u8 {
+        self.length
+    }
+
+    /// Gate of the current step (the first bit)
+    pub fn gate(&self) -> bool {
+        self.register & 1 != 0
+    }
+
+    /// Value of the current step (the first 8 bits)
+    pub fn value(&self) -> u8 {
+        self.register as u8
+    }
+
+    /// Move on to the next step
+    pub fn step(&mut self) {
+        let mut bit = (self.register >> (self.length - 1)) & 1;
+        if self.flip > 0 && self.rng.chance(self.flip) {
+            bit ^= 1;
+        }
+        self.register = (self.register << 1) | bit;
+    }
+
+    /// Pitch of `value`, spread over `octaves` octaves of the scale of `key`, from `low` up
+    pub fn pitch(value: u8, key: &Key, low: &NotePair, octaves: u8) -> NotePair {
+        let per_octave = match key.scale.mask().count_ones() {
+            0 => 12,
+            n => n,
+        };
+        let degrees = per_octave * octaves.max(1) as u32;
+        let degree = (value as u32 * degrees / 256) as i8;
+
+        if key.scale.mask() == 0 {
+            NotePair::from_semitones(low.semitones() + degree as i16)
+        } else {
+            key.transpose(low, degree)
+        }
+    }
+
+    /// Write the loop as it currently is (as if locked) to `track`, the current
+    /// step at `start`. Gates become notes, with the pitch `pitch(value)`.
+    pub fn fill(&self, track: &mut VoiceTrack, start: usize, mut pitch: impl FnMut(u8) -> NotePair) {
+        let mut locked = Self { flip: 0, ..*self };
+        let len = track.len();
+        for n in 0..len {
+            let state = match locked.gate() {
+                true => (Some(pitch(locked.value())), NoteFlag::Note),
+                false => (None, NoteFlag::None),
+            };
+            let step = (start + n) % len;
+            // pitches out of range become rests
+            if track.set_note(step, state).is_err() {
+                track.set_note(step, (None, NoteFlag::None)).unwrap();
+            }
+            locked.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::TuringMachine;
+    use crate::{Key, Note, NotePair, Scale, VoiceTrack};
+
+    fn run(tm: &mut TuringMachine, steps: usize) -> Vec<(bool, u8)> {
+        (0..steps)
+            .map(|_| {
+                let out = (tm.gate(), tm.value());
+                tm.step();
+                out
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_locked_loop() {
+        for length in [2, 3, 5, 8, 16] {
+            let mut tm = TuringMachine::new(42, length, 0);
+            let out = run(&mut tm, 64);
+            for n in length as usize..64 {
+                assert_eq!(out[n], out[n - length as usize]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_seed() {
+        let mut a = TuringMachine::new(7, 8, 20);
+        let mut b = TuringMachine::new(7, 8, 20);
+        assert_eq!(run(&mut a, 100), run(&mut b, 100));
+
+        let mut c = TuringMachine::new(8, 8, 20);
+        let mut a = TuringMachine::new(7, 8, 20);
+        assert_ne!(run(&mut a, 100), run(&mut c, 100));
+    }
+
+    #[test]
+    fn test_flip() {
+        // always flipping: the loop is inverted on every pass
+        let mut tm = TuringMachine::new(3, 4, 100);
+        let out = run(&mut tm, 12);
+        for n in 4..12 {
+            assert_eq!(out[n].0, !out[n - 4].0);
+        }
+    }
+
+    #[test]
+    fn test_pitch() {
+        let key = Key::new(Note::C, Scale::Major);
+        let low = NotePair(Note::C, 4);
+        assert_eq!(TuringMachine::pitch(0, &key, &low, 1), low);
+        // 7 degrees over the range of values
+        assert_eq!(TuringMachine::pitch(37, &key, &low, 1), NotePair(Note::D, 4));
+        assert_eq!(TuringMachine::pitch(255, &key, &low, 1), NotePair(Note::B, 4));
+        assert_eq!(TuringMachine::pitch(255, &key, &low, 2), NotePair(Note::B, 5));
+    }
+
+    #[test]
+    fn test_fill() {
+        let tm = TuringMachine::new(42, 4, 0);
+        let mut track = VoiceTrack::new(8);
+        tm.fill(&mut track, 2, |_| NotePair(Note::C, 4));
+
+        let mut locked = tm;
+        for n in 0..8 {
+            let (note, _) = track.get_note((n + 2) % 8).unwrap();
+            assert_eq!(note.is_some(), locked.gate());
+            locked.step();
+        }
+    }
+}